@@ -0,0 +1,24 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use sh_inline::*;
+
+fn render(c: &mut Criterion) {
+    let name = "some value with spaces";
+    let files = vec!["a", "b c", "d"];
+    c.bench_function("bash_command", |b| {
+        b.iter(|| bash_command!(r#"echo "${name}" ${files}"#, name, files).unwrap())
+    });
+    c.bench_function("bash_plan", |b| {
+        b.iter(|| bash_plan!(r#"echo "${name}" ${files}"#, name, files).unwrap())
+    });
+}
+
+fn execute(c: &mut Criterion) {
+    let name = "value";
+    c.bench_function("bash", |b| b.iter(|| bash!(r"true ${name}", name).unwrap()));
+    c.bench_function("bash_fast_path", |b| {
+        b.iter(|| bash!(fast_path = true, r"true ${name}", name).unwrap())
+    });
+}
+
+criterion_group!(benches, render, execute);
+criterion_main!(benches);
@@ -0,0 +1,402 @@
+//! Procedural macros for [`sh-inline`](https://docs.rs/sh-inline), enabled
+//! there by the `macros` feature.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::{Expr, Lit, LitStr, Token};
+
+/// Execute a fragment of Bash shell script like `bash!`, binding the Rust
+/// variables named by `{name}` placeholders in the script automatically.
+///
+/// Each placeholder becomes a reference to the bound variable, which is
+/// quoted as usual: `{name}` is replaced by `"${name}"`, or by `${name}`
+/// inside double quotes.  Placeholders inside single quotes are an error.
+/// Use `{{` and `}}` for literal braces; `${name}` is left alone, so shell
+/// variables can be used as usual.  Options can be given before the script
+/// and further bindings after it, as for the other macros.
+///
+/// ```
+/// use sh_inline::*;
+/// let dir = tempfile::tempdir()?;
+/// let src = dir.path().join("a file");
+/// let dst = dir.path().join("copy");
+/// bash_fmt!("echo hello > {src}; cp {src} {dst}")?;
+/// let greeting = "hello";
+/// bash_fmt!(r#"test "$(cat {dst})" = "{greeting}""#)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[proc_macro]
+pub fn bash_fmt(input: TokenStream) -> TokenStream {
+    match expand(input.into()) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// The arguments of a macro call: options, the script literal, and
+/// explicit bindings.
+struct Args {
+    options: Vec<Expr>,
+    script: LitStr,
+    bindings: Vec<Expr>,
+}
+
+impl Args {
+    fn parse(input: proc_macro2::TokenStream) -> syn::Result<Self> {
+        let mut args: Vec<Expr> = Punctuated::<Expr, Token![,]>::parse_terminated
+            .parse2(input)?
+            .into_iter()
+            .collect();
+        let position = args.iter().position(|e| match e {
+            Expr::Lit(l) => matches!(l.lit, Lit::Str(_)),
+            _ => false,
+        });
+        let position = match position {
+            Some(position) => position,
+            None => {
+                return Err(syn::Error::new(
+                    proc_macro2::Span::call_site(),
+                    "expected a string literal script",
+                ))
+            }
+        };
+        let bindings = args.split_off(position + 1);
+        let script = match args.pop() {
+            Some(Expr::Lit(syn::ExprLit {
+                lit: Lit::Str(lit), ..
+            })) => lit,
+            _ => unreachable!(),
+        };
+        Ok(Self {
+            options: args,
+            script,
+            bindings,
+        })
+    }
+
+    /// The names of the explicitly bound variables.
+    fn binding_names(&self) -> Vec<String> {
+        self.bindings
+            .iter()
+            .filter_map(|e| match e {
+                Expr::Path(p) => p.path.get_ident().map(|i| i.to_string()),
+                Expr::Assign(a) => match &*a.left {
+                    Expr::Path(p) => p.path.get_ident().map(|i| i.to_string()),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn expand(input: proc_macro2::TokenStream) -> syn::Result<proc_macro2::TokenStream> {
+    let args = Args::parse(input)?;
+    let lit = &args.script;
+    let (script, names) =
+        interpolate(&lit.value()).map_err(|msg| syn::Error::new(lit.span(), msg))?;
+    let explicit_names = args.binding_names();
+    let names = names
+        .iter()
+        .filter(|n| !explicit_names.contains(n))
+        .map(|n| syn::Ident::new(n, lit.span()));
+    let script = LitStr::new(&script, lit.span());
+    let options = &args.options;
+    let explicit = &args.bindings;
+    Ok(quote! {
+        ::sh_inline::__sh_inline_script!(@opts [] #( #options, )* #script #( , #names )* #( , #explicit )*).run()
+    })
+}
+
+/// Execute a fragment of Bash shell script like `bash!`, checking at
+/// compile time that every variable it references is either bound in the
+/// macro call or assigned in the script.
+///
+/// References which supply a default or an error, such as `${name:-}`, are
+/// not checked, nor are names in upper case, which are assumed to come
+/// from the environment or bash itself.  Assignments are recognized in the
+/// usual forms, including `local`, `declare`, `export`, `read`, `for` and
+/// `printf -v`.
+///
+/// ```
+/// use sh_inline::*;
+/// let name = "world";
+/// bash_checked!(r#"greeting="hello ${name}"; test "${greeting}" = "hello world""#, name)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// Forgetting a binding is an error:
+///
+/// ```compile_fail
+/// use sh_inline::*;
+/// let name = "world";
+/// bash_checked!(r#"echo "hello ${name}""#)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[proc_macro]
+pub fn bash_checked(input: TokenStream) -> TokenStream {
+    match expand_checked(input.into()) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_checked(input: proc_macro2::TokenStream) -> syn::Result<proc_macro2::TokenStream> {
+    let args = Args::parse(input)?;
+    let script = args.script.value();
+    let mut known = args.binding_names();
+    known.extend(assigned(&script));
+    // Variables defined by options
+    for option in args.options.iter() {
+        if let Expr::Assign(a) = option {
+            if let Expr::Path(p) = &*a.left {
+                if p.path.is_ident("scratch") {
+                    known.push("scratch".to_string());
+                }
+            }
+        }
+    }
+    let mut error: Option<syn::Error> = None;
+    for name in references(&script) {
+        if known.contains(&name) {
+            continue;
+        }
+        let e = syn::Error::new(
+            args.script.span(),
+            format!(
+                "variable `{}` is neither bound nor assigned in the script",
+                name
+            ),
+        );
+        match error.as_mut() {
+            Some(error) => error.combine(e),
+            None => error = Some(e),
+        }
+    }
+    if let Some(error) = error {
+        return Err(error);
+    }
+    let Args {
+        options,
+        script,
+        bindings,
+    } = args;
+    Ok(quote! {
+        ::sh_inline::__sh_inline_script!(@opts [] #( #options, )* #script #( , #bindings )*).run()
+    })
+}
+
+fn is_ident(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        && name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// The names of the variables which must be set for the script to run,
+/// in order of first use.
+fn references(script: &str) -> Vec<String> {
+    let chars: Vec<char> = script.chars().collect();
+    let ident_len = |start: usize| {
+        chars[start..]
+            .iter()
+            .position(|&c| !(c == '_' || c.is_ascii_alphanumeric()))
+            .unwrap_or(chars.len() - start)
+    };
+    let mut names: Vec<String> = Vec::new();
+    let mut quoting = Quoting::None;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match (c, quoting) {
+            ('\\', Quoting::None) | ('\\', Quoting::Double) => i += 1,
+            ('#', Quoting::None) if i == 0 || chars[i - 1].is_whitespace() => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            ('\'', Quoting::None) => quoting = Quoting::Single,
+            ('\'', Quoting::Single) => quoting = Quoting::None,
+            ('"', Quoting::None) => quoting = Quoting::Double,
+            ('"', Quoting::Double) => quoting = Quoting::None,
+            ('$', Quoting::None) | ('$', Quoting::Double) => {
+                let (start, braced) = match next {
+                    Some('{') if matches!(chars.get(i + 2), Some('#') | Some('!')) => (i + 3, true),
+                    Some('{') => (i + 2, true),
+                    _ => (i + 1, false),
+                };
+                let len = ident_len(start);
+                let name: String = chars[start..start + len].iter().collect();
+                let rest: String = chars[start + len..].iter().take(2).collect();
+                let guarded = braced
+                    && ["-", "=", "+", "?", ":-", ":=", ":+", ":?", "*", "@"]
+                        .iter()
+                        .any(|op| rest.starts_with(op));
+                let environment = name
+                    .chars()
+                    .all(|c| c == '_' || c.is_ascii_uppercase() || c.is_ascii_digit());
+                if is_ident(&name) && !guarded && !environment && !names.contains(&name) {
+                    names.push(name);
+                }
+                i = start + len;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    names
+}
+
+/// The names of the variables the script assigns, approximately.
+fn assigned(script: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut add = |word: &str| {
+        let name = word.split(['=', '[']).next().unwrap_or_default();
+        let name = name.strip_suffix('+').unwrap_or(name);
+        if is_ident(name) {
+            names.push(name.to_string());
+        }
+    };
+    for command in script.split(['\n', ';', '|', '&', '(', ')', '{', '}']) {
+        let mut words = command.split_whitespace().peekable();
+        // Assignments preceding the command
+        while let Some(word) = words.peek() {
+            if !word.contains('=') {
+                break;
+            }
+            add(word);
+            words.next();
+        }
+        let words: Vec<&str> = words.collect();
+        let args = words.iter().skip(1).filter(|w| !w.starts_with('-'));
+        match words.first().copied() {
+            Some("local") | Some("declare") | Some("typeset") | Some("readonly")
+            | Some("export") | Some("read") | Some("mapfile") | Some("readarray") => {
+                args.for_each(|w| add(w))
+            }
+            Some("for") | Some("select") => {
+                if let Some(w) = words.get(1) {
+                    add(w)
+                }
+            }
+            Some("getopts") => {
+                if let Some(w) = words.get(2) {
+                    add(w)
+                }
+            }
+            Some("printf") => {
+                if let Some(i) = words.iter().position(|w| *w == "-v") {
+                    if let Some(w) = words.get(i + 1) {
+                        add(w)
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Quoting {
+    None,
+    Single,
+    Double,
+}
+
+/// Replace the `{name}` placeholders in `script` with references to the
+/// corresponding shell variables, returning the script and the names in
+/// order of first use.
+fn interpolate(script: &str) -> Result<(String, Vec<String>), String> {
+    let chars: Vec<char> = script.chars().collect();
+    let mut out = String::with_capacity(script.len());
+    let mut names: Vec<String> = Vec::new();
+    // Command substitutions and subshells start a new, unquoted context
+    let mut stack = vec![Quoting::None];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let quoting = *stack.last().expect("context");
+        match (c, quoting) {
+            ('{', _) if next == Some('{') => {
+                out.push('{');
+                i += 2;
+                continue;
+            }
+            ('}', _) if next == Some('}') => {
+                out.push('}');
+                i += 2;
+                continue;
+            }
+            ('{', _) if i == 0 || chars[i - 1] != '$' => {
+                let len = chars[i + 1..]
+                    .iter()
+                    .position(|&c| !(c == '_' || c.is_ascii_alphanumeric()))
+                    .unwrap_or(chars.len() - i - 1);
+                let name: String = chars[i + 1..i + 1 + len].iter().collect();
+                let is_ident = name
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c == '_' || c.is_ascii_alphabetic());
+                if is_ident && chars.get(i + 1 + len) == Some(&'}') {
+                    match quoting {
+                        Quoting::None => out.push_str(&format!("\"${{{}}}\"", name)),
+                        Quoting::Double => out.push_str(&format!("${{{}}}", name)),
+                        Quoting::Single => {
+                            return Err(format!(
+                                "placeholder {{{}}} cannot be used inside single quotes",
+                                name
+                            ))
+                        }
+                    }
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                    i += len + 2;
+                    continue;
+                }
+            }
+            ('\\', Quoting::None) | ('\\', Quoting::Double) => {
+                out.push(c);
+                if let Some(next) = next {
+                    out.push(next);
+                }
+                i += 2;
+                continue;
+            }
+            ('#', Quoting::None) if i == 0 || chars[i - 1].is_whitespace() => {
+                // A comment, which may contain unbalanced quotes
+                while i < chars.len() && chars[i] != '\n' {
+                    out.push(chars[i]);
+                    i += 1;
+                }
+                continue;
+            }
+            ('$', Quoting::Double) if next == Some('(') => {
+                out.push_str("$(");
+                stack.push(Quoting::None);
+                i += 2;
+                continue;
+            }
+            ('(', Quoting::None) => stack.push(Quoting::None),
+            (')', Quoting::None) if stack.len() > 1 => {
+                stack.pop();
+            }
+            ('\'', Quoting::None) => *stack.last_mut().expect("context") = Quoting::Single,
+            ('\'', Quoting::Single) => *stack.last_mut().expect("context") = Quoting::None,
+            ('"', Quoting::None) => *stack.last_mut().expect("context") = Quoting::Double,
+            ('"', Quoting::Double) => *stack.last_mut().expect("context") = Quoting::None,
+            _ => {}
+        }
+        out.push(c);
+        i += 1;
+    }
+    Ok((out, names))
+}
@@ -0,0 +1,131 @@
+use crate::config::Config;
+use crate::fingerprint::Fingerprint;
+use crate::internals::shell_quote;
+use crate::plan::redact;
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What is known about a script before it runs, to be written into the
+/// artifacts directory if it fails; see the `artifacts` option.
+pub(crate) struct Bundle {
+    dir: PathBuf,
+    name: Option<String>,
+    env: Vec<(OsString, OsString)>,
+}
+
+impl Bundle {
+    /// Prepare a bundle for `cmd`, whose environment was cleared before
+    /// variables were set if `cleared`.
+    pub(crate) fn new(dir: &Path, name: Option<&str>, cmd: &Command, cleared: bool) -> Self {
+        let mut env: Vec<(OsString, OsString)> = if cleared {
+            Vec::new()
+        } else {
+            std::env::vars_os().collect()
+        };
+        for (k, v) in cmd.get_envs() {
+            env.retain(|(name, _)| name != k);
+            if let Some(v) = v {
+                env.push((k.to_os_string(), redact(k, v)));
+            }
+        }
+        env.sort();
+        Bundle {
+            dir: dir.to_path_buf(),
+            name: name.map(String::from),
+            env,
+        }
+    }
+
+    /// Write the artifacts of a failed execution of `script` into a new
+    /// subdirectory, returning its path.
+    pub(crate) fn write(
+        &self,
+        script: &str,
+        status: ExitStatus,
+        line: Option<usize>,
+        duration: Duration,
+        stdout: &[u8],
+        stderr: &[u8],
+    ) -> Result<PathBuf, std::io::Error> {
+        let dir = self.create_dir()?;
+        std::fs::write(dir.join("script.sh"), script)?;
+        let mut env = String::new();
+        for (k, v) in self.env.iter() {
+            let value = shell_quote(&v.to_string_lossy());
+            writeln!(env, "{}={}", k.to_string_lossy(), value).unwrap();
+        }
+        std::fs::write(dir.join("env"), env)?;
+        if Config::current().fingerprint {
+            std::fs::write(
+                dir.join("environment"),
+                format!("{}\n", Fingerprint::current()),
+            )?;
+        }
+        std::fs::write(dir.join("stdout"), stdout)?;
+        std::fs::write(dir.join("stderr"), stderr)?;
+        let started = (SystemTime::now() - duration)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut summary = String::new();
+        let fields = [
+            ("name", self.name.clone()),
+            ("exit_code", status.code().map(|c| c.to_string())),
+            ("signal", status.signal().map(|s| s.to_string())),
+            ("line", line.map(|l| l.to_string())),
+            ("started", Some(format!("{:.3}", started.as_secs_f64()))),
+            ("duration", Some(format!("{:.3}", duration.as_secs_f64()))),
+        ];
+        for (key, value) in fields.iter() {
+            if let Some(value) = value {
+                writeln!(summary, "{}={}", key, shell_quote(value)).unwrap();
+            }
+        }
+        std::fs::write(dir.join("status"), summary)?;
+        Ok(dir)
+    }
+
+    /// Create the first free subdirectory `NAME-N`, where `NAME` is derived
+    /// from the `name` option, or is `script`.
+    fn create_dir(&self) -> Result<PathBuf, std::io::Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        let label: String = match self.name.as_deref() {
+            Some(name) => name
+                .chars()
+                .map(|c| match c {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' => c,
+                    _ => '-',
+                })
+                .collect(),
+            None => "script".to_string(),
+        };
+        for n in 1.. {
+            let dir = self.dir.join(format!("{}-{}", label, n));
+            match std::fs::create_dir(&dir) {
+                Ok(()) => return Ok(dir),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!()
+    }
+}
+
+/// Standard output passed on to that of this process, keeping a copy.
+pub(crate) struct Tee<'a>(pub(crate) &'a mut Vec<u8>);
+
+impl Write for Tee<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = std::io::stdout().write(buf)?;
+        self.0.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stdout().flush()
+    }
+}
@@ -0,0 +1,26 @@
+use crate::shell_arg::{ShellArg, ToShellArg};
+
+/// A binding for arbitrary binary data, such as a checksum or a small file
+/// header, which is passed to the script using `$'...'` escapes rather than
+/// a (lossy) conversion to UTF-8.
+///
+/// A plain `u8` binding is a number; wrap it as e.g. `Bytes([b';'])` to bind
+/// it as a character instead.
+///
+/// Shell variables cannot contain NUL bytes; bash truncates the value at the
+/// first one.
+///
+/// ```
+/// use sh_inline::*;
+/// let magic = Bytes(vec![0x7f, b'E', b'L', b'F']);
+/// bash!(r#"test "$(head -c 4 /proc/self/exe)" = "${magic}""#, magic)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bytes<T: AsRef<[u8]>>(pub T);
+
+impl<T: AsRef<[u8]>> ToShellArg for Bytes<T> {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg::bytes(&self.0)
+    }
+}
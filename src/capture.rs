@@ -0,0 +1,364 @@
+//! Capturing the output of scripts in ways that go beyond
+//! [`Command::output()`].
+//!
+//! The functions here take a [`Command`] as created by
+//! [`bash_command`](../macro.bash_command.html).
+//!
+//! [`Command`]: https://doc.rust-lang.org/std/process/struct.Command.html
+//! [`Command::output()`]: https://doc.rust-lang.org/std/process/struct.Command.html#method.output
+
+use crate::internals::nix_error;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::io::AsRawFd;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// The output stream a piece of output was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stream {
+    /// Standard output
+    Out,
+    /// Standard error
+    Err,
+}
+
+/// A piece of output, as returned by a single read from one of the streams.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// The stream which was written to
+    pub stream: Stream,
+    /// Time since the script was started
+    pub elapsed: Duration,
+    /// The output itself
+    pub data: Vec<u8>,
+}
+
+/// The output of a script with standard output and error interleaved in the
+/// order they were written; see [`interleaved`](fn.interleaved.html).
+#[derive(Debug, Clone)]
+pub struct Interleaved {
+    /// Exit status of the script
+    pub status: ExitStatus,
+    /// Output in the order it was received
+    pub chunks: Vec<Chunk>,
+}
+
+impl Interleaved {
+    /// All output written to `stream`, byte for byte.
+    pub fn bytes(&self, stream: Stream) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|c| c.stream == stream)
+            .flat_map(|c| c.data.iter().copied())
+            .collect()
+    }
+
+    /// Standard output, byte for byte.
+    pub fn stdout(&self) -> Vec<u8> {
+        self.bytes(Stream::Out)
+    }
+
+    /// Standard error, byte for byte.
+    pub fn stderr(&self) -> Vec<u8> {
+        self.bytes(Stream::Err)
+    }
+
+    /// Standard output as text, with invalid UTF-8 replaced by `U+FFFD`.
+    pub fn stdout_utf8_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.stdout()).into_owned()
+    }
+
+    /// Standard error as text, with invalid UTF-8 replaced by `U+FFFD`.
+    pub fn stderr_utf8_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.stderr()).into_owned()
+    }
+
+    /// Standard output as text, or an error holding the bytes if it is not
+    /// valid UTF-8.
+    pub fn stdout_str(&self) -> Result<String, std::string::FromUtf8Error> {
+        String::from_utf8(self.stdout())
+    }
+
+    /// Standard error as text, or an error holding the bytes if it is not
+    /// valid UTF-8.
+    pub fn stderr_str(&self) -> Result<String, std::string::FromUtf8Error> {
+        String::from_utf8(self.stderr())
+    }
+}
+
+/// Execute `cmd`, capturing its standard output and error as an ordered,
+/// timestamped sequence of chunks.
+///
+/// Both streams are polled together, so the relative order of writes is
+/// preserved as long as the script doesn't write to both faster than they
+/// can be read.
+///
+/// ```
+/// use sh_inline::*;
+/// use sh_inline::capture::Stream;
+/// let r = capture::interleaved(bash_command!(r"echo one; sleep 0.1; echo two >&2")?)?;
+/// assert!(r.status.success());
+/// let streams: Vec<_> = r.chunks.iter().map(|c| c.stream).collect();
+/// assert_eq!(streams, [Stream::Out, Stream::Err]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn interleaved(mut cmd: Command) -> Result<Interleaved, std::io::Error> {
+    use nix::poll::{poll, PollFd, PollFlags};
+    let start = Instant::now();
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let mut stdout = child.stdout.take().expect("stdout was piped");
+    let mut stderr = child.stderr.take().expect("stderr was piped");
+    let mut open = [true, true];
+    let mut chunks = Vec::new();
+    let mut buf = [0u8; 8192];
+    while open.iter().any(|&o| o) {
+        // Closed streams get a negative descriptor, which poll() ignores
+        let fd = |i: usize, raw| if open[i] { raw } else { -1 };
+        let mut fds = [
+            PollFd::new(fd(0, stdout.as_raw_fd()), PollFlags::POLLIN),
+            PollFd::new(fd(1, stderr.as_raw_fd()), PollFlags::POLLIN),
+        ];
+        match poll(&mut fds, -1) {
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            r => r.map_err(nix_error)?,
+        };
+        for (i, pfd) in fds.iter().enumerate() {
+            let ready = pfd.revents().map(|r| !r.is_empty()).unwrap_or_default();
+            if !open[i] || !ready {
+                continue;
+            }
+            let (n, stream) = if i == 0 {
+                (stdout.read(&mut buf)?, Stream::Out)
+            } else {
+                (stderr.read(&mut buf)?, Stream::Err)
+            };
+            if n == 0 {
+                open[i] = false;
+                continue;
+            }
+            chunks.push(Chunk {
+                stream,
+                elapsed: start.elapsed(),
+                data: buf[..n].to_vec(),
+            });
+        }
+    }
+    let status = child.wait()?;
+    Ok(Interleaved { status, chunks })
+}
+
+/// The output of a script as lines tagged with the stream they were written
+/// to, in order; see [`tagged`](fn.tagged.html).
+///
+/// The `Display` implementation shows the lines as they would have appeared
+/// in a terminal.
+#[derive(Debug, Clone)]
+pub struct Tagged {
+    /// Exit status of the script
+    pub status: ExitStatus,
+    /// Output lines, without trailing newlines
+    pub lines: Vec<(Stream, String)>,
+}
+
+impl From<Interleaved> for Tagged {
+    fn from(r: Interleaved) -> Self {
+        fn flush(lines: &mut Vec<(Stream, String)>, stream: Stream, line: &[u8]) {
+            lines.push((stream, String::from_utf8_lossy(line).into_owned()));
+        }
+        let mut lines = Vec::new();
+        let mut pending: [Vec<u8>; 2] = Default::default();
+        for chunk in r.chunks {
+            let buf = &mut pending[chunk.stream as usize];
+            for &c in chunk.data.iter() {
+                if c == b'\n' {
+                    flush(&mut lines, chunk.stream, buf);
+                    buf.clear();
+                } else {
+                    buf.push(c);
+                }
+            }
+        }
+        for (stream, buf) in [Stream::Out, Stream::Err].iter().zip(pending.iter()) {
+            if !buf.is_empty() {
+                flush(&mut lines, *stream, buf);
+            }
+        }
+        Tagged {
+            status: r.status,
+            lines,
+        }
+    }
+}
+
+impl std::fmt::Display for Tagged {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (_, line) in self.lines.iter() {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Execute `cmd`, capturing its standard output and error as a single list
+/// of lines each tagged with its stream.  This is built on
+/// [`interleaved`](fn.interleaved.html).
+///
+/// ```
+/// use sh_inline::*;
+/// use sh_inline::capture::Stream;
+/// let r = capture::tagged(bash_command!(r"echo one; sleep 0.1; echo two >&2")?)?;
+/// assert_eq!(r.lines, [(Stream::Out, "one".to_string()), (Stream::Err, "two".to_string())]);
+/// assert_eq!(r.to_string(), "one\ntwo\n");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn tagged(cmd: Command) -> Result<Tagged, std::io::Error> {
+    interleaved(cmd).map(Tagged::from)
+}
+
+/// Live, independent line streams for the standard output and error of a
+/// running script; see [`streams`](fn.streams.html).
+pub struct Streams {
+    /// Lines written to standard output, without trailing newlines
+    pub stdout: Receiver<String>,
+    /// Lines written to standard error, without trailing newlines
+    pub stderr: Receiver<String>,
+    child: Child,
+    readers: Vec<JoinHandle<Result<(), std::io::Error>>>,
+}
+
+impl Streams {
+    /// The process identifier of the `bash` process.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Wait for the script to exit and both streams to be closed.  Lines
+    /// not yet received remain available from the receivers.
+    pub fn wait(mut self) -> Result<ExitStatus, std::io::Error> {
+        let status = self.child.wait()?;
+        for reader in self.readers.drain(..) {
+            reader.join().expect("output reader thread panicked")?;
+        }
+        Ok(status)
+    }
+}
+
+/// Spawn `cmd`, returning independent channels of the lines written to its
+/// standard output and error.  Each stream is read by its own thread into an
+/// unbounded channel, so the script can never block on a full pipe
+/// regardless of which channel the caller reads from.
+///
+/// ```
+/// use sh_inline::*;
+/// let s = capture::streams(bash_command!(r"echo progress; echo warning >&2")?)?;
+/// assert_eq!(s.stdout.recv()?, "progress");
+/// assert_eq!(s.stderr.recv()?, "warning");
+/// assert!(s.wait()?.success());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn streams(mut cmd: Command) -> Result<Streams, std::io::Error> {
+    fn forward<R: Read + Send + 'static>(
+        r: R,
+    ) -> (Receiver<String>, JoinHandle<Result<(), std::io::Error>>) {
+        let (tx, rx) = channel();
+        let reader = std::thread::spawn(move || {
+            let mut r = BufReader::new(r);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                if r.read_until(b'\n', &mut line)? == 0 {
+                    return Ok(());
+                }
+                if line.last() == Some(&b'\n') {
+                    line.pop();
+                }
+                // The receiver may have been dropped; keep draining regardless
+                let _ = tx.send(String::from_utf8_lossy(&line).into_owned());
+            }
+        });
+        (rx, reader)
+    }
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let (stdout, out_reader) = forward(child.stdout.take().expect("stdout was piped"));
+    let (stderr, err_reader) = forward(child.stderr.take().expect("stderr was piped"));
+    Ok(Streams {
+        stdout,
+        stderr,
+        child,
+        readers: vec![out_reader, err_reader],
+    })
+}
+
+/// A compression format for [`compressed`](fn.compressed.html) output.
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// gzip at the default level (feature `gzip`)
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// zstd at the given level, where 0 selects the default (feature `zstd`)
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+/// The result of [`compressed`](fn.compressed.html).
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+#[derive(Debug)]
+pub struct Compressed<W> {
+    /// Exit status of the script
+    pub status: ExitStatus,
+    /// The writer given, holding the complete compressed output
+    pub output: W,
+    /// Size of the output before compression
+    pub len: u64,
+}
+
+/// Execute `cmd`, compressing its standard output and error, combined as
+/// with `2>&1`, into `output` (a file or buffer) as it is produced.  Memory
+/// use stays bounded regardless of how much the script writes, while the
+/// full log remains available for inspecting failures.
+///
+/// ```
+/// # #[cfg(feature = "gzip")] {
+/// use sh_inline::*;
+/// use sh_inline::capture::Compression;
+/// let cmd = bash_command!(r"head -c 1000000 /dev/zero; echo done >&2")?;
+/// let r = capture::compressed(cmd, Compression::Gzip, Vec::new())?;
+/// assert!(r.status.success());
+/// assert!(r.output.len() < r.len as usize / 4);
+/// # }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub fn compressed<W: std::io::Write>(
+    mut cmd: Command,
+    compression: Compression,
+    output: W,
+) -> Result<Compressed<W>, std::io::Error> {
+    let (mut reader, writer) = crate::internals::pipe()?;
+    cmd.stdout(writer.try_clone()?).stderr(writer);
+    let mut child = cmd.spawn()?;
+    // Close our copies of the write end so that reading ends with the script
+    drop(cmd);
+    let (len, output) = match compression {
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => {
+            let mut w = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+            let len = std::io::copy(&mut reader, &mut w)?;
+            (len, w.finish()?)
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd(level) => {
+            let mut w = zstd::stream::write::Encoder::new(output, level)?;
+            let len = std::io::copy(&mut reader, &mut w)?;
+            (len, w.finish()?)
+        }
+    };
+    Ok(Compressed {
+        status: child.wait()?,
+        output,
+        len,
+    })
+}
@@ -0,0 +1,185 @@
+use crate::limits::Limits;
+use crate::policy::Policy;
+use crate::recording::Recording;
+use crate::strict::StrictMode;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+static GLOBAL: RwLock<Config> = RwLock::new(Config::new());
+
+/// Process-wide defaults applied to every script, in addition to the
+/// options given to the individual macro invocations.
+///
+/// ```
+/// use sh_inline::*;
+/// Config::current().shopt("inherit_errexit").install();
+/// bash!(r"shopt -q inherit_errexit")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub(crate) shopt: Vec<String>,
+    pub(crate) safe_ifs: bool,
+    pub(crate) glob: Glob,
+    pub(crate) policy: Option<Policy>,
+    pub(crate) fast_path: bool,
+    pub(crate) bash: Option<PathBuf>,
+    pub(crate) bash_candidates: Option<Vec<PathBuf>>,
+    pub(crate) recording: Option<Recording>,
+    pub(crate) stderr_tail: usize,
+    pub(crate) limits: Option<Limits>,
+    pub(crate) artifacts: Option<PathBuf>,
+    pub(crate) strict: StrictMode,
+    pub(crate) fingerprint: bool,
+}
+
+/// How pathname expansion ("globbing") behaves in scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Glob {
+    /// The bash default: patterns that match nothing are left as-is.
+    #[default]
+    Enabled,
+    /// Patterns that match nothing are an error (`shopt -s failglob`).
+    Fail,
+    /// Pathname expansion is disabled entirely (`set -f`).
+    Disabled,
+}
+
+impl Glob {
+    /// The prelude line implementing this setting, if any.
+    pub(crate) fn prelude(self) -> Option<&'static str> {
+        match self {
+            Glob::Enabled => None,
+            Glob::Fail => Some("shopt -s failglob"),
+            Glob::Disabled => Some("set -f"),
+        }
+    }
+}
+
+impl Config {
+    /// An empty configuration.
+    pub const fn new() -> Self {
+        Config {
+            shopt: Vec::new(),
+            safe_ifs: false,
+            glob: Glob::Enabled,
+            policy: None,
+            fast_path: false,
+            bash: None,
+            bash_candidates: None,
+            recording: None,
+            stderr_tail: 0,
+            limits: None,
+            artifacts: None,
+            strict: StrictMode::new(),
+            fingerprint: false,
+        }
+    }
+
+    /// A copy of the configuration currently in effect.
+    pub fn current() -> Self {
+        GLOBAL.read().unwrap().clone()
+    }
+
+    /// Make this the configuration used for all subsequently rendered scripts.
+    pub fn install(self) {
+        *GLOBAL.write().unwrap() = self;
+    }
+
+    /// Set the shell options at the start of scripts which don't select
+    /// them with the `strict` option; see [`StrictMode`](struct.StrictMode.html).
+    pub fn strict(mut self, strict: StrictMode) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Enable the bash option `name` (as with `shopt -s`, e.g. `inherit_errexit`,
+    /// `nullglob`, `failglob` or `lastpipe`) after strict mode is set.
+    pub fn shopt(mut self, name: &str) -> Self {
+        self.shopt.push(name.to_string());
+        self
+    }
+
+    /// Set `IFS=$'\n\t'` so that word splitting only happens on newlines
+    /// and tabs, not spaces.
+    pub fn safe_ifs(mut self, safe_ifs: bool) -> Self {
+        self.safe_ifs = safe_ifs;
+        self
+    }
+
+    /// Control pathname expansion; see [`Glob`](enum.Glob.html).
+    pub fn glob(mut self, glob: Glob) -> Self {
+        self.glob = glob;
+        self
+    }
+
+    /// Execute scripts with the bash at `path`, rather than the one named by
+    /// the `SH_INLINE_BASH` environment variable or found by searching
+    /// `PATH` (which is only done once per process).
+    pub fn bash<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.bash = Some(path.into());
+        self
+    }
+
+    /// Locations to check for bash, in order, if it is not found in `PATH`.
+    /// The default covers the usual locations along with e.g. NixOS and
+    /// Homebrew; if none exists, executing scripts fails with an error
+    /// listing the locations tried.
+    pub fn bash_candidates<I, P>(mut self, candidates: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.bash_candidates = Some(candidates.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Execute scripts consisting of a single simple command directly, without
+    /// bash; see the `fast_path` option.
+    pub fn fast_path(mut self, fast_path: bool) -> Self {
+        self.fast_path = fast_path;
+        self
+    }
+
+    /// Only execute scripts allowed by `policy`; see [`Policy`](struct.Policy.html).
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Record all script executions, or replay them without running
+    /// anything; see [`Recording`](enum.Recording.html).
+    pub fn recording(mut self, recording: Recording) -> Self {
+        self.recording = Some(recording);
+        self
+    }
+
+    /// Keep the last `bytes` of the standard error of failing scripts in
+    /// their errors; see the `stderr_tail` option.
+    pub fn stderr_tail(mut self, bytes: usize) -> Self {
+        self.stderr_tail = bytes;
+        self
+    }
+
+    /// Apply the hardening profile `limits` to scripts which don't select
+    /// one with the `limits` option.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Write the artifacts of failing scripts into `dir`, e.g. for a CI
+    /// system to collect; see the `artifacts` option.
+    pub fn artifacts<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.artifacts = Some(dir.into());
+        self
+    }
+
+    /// Attach the [`Fingerprint`](struct.Fingerprint.html) of the
+    /// environment to the errors of scripts, whose messages then end with
+    /// its identifier, and write it into their artifacts as `environment`.
+    pub fn fingerprint(mut self, fingerprint: bool) -> Self {
+        self.fingerprint = fingerprint;
+        self
+    }
+}
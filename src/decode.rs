@@ -0,0 +1,230 @@
+//! Decoding the standard output of a script into typed rows; see
+//! [`BashOutput::decode`](../struct.BashOutput.html#method.decode).
+
+#[cfg(any(feature = "json", feature = "csv"))]
+use std::marker::PhantomData;
+
+/// A format for the standard output of a script, turning it into a
+/// sequence of rows.
+///
+/// Implementations for JSON lines and for CSV and TSV tables are provided
+/// with the `json` and `csv` features; other formats can be added by
+/// implementing this trait.
+///
+/// ```
+/// use sh_inline::*;
+/// use sh_inline::decode::Decoder;
+///
+/// struct Words;
+/// impl Decoder for Words {
+///     type Row = String;
+///     fn decode(&self, stdout: &[u8]) -> Result<Vec<String>, std::io::Error> {
+///         Ok(String::from_utf8_lossy(stdout)
+///             .split_whitespace()
+///             .map(String::from)
+///             .collect())
+///     }
+/// }
+///
+/// let r = bash_run!(r"echo a b; echo c")?;
+/// assert_eq!(r.decode(Words)?, ["a", "b", "c"]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub trait Decoder {
+    /// The type each row is decoded into
+    type Row;
+
+    /// Decode all of standard output, returning an error of kind
+    /// `InvalidData` if it is not in the expected format.
+    fn decode(&self, stdout: &[u8]) -> Result<Vec<Self::Row>, std::io::Error>;
+}
+
+/// An error of kind `InvalidData` for a row which could not be decoded.
+#[cfg(any(feature = "json", feature = "csv"))]
+fn invalid<E: std::fmt::Display>(format: &str, line: u64, e: E) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!(
+            "invalid {} in bash script output at line {}: {}",
+            format, line, e
+        ),
+    )
+}
+
+/// One JSON value per line, deserialized with serde into `T`; blank lines
+/// are skipped (feature `json`).
+///
+/// ```
+/// # #[cfg(feature = "json")] {
+/// use sh_inline::*;
+/// use sh_inline::decode::JsonLines;
+/// let r = bash_run!(r#"echo '{"n": 1}'; echo '{"n": 2}'"#)?;
+/// let rows: Vec<std::collections::BTreeMap<String, u32>> = r.decode(JsonLines::new())?;
+/// assert_eq!(rows[1]["n"], 2);
+/// # }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(feature = "json")]
+pub struct JsonLines<T>(PhantomData<fn() -> T>);
+
+#[cfg(feature = "json")]
+impl<T: serde::de::DeserializeOwned> JsonLines<T> {
+    /// Decode each line into `T`.
+    pub fn new() -> Self {
+        JsonLines(PhantomData)
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: serde::de::DeserializeOwned> Default for JsonLines<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: serde::de::DeserializeOwned> Decoder for JsonLines<T> {
+    type Row = T;
+
+    fn decode(&self, stdout: &[u8]) -> Result<Vec<T>, std::io::Error> {
+        stdout
+            .split(|&c| c == b'\n')
+            .enumerate()
+            .filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+            .map(|(i, line)| {
+                serde_json::from_slice(line).map_err(|e| invalid("JSON", i as u64 + 1, e))
+            })
+            .collect()
+    }
+}
+
+/// A table of delimited values, deserialized with serde into `T` (feature
+/// `csv`).
+///
+/// By default the first line holds the column names, which are matched to
+/// the fields of `T`; without headers, columns are matched by position, as
+/// for tuples.
+///
+/// ```
+/// # #[cfg(feature = "csv")] {
+/// use sh_inline::*;
+/// use sh_inline::decode::Csv;
+/// let r = bash_run!(r#"printf 'name,size\n"a, b",3\nc,4\n'"#)?;
+/// let rows: Vec<(String, u64)> = r.decode(Csv::new())?;
+/// assert_eq!(rows, [("a, b".to_string(), 3), ("c".to_string(), 4)]);
+/// # }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(feature = "csv")]
+pub struct Csv<T> {
+    delimiter: u8,
+    quoting: bool,
+    headers: bool,
+    row: PhantomData<fn() -> T>,
+}
+
+/// Tab-separated values, as printed by many command line tools, without
+/// quoting; see [`Csv`](struct.Csv.html) for how rows are matched
+/// (feature `csv`).
+///
+/// ```
+/// # #[cfg(feature = "csv")] {
+/// use sh_inline::*;
+/// use sh_inline::decode::Tsv;
+/// let r = bash_run!(r#"printf '1\t"x"\n2\ty\n'"#)?;
+/// let rows: Vec<(u32, String)> = r.decode(Tsv::new().no_headers())?;
+/// assert_eq!(rows[0].1, "\"x\"");
+/// # }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(feature = "csv")]
+pub struct Tsv<T>(Csv<T>);
+
+#[cfg(feature = "csv")]
+impl<T: serde::de::DeserializeOwned> Csv<T> {
+    /// Comma-separated values with a header line.
+    pub fn new() -> Self {
+        Csv {
+            delimiter: b',',
+            quoting: true,
+            headers: true,
+            row: PhantomData,
+        }
+    }
+
+    /// Separate values with `delimiter` rather than a comma.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Treat every line as a row, matching columns by position.
+    pub fn no_headers(mut self) -> Self {
+        self.headers = false;
+        self
+    }
+}
+
+#[cfg(feature = "csv")]
+impl<T: serde::de::DeserializeOwned> Default for Csv<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "csv")]
+impl<T: serde::de::DeserializeOwned> Tsv<T> {
+    /// Tab-separated values with a header line.
+    pub fn new() -> Self {
+        let mut csv = Csv::new().delimiter(b'\t');
+        csv.quoting = false;
+        Tsv(csv)
+    }
+
+    /// Treat every line as a row, matching columns by position.
+    pub fn no_headers(self) -> Self {
+        Tsv(self.0.no_headers())
+    }
+}
+
+#[cfg(feature = "csv")]
+impl<T: serde::de::DeserializeOwned> Default for Tsv<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "csv")]
+impl<T: serde::de::DeserializeOwned> Decoder for Tsv<T> {
+    type Row = T;
+
+    fn decode(&self, stdout: &[u8]) -> Result<Vec<T>, std::io::Error> {
+        self.0.decode(stdout)
+    }
+}
+
+#[cfg(feature = "csv")]
+impl<T: serde::de::DeserializeOwned> Decoder for Csv<T> {
+    type Row = T;
+
+    fn decode(&self, stdout: &[u8]) -> Result<Vec<T>, std::io::Error> {
+        let format = if self.delimiter == b'\t' {
+            "TSV"
+        } else {
+            "CSV"
+        };
+        csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .quoting(self.quoting)
+            .has_headers(self.headers)
+            .from_reader(stdout)
+            .into_deserialize()
+            .map(|row| {
+                row.map_err(|e| {
+                    let line = e.position().map(|p| p.line()).unwrap_or(0);
+                    invalid(format, line, e)
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+
+/// The changes a script made to its exported environment, as returned by
+/// [`bash_env`](macro.bash_env.html).
+///
+/// The environment is recorded when the script starts and again when it
+/// exits, from an `EXIT` trap; a script which replaces that trap, or the
+/// shell via `exec`, fails with an error of kind `InvalidData`.  The `_`
+/// variable, which bash updates after every command, is ignored.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EnvDiff {
+    /// Variables exported with a new or changed value; names are always
+    /// valid shell identifiers
+    pub set: BTreeMap<String, OsString>,
+    /// Variables which were exported at the start but no longer are
+    pub unset: BTreeSet<String>,
+}
+
+impl EnvDiff {
+    /// Whether the script left its environment unchanged.
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.unset.is_empty()
+    }
+
+    /// Keep only the changes to the variables in `names`.
+    pub fn select<S: AsRef<str>>(mut self, names: &[S]) -> Self {
+        let wanted = |name: &String| names.iter().any(|n| n.as_ref() == name);
+        self.set.retain(|name, _| wanted(name));
+        self.unset.retain(wanted);
+        self
+    }
+
+    /// Apply the changes to the environment of this process, as `source`
+    /// would in a shell.  Like `std::env::set_var`, this should only be
+    /// done while no other threads may be accessing the environment.
+    pub fn apply(&self) {
+        for (name, value) in self.set.iter() {
+            std::env::set_var(name, value);
+        }
+        for name in self.unset.iter() {
+            std::env::remove_var(name);
+        }
+    }
+
+    /// Apply the changes to an environment held in a map, such as one
+    /// collected from `std::env::vars_os()`.
+    pub fn apply_to(&self, env: &mut HashMap<OsString, OsString>) {
+        for (name, value) in self.set.iter() {
+            env.insert(name.into(), value.clone());
+        }
+        for name in self.unset.iter() {
+            env.remove(std::ffi::OsStr::new(name));
+        }
+    }
+}
+
+/// Script text recording the exported environment to `fd` now, as
+/// NUL-terminated `NAME=VALUE` records followed by an empty record, and
+/// defining `__sh_inline_env` to record it again when the script exits.
+pub(crate) fn prelude(fd: i32) -> String {
+    format!(
+        r#"__sh_inline_env() {{
+    local __sh_inline_name __sh_inline_names
+    mapfile -t __sh_inline_names < <(compgen -e)
+    for __sh_inline_name in "${{__sh_inline_names[@]}}"; do
+        [ -n "${{!__sh_inline_name+x}}" ] || continue
+        printf '%s=%s\0' "${{__sh_inline_name}}" "${{!__sh_inline_name}}"
+    done
+    printf '\0'
+}} >&{}
+__sh_inline_env
+"#,
+        fd
+    )
+}
+
+type Snapshot = BTreeMap<String, OsString>;
+
+/// Parse the two snapshots written by the code from [`prelude`] and compute
+/// their difference.
+pub(crate) fn diff(recorded: &[u8]) -> Result<EnvDiff, std::io::Error> {
+    let mut snapshots: Vec<Snapshot> = Vec::new();
+    let mut current = Snapshot::new();
+    let records = recorded.strip_suffix(b"\0").unwrap_or(recorded);
+    for record in records.split(|&c| c == 0) {
+        if record.is_empty() {
+            snapshots.push(std::mem::take(&mut current));
+            continue;
+        }
+        let eq = record
+            .iter()
+            .position(|&c| c == b'=')
+            .unwrap_or(record.len());
+        let (name, value) = record.split_at(eq);
+        if name != b"_" {
+            let value = value.get(1..).unwrap_or_default();
+            current.insert(
+                String::from_utf8_lossy(name).into_owned(),
+                OsString::from_vec(value.into()),
+            );
+        }
+    }
+    let (start, end) = match (snapshots.first(), snapshots.get(1)) {
+        (Some(start), Some(end)) => (start, end),
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "bash script exited without recording its environment",
+            ))
+        }
+    };
+    Ok(EnvDiff {
+        set: end
+            .iter()
+            .filter(|(name, value)| start.get(*name) != Some(*value))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect(),
+        unset: start
+            .keys()
+            .filter(|name| !end.contains_key(*name))
+            .cloned()
+            .collect(),
+    })
+}
@@ -0,0 +1,267 @@
+use crate::config::Config;
+use crate::fingerprint::Fingerprint;
+use std::fmt;
+use std::fmt::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// Why a bash script failed.
+///
+/// The functions and macros of this crate return `std::io::Error`, so that
+/// they compose with other I/O; when a script could not be started, timed
+/// out or exited unsuccessfully, the error wraps one of these, which can be
+/// retrieved with [`Error::of`](#method.of).  Standard error is only
+/// included if it was captured, for instance with the `stderr_tail` option.
+/// When the [`line`](#method.line) of the failing command is known, the
+/// message shows the lines of the script around it, marking the command
+/// with a caret.
+///
+/// ```
+/// use sh_inline::*;
+/// let e = bash!(stderr_tail = 1024, r"echo oops >&2; exit 3").unwrap_err();
+/// let failure = Error::of(&e).expect("script failure");
+/// assert_eq!(failure.code(), Some(3));
+/// assert_eq!(failure.stderr(), b"oops\n");
+/// assert!(failure.script().unwrap().ends_with("exit 3"));
+/// ```
+#[derive(Debug)]
+pub enum Error {
+    /// The script exited unsuccessfully or was killed by a signal
+    Exit {
+        /// The name given with the `name` option, if any
+        name: Option<String>,
+        /// How the script exited
+        status: ExitStatus,
+        /// The script as rendered, including the prelude, if known
+        script: Option<String>,
+        /// The line of the rendered script at which a failing command made
+        /// it exit, if known
+        line: Option<usize>,
+        /// The end of the standard error of the script, if it was captured
+        stderr: Vec<u8>,
+        /// The fingerprint of the environment, with `Config::fingerprint`
+        fingerprint: Option<Fingerprint>,
+    },
+    /// The script was terminated because it exceeded its time limit
+    TimedOut {
+        /// The name given with the `name` option, if any
+        name: Option<String>,
+        /// The time limit
+        limit: Duration,
+        /// The script as rendered, including the prelude
+        script: String,
+        /// The fingerprint of the environment, with `Config::fingerprint`
+        fingerprint: Option<Fingerprint>,
+    },
+    /// The script could not be started
+    Spawn {
+        /// The name given with the `name` option, if any
+        name: Option<String>,
+        /// The script as rendered, including the prelude
+        script: String,
+        /// The error from starting bash
+        source: std::io::Error,
+        /// The fingerprint of the environment, with `Config::fingerprint`
+        fingerprint: Option<Fingerprint>,
+    },
+}
+
+impl Error {
+    /// The script failure wrapped by `e`, if any.
+    pub fn of(e: &std::io::Error) -> Option<&Error> {
+        e.get_ref()?.downcast_ref()
+    }
+
+    /// The name given with the `name` option, if any.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Error::Exit { name, .. } | Error::TimedOut { name, .. } | Error::Spawn { name, .. } => {
+                name.as_deref()
+            }
+        }
+    }
+
+    /// The exit code of the script, if it exited normally.
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            Error::Exit { status, .. } => status.code(),
+            Error::TimedOut { .. } | Error::Spawn { .. } => None,
+        }
+    }
+
+    /// The signal which killed the script, if any.
+    pub fn signal(&self) -> Option<i32> {
+        match self {
+            Error::Exit { status, .. } => status.signal(),
+            Error::TimedOut { .. } | Error::Spawn { .. } => None,
+        }
+    }
+
+    /// The script as rendered, including the prelude, if known.
+    pub fn script(&self) -> Option<&str> {
+        match self {
+            Error::Exit { script, .. } => script.as_deref(),
+            Error::TimedOut { script, .. } | Error::Spawn { script, .. } => Some(script),
+        }
+    }
+
+    /// The line of the rendered [`script`](#method.script) at which a
+    /// failing command made the script exit, if known.  This is recorded by
+    /// an `ERR` trap when the script is run to completion by e.g. `bash!`,
+    /// so it is not known for scripts which call `exit` or replace the trap,
+    /// nor for those spawned or replayed from a recording.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Error::Exit { line, .. } => *line,
+            Error::TimedOut { .. } | Error::Spawn { .. } => None,
+        }
+    }
+
+    /// The end of the standard error of the script; empty if it was not
+    /// captured.
+    pub fn stderr(&self) -> &[u8] {
+        match self {
+            Error::Exit { stderr, .. } => stderr,
+            Error::TimedOut { .. } | Error::Spawn { .. } => &[],
+        }
+    }
+
+    /// The fingerprint of the environment the script was executed in, if
+    /// enabled with [`Config::fingerprint`](struct.Config.html#method.fingerprint).
+    pub fn fingerprint(&self) -> Option<&Fingerprint> {
+        match self {
+            Error::Exit { fingerprint, .. }
+            | Error::TimedOut { fingerprint, .. }
+            | Error::Spawn { fingerprint, .. } => fingerprint.as_ref(),
+        }
+    }
+
+    /// Return an error wrapping an `Exit` failure if `status` is
+    /// unsuccessful, keeping at most the last `tail` bytes of `stderr`.
+    pub(crate) fn check(
+        status: ExitStatus,
+        name: Option<&str>,
+        script: Option<&str>,
+        line: Option<usize>,
+        stderr: &[u8],
+        tail: usize,
+    ) -> Result<(), std::io::Error> {
+        if status.success() {
+            return Ok(());
+        }
+        let stderr = &stderr[stderr.len().saturating_sub(tail)..];
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            Error::Exit {
+                name: name.map(String::from),
+                status,
+                script: script.map(String::from),
+                line,
+                stderr: stderr.to_vec(),
+                fingerprint: fingerprint(),
+            },
+        ))
+    }
+
+    /// An error of kind `TimedOut` for a script which exceeded `limit`.
+    pub(crate) fn timed_out(limit: Duration, name: Option<&str>, script: &str) -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            Error::TimedOut {
+                name: name.map(String::from),
+                limit,
+                script: script.to_string(),
+                fingerprint: fingerprint(),
+            },
+        )
+    }
+
+    /// Wrap an error from starting a script, keeping its kind.
+    pub(crate) fn spawn(
+        source: std::io::Error,
+        name: Option<&str>,
+        script: &str,
+    ) -> std::io::Error {
+        std::io::Error::new(
+            source.kind(),
+            Error::Spawn {
+                name: name.map(String::from),
+                script: script.to_string(),
+                source,
+                fingerprint: fingerprint(),
+            },
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = crate::internals::describe(self.name());
+        match self {
+            Error::Exit {
+                status,
+                script,
+                line,
+                stderr,
+                ..
+            } => {
+                write!(f, "{} failed: {}", what, status)?;
+                if let Some(excerpt) = line.zip(script.as_deref()).and_then(|(l, s)| excerpt(s, l))
+                {
+                    write!(f, "\n{}", excerpt.trim_end())?;
+                }
+                if !stderr.is_empty() {
+                    let stderr = String::from_utf8_lossy(stderr);
+                    write!(f, "\nstandard error:\n{}", stderr.trim_end())?;
+                }
+            }
+            Error::TimedOut { limit, .. } => write!(f, "{} timed out after {:?}", what, limit)?,
+            Error::Spawn { source, .. } => write!(f, "{} could not be started: {}", what, source)?,
+        }
+        if let Some(fingerprint) = self.fingerprint() {
+            write!(f, "\nenvironment fingerprint {}", fingerprint.id())?;
+        }
+        Ok(())
+    }
+}
+
+/// The fingerprint to attach to errors, if enabled.
+fn fingerprint() -> Option<Fingerprint> {
+    match Config::current().fingerprint {
+        true => Some(Fingerprint::current()),
+        false => None,
+    }
+}
+
+/// Lines of context shown around the failing line of a script.
+const EXCERPT_CONTEXT: usize = 2;
+
+/// The lines of `script` around `line`, with a caret marker under it.
+fn excerpt(script: &str, line: usize) -> Option<String> {
+    let lines: Vec<&str> = script.lines().collect();
+    lines.get(line.checked_sub(1)?)?;
+    let first = line.saturating_sub(EXCERPT_CONTEXT).max(1);
+    let last = (line + EXCERPT_CONTEXT).min(lines.len());
+    let width = last.to_string().len();
+    let mut r = format!("at line {}:\n", line);
+    for (n, text) in (first..=last).zip(&lines[first - 1..last]) {
+        let marker = if n == line { '>' } else { ' ' };
+        writeln!(r, "{} {:>width$} | {}", marker, n, text, width = width).unwrap();
+        if n == line {
+            let indent = &text[..text.len() - text.trim_start().len()];
+            let carets = "^".repeat(text.trim().chars().count().max(1));
+            writeln!(r, "  {:width$} | {}{}", "", indent, carets, width = width).unwrap();
+        }
+    }
+    Some(r)
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Exit { .. } | Error::TimedOut { .. } => None,
+            Error::Spawn { source, .. } => Some(source),
+        }
+    }
+}
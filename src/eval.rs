@@ -0,0 +1,51 @@
+use std::fmt;
+
+/// The error returned by [`bash_eval`](macro.bash_eval.html): either
+/// executing the script failed, or its output could not be parsed.
+#[derive(Debug)]
+pub enum EvalError<E> {
+    /// The script could not be executed or exited unsuccessfully
+    Io(std::io::Error),
+    /// The (trimmed) output of the script could not be parsed
+    Parse {
+        /// The output which failed to parse
+        output: String,
+        /// The error from `FromStr`
+        error: E,
+    },
+}
+
+impl<E: fmt::Display> fmt::Display for EvalError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::Io(e) => write!(f, "{}", e),
+            EvalError::Parse { output, error } => {
+                write!(f, "parsing output {:?} of bash script: {}", output, error)
+            }
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for EvalError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EvalError::Io(e) => Some(e),
+            EvalError::Parse { error, .. } => Some(error),
+        }
+    }
+}
+
+impl<E> From<std::io::Error> for EvalError<E> {
+    fn from(e: std::io::Error) -> Self {
+        EvalError::Io(e)
+    }
+}
+
+impl<E: std::error::Error + Send + Sync + 'static> From<EvalError<E>> for std::io::Error {
+    fn from(e: EvalError<E>) -> Self {
+        match e {
+            EvalError::Io(e) => e,
+            e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        }
+    }
+}
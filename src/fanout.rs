@@ -0,0 +1,154 @@
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, ExitStatus, Stdio};
+
+/// How [`fan_out`](fn.fan_out.html) assigns lines to consumers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Distribution {
+    /// Each line goes to the next consumer in turn.
+    #[default]
+    RoundRobin,
+    /// Identical lines always go to the same consumer.
+    Hash,
+}
+
+/// The exit statuses of the scripts run by [`fan_out`](fn.fan_out.html).
+#[derive(Debug, Clone)]
+pub struct FanOut {
+    /// Exit status of the producer
+    pub producer: ExitStatus,
+    /// Exit statuses of the consumers, in order
+    pub consumers: Vec<ExitStatus>,
+}
+
+impl FanOut {
+    /// Whether the producer and all the consumers exited successfully.
+    pub fn success(&self) -> bool {
+        self.producer.success() && self.consumers.iter().all(|s| s.success())
+    }
+
+    /// Return an error describing the first script which exited
+    /// unsuccessfully, if any.
+    pub fn check(&self) -> Result<(), std::io::Error> {
+        if !self.producer.success() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("producer bash script failed: {}", self.producer),
+            ));
+        }
+        if let Some((i, status)) = self
+            .consumers
+            .iter()
+            .enumerate()
+            .find(|(_, s)| !s.success())
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("consumer bash script {} failed: {}", i, status),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Run the `producer` script and distribute the lines of its standard output
+/// among `n` concurrently running instances of a consumer script, which
+/// read them from standard input; `consumer` is called with the index of
+/// each instance to create it.  Consumers must be created with the option
+/// `stdin = Stdio::piped()`.
+///
+/// Lines are passed on as they are produced; a consumer which isn't keeping
+/// up holds back the others.  If a consumer exits early, the lines it would
+/// have received are given to the others with `RoundRobin` and dropped with
+/// `Hash`.  All the scripts are waited for, and their statuses returned.
+///
+/// ```
+/// use sh_inline::*;
+/// use std::process::Stdio;
+/// let dir = tempfile::tempdir()?;
+/// let dir = dir.path();
+/// let r = fan_out(bash_command!(r"seq 1 10")?, 3, Distribution::RoundRobin, |i| {
+///     bash_command!(stdin = Stdio::piped(), r#"while read n; do echo "${n}" >> "${dir}/${i}"; done"#, dir, i)
+/// })?;
+/// r.check()?;
+/// assert_eq!(std::fs::read_to_string(dir.join("0"))?, "1\n4\n7\n10\n");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn fan_out<F>(
+    mut producer: Command,
+    n: usize,
+    distribution: Distribution,
+    mut consumer: F,
+) -> Result<FanOut, std::io::Error>
+where
+    F: FnMut(usize) -> Result<Command, std::io::Error>,
+{
+    if n == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "fan_out needs at least one consumer",
+        ));
+    }
+    let mut consumers = Vec::with_capacity(n);
+    for i in 0..n {
+        let mut cmd = consumer(i)?;
+        let mut child = cmd.spawn()?;
+        let stdin = child.stdin.take().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "fan_out consumers must be created with stdin = Stdio::piped()",
+            )
+        })?;
+        consumers.push((cmd, child, Some(stdin)));
+    }
+    let mut child = producer.stdout(Stdio::piped()).spawn()?;
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout was piped"));
+    let mut line = Vec::new();
+    let mut next = 0;
+    loop {
+        line.clear();
+        if stdout.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if line.last() != Some(&b'\n') {
+            line.push(b'\n');
+        }
+        let target = match distribution {
+            Distribution::RoundRobin => {
+                let open = (0..n)
+                    .map(|k| (next + k) % n)
+                    .find(|&k| consumers[k].2.is_some());
+                match open {
+                    Some(k) => k,
+                    None => break,
+                }
+            }
+            Distribution::Hash => {
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                line.hash(&mut hasher);
+                (hasher.finish() % n as u64) as usize
+            }
+        };
+        next = target + 1;
+        if let Some(stdin) = consumers[target].2.as_mut() {
+            if let Err(e) = stdin.write_all(&line) {
+                if e.kind() != std::io::ErrorKind::BrokenPipe {
+                    return Err(e);
+                }
+                consumers[target].2 = None;
+            }
+        }
+    }
+    // Stop reading; a producer still writing gets SIGPIPE
+    drop(stdout);
+    let producer = child.wait()?;
+    let mut statuses = Vec::with_capacity(n);
+    for (_cmd, mut child, stdin) in consumers {
+        drop(stdin);
+        statuses.push(child.wait()?);
+    }
+    Ok(FanOut {
+        producer,
+        consumers: statuses,
+    })
+}
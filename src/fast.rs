@@ -0,0 +1,162 @@
+use crate::internals::CommandArg;
+
+/// The builtins and reserved words of bash, which take precedence over an
+/// executable of the same name and may behave differently from it (e.g.
+/// `echo`), if one exists at all.
+const SHELL_WORDS: &[&str] = &[
+    "!",
+    ".",
+    ":",
+    "[",
+    "[[",
+    "]]",
+    "{",
+    "}",
+    "alias",
+    "bg",
+    "bind",
+    "break",
+    "builtin",
+    "caller",
+    "case",
+    "cd",
+    "command",
+    "compgen",
+    "complete",
+    "compopt",
+    "continue",
+    "coproc",
+    "declare",
+    "dirs",
+    "disown",
+    "do",
+    "done",
+    "echo",
+    "elif",
+    "else",
+    "enable",
+    "esac",
+    "eval",
+    "exec",
+    "exit",
+    "export",
+    "false",
+    "fc",
+    "fg",
+    "fi",
+    "for",
+    "function",
+    "getopts",
+    "hash",
+    "help",
+    "history",
+    "if",
+    "in",
+    "jobs",
+    "kill",
+    "let",
+    "local",
+    "logout",
+    "mapfile",
+    "popd",
+    "printf",
+    "pushd",
+    "pwd",
+    "read",
+    "readarray",
+    "readonly",
+    "return",
+    "select",
+    "set",
+    "shift",
+    "shopt",
+    "source",
+    "suspend",
+    "test",
+    "then",
+    "time",
+    "times",
+    "trap",
+    "true",
+    "type",
+    "typeset",
+    "ulimit",
+    "umask",
+    "unalias",
+    "unset",
+    "until",
+    "wait",
+    "while",
+];
+
+/// Whether `c` can appear in a word without quoting.
+fn is_plain(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c)
+}
+
+/// Parse a reference to a variable, `$name` or `${name}`, making up all of `word`.
+fn variable(word: &str) -> Option<&str> {
+    let name = word.strip_prefix('$')?;
+    let name = match name.strip_prefix('{') {
+        Some(name) => name.strip_suffix('}')?,
+        None => name,
+    };
+    let valid = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+/// If `script` is a single simple command consisting only of plain words and
+/// references to the (literal) variables `lookup` knows about, return the
+/// arguments it would be executed with; otherwise bash is required.
+pub(crate) fn direct_argv<'a, F>(script: &str, lookup: F) -> Option<Vec<String>>
+where
+    F: Fn(&str) -> Option<&'a CommandArg>,
+{
+    let script = script.trim_end_matches('\n');
+    if script.contains('\n') {
+        return None;
+    }
+    let mut argv = Vec::new();
+    for word in script.split([' ', '\t']).filter(|w| !w.is_empty()) {
+        let (quoted, inner) = match word.strip_prefix('"').and_then(|w| w.strip_suffix('"')) {
+            Some(inner) => (true, inner),
+            None => (false, word),
+        };
+        match (variable(inner), quoted) {
+            (Some(name), _) => {
+                let value = match lookup(name)? {
+                    CommandArg::Empty if quoted => "",
+                    CommandArg::Empty => continue,
+                    CommandArg::Literal(value) => value.as_str(),
+                    CommandArg::Path(path) => path.to_str()?,
+                    _ => return None,
+                };
+                // Unquoted values are subject to word splitting and
+                // pathname expansion, which we don't emulate
+                if !quoted && (value.is_empty() || !value.chars().all(is_plain)) {
+                    return None;
+                }
+                argv.push(value.to_string());
+            }
+            (None, false) if inner.chars().all(is_plain) => argv.push(inner.to_string()),
+            _ => return None,
+        }
+    }
+    // An assignment rather than a command
+    match argv.first() {
+        Some(program)
+            if !program.is_empty()
+                && !program.contains('=')
+                && !SHELL_WORDS.contains(&program.as_str()) =>
+        {
+            Some(argv)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,131 @@
+use crate::config::Config;
+use crate::internals;
+use crate::version::BashVersion;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Environment variables which commonly change how scripts behave, in
+/// addition to the locale (`LANG` and `LC_*`) and those of this crate
+/// (`SH_INLINE_*`).
+const RELEVANT_VARS: &[&str] = &[
+    "PATH",
+    "HOME",
+    "SHELL",
+    "TMPDIR",
+    "TZ",
+    "LANGUAGE",
+    "BASH_ENV",
+    "ENV",
+    "BASHOPTS",
+    "SHELLOPTS",
+    "POSIXLY_CORRECT",
+    "CDPATH",
+    "GLOBIGNORE",
+    "IFS",
+];
+
+static CURRENT: OnceLock<Fingerprint> = OnceLock::new();
+
+/// A summary of the environment scripts are executed in, to diagnose
+/// scripts which behave differently on different machines from their logs.
+///
+/// The `Display` implementation is a report listing the bash executable
+/// and its version, the operating system, the umask and the values of the
+/// relevant environment variables, headed by an [`id`](#method.id) which
+/// differs whenever any of them does.  With
+/// [`Config::fingerprint`](struct.Config.html#method.fingerprint), errors
+/// and artifacts of failing scripts include it.
+///
+/// ```
+/// use sh_inline::*;
+/// let fingerprint = Fingerprint::current();
+/// assert!(fingerprint.to_string().starts_with(&format!("fingerprint {}\n", fingerprint.id())));
+/// assert!(fingerprint.vars.iter().any(|(name, _)| name == "PATH"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// The bash executable used for scripts, if one was found
+    pub bash: Option<PathBuf>,
+    /// Its version, if it could be detected
+    pub bash_version: Option<BashVersion>,
+    /// The operating system and architecture, e.g. `linux x86_64`
+    pub os: String,
+    /// The file mode creation mask, if known
+    pub umask: Option<u32>,
+    /// The relevant environment variables which are set, sorted by name
+    pub vars: Vec<(String, String)>,
+}
+
+impl Fingerprint {
+    /// The fingerprint captured the first time this is called; call it at
+    /// startup, before the environment is changed, to log it.
+    pub fn current() -> Fingerprint {
+        CURRENT.get_or_init(Self::capture).clone()
+    }
+
+    /// Capture the fingerprint of the environment as it is now.
+    pub fn capture() -> Fingerprint {
+        let bash = internals::bash_path(&Config::current()).ok();
+        let bash_version = bash.as_ref().and_then(|b| BashVersion::of(b).ok());
+        let mut vars: Vec<(String, String)> = std::env::vars_os()
+            .filter_map(|(k, v)| {
+                let k = k.into_string().ok()?;
+                let relevant = RELEVANT_VARS.contains(&k.as_str())
+                    || k == "LANG"
+                    || k.starts_with("LC_")
+                    || k.starts_with("SH_INLINE_");
+                relevant.then(|| (k, v.to_string_lossy().into_owned()))
+            })
+            .collect();
+        vars.sort();
+        Fingerprint {
+            bash,
+            bash_version,
+            os: format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+            umask: umask(),
+            vars,
+        }
+    }
+
+    /// A short identifier, a hash of the whole fingerprint.
+    pub fn id(&self) -> String {
+        format!("{:016x}", internals::fnv1a(self.report().bytes()))
+    }
+
+    /// The report without the identifier.
+    fn report(&self) -> String {
+        use std::fmt::Write;
+        let mut r = String::new();
+        match (self.bash.as_ref(), self.bash_version.as_ref()) {
+            (Some(bash), Some(version)) => writeln!(r, "bash {} {}", bash.display(), version),
+            (Some(bash), None) => writeln!(r, "bash {} (unknown version)", bash.display()),
+            (None, _) => writeln!(r, "bash not found"),
+        }
+        .unwrap();
+        writeln!(r, "os {}", self.os).unwrap();
+        if let Some(umask) = self.umask {
+            writeln!(r, "umask {:04o}", umask).unwrap();
+        }
+        for (k, v) in self.vars.iter() {
+            writeln!(r, "{}={}", k, internals::shell_quote(v)).unwrap();
+        }
+        r
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "fingerprint {}", self.id())?;
+        f.write_str(self.report().trim_end())
+    }
+}
+
+/// The umask of this process; it can only be read by setting it, which
+/// would race with other threads, so it is only known where `/proc` shows
+/// it, e.g. on Linux.
+fn umask() -> Option<u32> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find_map(|l| l.strip_prefix("Umask:"))?;
+    u32::from_str_radix(line.trim(), 8).ok()
+}
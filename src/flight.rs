@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// The outcome of an execution, in a form which can be shared.
+type Outcome = Result<Vec<u8>, (std::io::ErrorKind, String)>;
+
+/// An execution which other callers may wait for.
+#[derive(Default)]
+struct Flight {
+    outcome: Mutex<Option<Outcome>>,
+    done: Condvar,
+}
+
+static IN_FLIGHT: Mutex<Option<HashMap<String, Arc<Flight>>>> = Mutex::new(None);
+
+/// A key identifying executions which would be identical; it is compared in
+/// full, so that different executions are never merged.
+pub(crate) fn key<T: std::fmt::Debug>(value: &T) -> String {
+    format!("{:?}", value)
+}
+
+/// Run `execute`, unless an execution with the same `key` is already in
+/// progress, in which case wait for it and return its outcome instead.
+pub(crate) fn share<F>(key: String, execute: F) -> Result<Vec<u8>, std::io::Error>
+where
+    F: FnOnce() -> Result<Vec<u8>, std::io::Error>,
+{
+    let (flight, leader) = {
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        let in_flight = in_flight.get_or_insert_with(HashMap::new);
+        match in_flight.get(&key) {
+            Some(flight) => (flight.clone(), false),
+            None => {
+                let flight = Arc::new(Flight::default());
+                in_flight.insert(key.clone(), flight.clone());
+                (flight, true)
+            }
+        }
+    };
+    if !leader {
+        let mut outcome = flight.outcome.lock().unwrap();
+        while outcome.is_none() {
+            outcome = flight.done.wait(outcome).unwrap();
+        }
+        let outcome = outcome.clone().expect("outcome set");
+        return outcome.map_err(|(kind, msg)| std::io::Error::new(kind, msg));
+    }
+    let mut publish = Publish {
+        key,
+        flight: &flight,
+        outcome: None,
+    };
+    let result = execute();
+    publish.outcome = Some(match result.as_ref() {
+        Ok(output) => Ok(output.clone()),
+        Err(e) => Err((e.kind(), e.to_string())),
+    });
+    // The leader gets the original error, which may carry more detail
+    result
+}
+
+/// Publishes the outcome of an execution to those waiting for it when
+/// dropped, even if the execution panicked.
+struct Publish<'a> {
+    key: String,
+    flight: &'a Flight,
+    outcome: Option<Outcome>,
+}
+
+impl Drop for Publish<'_> {
+    fn drop(&mut self) {
+        if let Some(in_flight) = IN_FLIGHT.lock().unwrap().as_mut() {
+            in_flight.remove(&self.key);
+        }
+        let outcome = self.outcome.take().unwrap_or_else(|| {
+            Err((
+                std::io::ErrorKind::Other,
+                "shared execution of bash script panicked".to_string(),
+            ))
+        });
+        *self.flight.outcome.lock().unwrap() = Some(outcome);
+        self.flight.done.notify_all();
+    }
+}
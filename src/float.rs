@@ -0,0 +1,54 @@
+use crate::shell_arg::{ShellArg, ToShellArg};
+use std::fmt;
+
+/// A floating point binding with explicit formatting.
+///
+/// Floats are always formatted with `.` as the decimal separator and without
+/// an exponent, regardless of locale; note however that tools such as
+/// `printf` in the script parse them according to `LC_NUMERIC`, which may
+/// need to be set to `C`.  Plain `f32` and `f64` bindings use the shortest
+/// representation which round-trips; use this to fix the number of digits
+/// after the decimal point instead.
+///
+/// ```
+/// use sh_inline::*;
+/// let ratio = Float::new(2.0 / 3.0).precision(3);
+/// bash!(r#"test "${ratio}" = 0.667 && LC_NUMERIC=C printf '%.1f' "${ratio}" >/dev/null"#, ratio)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Float {
+    value: f64,
+    precision: Option<usize>,
+}
+
+impl Float {
+    /// Wrap `value`, formatted with the shortest representation by default.
+    pub fn new<T: Into<f64>>(value: T) -> Self {
+        Float {
+            value: value.into(),
+            precision: None,
+        }
+    }
+
+    /// Format with exactly `digits` digits after the decimal point.
+    pub fn precision(mut self, digits: usize) -> Self {
+        self.precision = Some(digits);
+        self
+    }
+}
+
+impl fmt::Display for Float {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.precision {
+            Some(digits) => write!(f, "{:.*}", digits, self.value),
+            None => write!(f, "{}", self.value),
+        }
+    }
+}
+
+impl ToShellArg for Float {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg::string(self.to_string())
+    }
+}
@@ -0,0 +1,54 @@
+# Helper functions injected by the `helpers` option of sh-inline.
+
+# die <message...>: Print an error to stderr and exit unsuccessfully.
+die() {
+    echo "error: $*" 1>&2
+    exit 1
+}
+
+# log <message...>: Print a timestamped message to stderr.
+log() {
+    echo "$(date -u +%Y-%m-%dT%H:%M:%SZ) $*" 1>&2
+}
+
+# Note that local variables use a reserved prefix, as bash scoping is
+# dynamic and they would otherwise shadow those of the caller.
+
+# retry <attempts> <delay> <command...>: Run a command until it succeeds,
+# sleeping <delay> seconds between at most <attempts> tries.
+retry() {
+    local __sh_inline_attempts=$1 __sh_inline_delay=$2 __sh_inline_n=1
+    shift 2
+    until "$@"; do
+        if [ "${__sh_inline_n}" -ge "${__sh_inline_attempts}" ]; then
+            echo "error: failed after ${__sh_inline_n} attempts: $*" 1>&2
+            return 1
+        fi
+        __sh_inline_n=$((__sh_inline_n + 1))
+        sleep "${__sh_inline_delay}"
+    done
+}
+
+# require_cmd <command...>: Exit unless all the given commands are available.
+require_cmd() {
+    local __sh_inline_cmd
+    for __sh_inline_cmd in "$@"; do
+        command -v "${__sh_inline_cmd}" >/dev/null ||
+            die "required command not found: ${__sh_inline_cmd}"
+    done
+}
+
+# tmpfile_tracked <variable>: Create a temporary file, storing its path in
+# the named variable; it is removed when the script exits.
+__sh_inline_tracked=()
+__sh_inline_cleanup() {
+    if [ "${#__sh_inline_tracked[@]}" -gt 0 ]; then
+        rm -rf -- "${__sh_inline_tracked[@]}"
+    fi
+}
+tmpfile_tracked() {
+    local __sh_inline_path
+    __sh_inline_path=$(mktemp)
+    __sh_inline_tracked+=("${__sh_inline_path}")
+    printf -v "$1" '%s' "${__sh_inline_path}"
+}
@@ -0,0 +1,26 @@
+use crate::internals::CommandArg;
+use crate::shell_arg::{ShellArg, ToShellArg};
+
+/// A binding for text such as a configuration file, which is embedded in the
+/// script verbatim as a quoted heredoc rather than escaped, keeping it
+/// readable in plans and traces.  A delimiter which doesn't collide with the
+/// text is chosen automatically, and the value is preserved exactly,
+/// including trailing newlines.
+///
+/// ```
+/// use sh_inline::*;
+/// let dir = tempfile::tempdir()?;
+/// let config = Heredoc("[server]\nport = 8080\nname = '$HOSTNAME'\n");
+/// let path = dir.path().join("server.conf");
+/// bash!(r#"printf '%s' "${config}" > "${path}""#, config, path)?;
+/// assert_eq!(std::fs::read_to_string(&path)?, config.0);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heredoc<T: AsRef<str>>(pub T);
+
+impl<T: AsRef<str>> ToShellArg for Heredoc<T> {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg(CommandArg::Heredoc(self.0.as_ref().to_string()))
+    }
+}
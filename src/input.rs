@@ -0,0 +1,96 @@
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::os::unix::io::OwnedFd;
+use std::process::{ChildStderr, ChildStdin, ChildStdout, Stdio};
+
+/// Conversion of a value into the standard input of a script, as used by the
+/// `stdin` option of the macros and [`Script::stdin`](struct.Script.html#method.stdin).
+///
+/// Handles such as a `File`, `OwnedFd` or `Stdio` are connected directly.
+/// Data, i.e. byte slices and strings, and the contents of anything
+/// implementing `Read`, passed as `&mut reader`, are first written into an
+/// anonymous temporary file, so that writing can't block on a script which
+/// doesn't read its input; a reader which never ends can't be used this way.
+///
+/// ```
+/// use sh_inline::*;
+/// assert_eq!(bash_output!(stdin = "a\nb\n", r"wc -l")?.trim(), "2");
+/// let mut reader = std::io::Cursor::new(b"hello".to_vec());
+/// assert_eq!(bash_output!(stdin = &mut reader, r"cat")?, "hello");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub trait ToStdin {
+    /// The standard input for a script.
+    fn to_stdin(self) -> Result<Stdio, std::io::Error>;
+}
+
+/// Copy `reader` into an anonymous temporary file, rewound to the start.
+fn buffered<R: Read + ?Sized>(reader: &mut R) -> Result<Stdio, std::io::Error> {
+    let mut file = tempfile::tempfile()?;
+    std::io::copy(reader, &mut file)?;
+    file.flush()?;
+    file.seek(std::io::SeekFrom::Start(0))?;
+    Ok(file.into())
+}
+
+macro_rules! handle_stdin {
+    ($($t:ty),*) => {
+        $(
+            impl ToStdin for $t {
+                fn to_stdin(self) -> Result<Stdio, std::io::Error> {
+                    Ok(self.into())
+                }
+            }
+        )*
+    };
+}
+
+handle_stdin!(Stdio, File, OwnedFd, ChildStdin, ChildStdout, ChildStderr);
+
+impl ToStdin for &[u8] {
+    fn to_stdin(mut self) -> Result<Stdio, std::io::Error> {
+        buffered(&mut self)
+    }
+}
+
+impl<const N: usize> ToStdin for &[u8; N] {
+    fn to_stdin(self) -> Result<Stdio, std::io::Error> {
+        self.as_slice().to_stdin()
+    }
+}
+
+impl ToStdin for &Vec<u8> {
+    fn to_stdin(self) -> Result<Stdio, std::io::Error> {
+        self.as_slice().to_stdin()
+    }
+}
+
+impl ToStdin for Vec<u8> {
+    fn to_stdin(self) -> Result<Stdio, std::io::Error> {
+        self.as_slice().to_stdin()
+    }
+}
+
+impl ToStdin for &str {
+    fn to_stdin(self) -> Result<Stdio, std::io::Error> {
+        self.as_bytes().to_stdin()
+    }
+}
+
+impl ToStdin for &String {
+    fn to_stdin(self) -> Result<Stdio, std::io::Error> {
+        self.as_bytes().to_stdin()
+    }
+}
+
+impl ToStdin for String {
+    fn to_stdin(self) -> Result<Stdio, std::io::Error> {
+        self.as_bytes().to_stdin()
+    }
+}
+
+impl<R: Read + ?Sized> ToStdin for &mut R {
+    fn to_stdin(self) -> Result<Stdio, std::io::Error> {
+        buffered(self)
+    }
+}
@@ -1,8 +1,17 @@
+use std::ffi::{OsStr, OsString};
 use std::fmt;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
 use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::config::Config;
+use crate::quoting::{Bash, QuotingProfile};
+pub use crate::script::Script;
+use crate::shell_arg::ToShellArg;
+pub use crate::spawn::spawn;
 
 /// A parsed argument that will be provided to a `Command` object.
 /// An implementation detail of the macros.
@@ -10,157 +19,217 @@ use std::process::Command;
 pub enum CommandArg {
     Empty,
     Literal(String),
-    Raw(String),
-    List(Vec<String>),
+    /// Arbitrary bytes, e.g. bound as `$'...'` in bash
+    Bytes(Vec<u8>),
+    /// Bound as a bash array, e.g. `name=('a' 'b c')`
+    Array(Vec<String>),
+    /// Bound as a bash associative array, e.g. `name=(['k']='v')`
+    Assoc(Vec<(String, String)>),
+    Heredoc(String),
+    Path(PathBuf),
+    /// A duplicate of a descriptor, passed to the script as `/dev/fd/N`
+    Fd(std::io::Result<OwnedFd>),
+    /// Passed to the script in an environment variable, never rendered
+    Secret(crate::secret::Secret),
+    /// Bound as its path
+    TempFile(crate::tempfiles::TempFile),
 }
 
-fn shell_quote(value: &str) -> String {
-    shlex::quote(value).to_string()
+/// Search `PATH` for an executable named `name`.
+pub(crate) fn find_in_path(name: &str) -> Option<PathBuf> {
+    find_in(&std::env::var_os("PATH")?, name)
 }
 
-// https://wiki.bash-hackers.org/syntax/quoting#ansi_c_like_strings
-fn bash_binary_quote(value: &[u8]) -> String {
-    let mut r = Vec::new();
-    r.extend(b"$'".iter());
-    r.extend(value.iter().flat_map(|&c| std::ascii::escape_default(c)));
-    r.extend(b"'".iter());
-    String::from_utf8(r).expect("bash_binary quote should have output utf8")
+/// The program `name` for wrapping scripts, resolved in the `PATH` of this
+/// process, since that of the script may differ, or `name` itself if it
+/// isn't found there.
+pub(crate) fn wrapper_program(name: &str) -> OsString {
+    find_in_path(name).map_or_else(|| name.into(), Into::into)
 }
 
-impl fmt::Display for CommandArg {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use self::CommandArg::*;
-        match *self {
-            Empty => write!(f, ""),
-            Literal(ref value) => write!(f, "{}", shell_quote(value)),
-            Raw(ref value) => write!(f, "{}", value),
-            List(ref list) => write!(
-                f,
-                "{}",
-                list.iter()
-                    .map(|x| shell_quote(x))
-                    .collect::<Vec<_>>()
-                    .join(" ")
-            ),
-        }
-    }
+/// Search the directories listed in `path`, as in `PATH`, for an
+/// executable named `name`.
+pub(crate) fn find_in(path: &OsStr, name: &str) -> Option<PathBuf> {
+    std::env::split_paths(path)
+        .map(|dir| dir.join(name))
+        .find(|p| is_executable(p))
 }
 
-impl<'a, 'b> From<&'a &'b str> for CommandArg {
-    fn from(value: &&str) -> Self {
-        CommandArg::Literal(value.to_string())
-    }
+/// Whether `path` is an executable file.
+pub(crate) fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
 }
 
-impl From<String> for CommandArg {
-    fn from(value: String) -> Self {
-        CommandArg::Literal(value)
-    }
-}
+static BASH: OnceLock<Option<PathBuf>> = OnceLock::new();
 
-impl<'a> From<&'a String> for CommandArg {
-    fn from(value: &String) -> Self {
-        CommandArg::Literal(value.to_string())
-    }
-}
+/// Locations checked for bash if it is not in `PATH`, covering systems
+/// without the usual filesystem layout such as NixOS and Homebrew on macOS.
+pub(crate) const DEFAULT_BASH_CANDIDATES: &[&str] = &[
+    "/bin/bash",
+    "/usr/bin/bash",
+    "/usr/local/bin/bash",
+    "/run/current-system/sw/bin/bash",
+    "/opt/homebrew/bin/bash",
+];
 
-impl<'a> From<&'a str> for CommandArg {
-    fn from(value: &str) -> Self {
-        CommandArg::Literal(value.to_string())
+/// The bash executable to use: the one configured via `Config::bash`, or else
+/// the one named by the `SH_INLINE_BASH` environment variable, or else
+/// `bash` as found in `PATH` when first needed (as `/usr/bin/env bash` would),
+/// or else the first of the candidate locations which exists.
+pub(crate) fn bash_path(config: &Config) -> Result<PathBuf, std::io::Error> {
+    if let Some(path) = config.bash.as_ref() {
+        return Ok(path.clone());
     }
-}
-
-impl<'a> From<&'a Path> for CommandArg {
-    fn from(value: &Path) -> Self {
-        use std::os::unix::ffi::OsStrExt;
-        if let Some(s) = value.to_str() {
-            CommandArg::Literal(s.to_string())
-        } else {
-            CommandArg::Raw(bash_binary_quote(value.as_os_str().as_bytes()))
-        }
+    if let Some(path) = std::env::var_os("SH_INLINE_BASH").filter(|p| !p.is_empty()) {
+        return Ok(path.into());
     }
-}
-
-impl<'a> From<&'a PathBuf> for CommandArg {
-    fn from(value: &PathBuf) -> Self {
-        value.as_path().into()
+    if let Some(path) = BASH.get_or_init(|| find_in_path("bash")) {
+        return Ok(path.clone());
     }
+    let candidates: Vec<PathBuf> = match config.bash_candidates.as_ref() {
+        Some(candidates) => candidates.clone(),
+        None => DEFAULT_BASH_CANDIDATES.iter().map(PathBuf::from).collect(),
+    };
+    if let Some(path) = candidates.iter().find(|p| p.is_file()) {
+        return Ok(path.clone());
+    }
+    let mut tried = vec![format!(
+        "PATH={}",
+        std::env::var_os("PATH")
+            .unwrap_or_default()
+            .to_string_lossy()
+    )];
+    tried.extend(candidates.iter().map(|p| p.display().to_string()));
+    Err(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("bash not found; tried {}", tried.join(", ")),
+    ))
 }
 
-impl<'a, 'b> From<&'a &'b Path> for CommandArg {
-    fn from(value: &&Path) -> Self {
-        CommandArg::from(*value)
+/// Convert an error from `nix` into a `std::io::Error`.
+pub(crate) fn nix_error(e: nix::Error) -> std::io::Error {
+    match e.as_errno() {
+        Some(errno) => std::io::Error::from_raw_os_error(errno as i32),
+        None => std::io::Error::new(std::io::ErrorKind::Other, e),
     }
 }
 
-impl<'a> From<&'a u64> for CommandArg {
-    fn from(value: &u64) -> Self {
-        CommandArg::Literal(value.to_string())
+/// Create a pipe, as the `(reader, writer)` ends, which aren't inherited by
+/// the scripts run.
+pub(crate) fn pipe() -> Result<(std::fs::File, std::fs::File), std::io::Error> {
+    use std::os::unix::io::FromRawFd;
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    let (reader, writer) = nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC).map_err(nix_error)?;
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+    let (reader, writer) = nix::unistd::pipe().map_err(nix_error)?;
+    // SAFETY: The descriptors were just created and nothing else owns them
+    let ends = unsafe {
+        (
+            std::fs::File::from_raw_fd(reader),
+            std::fs::File::from_raw_fd(writer),
+        )
+    };
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+    for fd in [reader, writer] {
+        use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).map_err(nix_error)?;
     }
+    Ok(ends)
 }
 
-impl<'a> From<&'a f64> for CommandArg {
-    fn from(value: &f64) -> Self {
-        CommandArg::Literal(value.to_string())
-    }
+/// Send `signal` to the process group led by `pid`, ignoring errors such as
+/// the group having no members left.
+pub(crate) fn signal_group(pid: u32, signal: nix::sys::signal::Signal) {
+    let _ = nix::sys::signal::killpg(nix::unistd::Pid::from_raw(pid as i32), signal);
 }
 
-impl<'a> From<&'a i32> for CommandArg {
-    fn from(value: &i32) -> Self {
-        CommandArg::Literal(value.to_string())
-    }
+pub(crate) fn shell_quote(value: &str) -> String {
+    Bash.quote(value)
 }
 
-impl<'a> From<&'a i64> for CommandArg {
-    fn from(value: &i64) -> Self {
-        CommandArg::Literal(value.to_string())
+/// A heredoc delimiter which doesn't appear as a line of `value`.
+fn heredoc_delimiter(value: &str) -> String {
+    let mut delimiter = "SH_INLINE_EOF".to_string();
+    let mut n = 0;
+    while value.lines().any(|l| l == delimiter) {
+        n += 1;
+        delimiter = format!("SH_INLINE_EOF_{}", n);
     }
+    delimiter
 }
 
-impl<'a, T> From<&'a [T]> for CommandArg
-where
-    T: fmt::Display,
-{
-    fn from(list: &[T]) -> Self {
-        CommandArg::List(list.iter().map(|x| format!("{}", x)).collect())
+impl CommandArg {
+    /// Render the value for the shell of `profile`, or return `None` if it
+    /// can't be expressed there.  `Heredoc` values are rendered as sh
+    /// here-documents, ending in a line `.` which the script strips, so that
+    /// trailing newlines are kept.  `Secret` values are never rendered.
+    pub(crate) fn render(&self, profile: &dyn QuotingProfile) -> Option<String> {
+        use self::CommandArg::*;
+        match self {
+            Empty => Some(profile.quote("")),
+            Literal(value) => Some(profile.quote(value)),
+            Bytes(value) => profile.quote_bytes(value),
+            Path(value) => render_path(profile, value),
+            TempFile(file) => render_path(profile, file.path()),
+            Fd(Ok(fd)) => Some(format!("/dev/fd/{}", fd.as_raw_fd())),
+            Fd(Err(_)) => Some("/dev/null".to_string()),
+            Heredoc(value) => {
+                let delimiter = heredoc_delimiter(value);
+                Some(format!(
+                    "$(cat <<'{}'\n{}\n{}\necho .)",
+                    delimiter, value, delimiter
+                ))
+            }
+            Array(list) => profile.quote_list(list),
+            Assoc(map) => profile.quote_map(map),
+            Secret(_) => None,
+        }
     }
 }
 
-impl<'a, T> From<&'a Vec<T>> for CommandArg
-where
-    T: fmt::Display,
-{
-    fn from(list: &Vec<T>) -> Self {
-        CommandArg::from(list.as_slice())
+fn render_path(profile: &dyn QuotingProfile, path: &Path) -> Option<String> {
+    match path.to_str() {
+        Some(s) => Some(profile.quote(s)),
+        None => profile.quote_bytes(path.as_os_str().as_bytes()),
     }
 }
 
-impl<'a, T> From<&'a Option<T>> for CommandArg
-where
-    T: fmt::Display,
-{
-    fn from(opt: &Option<T>) -> Self {
-        if let Some(ref value) = *opt {
-            CommandArg::Literal(format!("{}", value))
-        } else {
-            CommandArg::Empty
-        }
+impl fmt::Display for CommandArg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rendered = self
+            .render(&Bash)
+            .expect("bash can express any value but secrets");
+        f.write_str(&rendered)
     }
 }
 
 /// Create a [`CommandArg`]; implementation detail of the macros.
 #[doc(hidden)]
-pub fn command_arg<'a, T>(value: &'a T) -> CommandArg
-where
-    CommandArg: std::convert::From<&'a T>,
-{
-    CommandArg::from(value)
+pub fn command_arg<T: ToShellArg + ?Sized>(value: &T) -> CommandArg {
+    value.to_shell_arg().0
 }
 
-fn impl_render(script: &str, args: String) -> Result<Command, std::io::Error> {
+/// A stable (FNV-1a) hash of `bytes`, e.g. to name files after contents.
+pub(crate) fn fnv1a<I: IntoIterator<Item = u8>>(bytes: I) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325u64, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Bind `value` as a string if it is valid UTF-8, or as bytes otherwise.
+pub(crate) fn os_str_arg(value: &OsStr) -> CommandArg {
+    match value.to_str() {
+        Some(s) => CommandArg::Literal(s.to_string()),
+        None => CommandArg::Bytes(value.as_bytes().to_vec()),
+    }
+}
+
+fn impl_render(c: &mut Command, script: &str, args: String) -> Result<(), std::io::Error> {
     use std::io::Seek;
     use std::io::Write;
-    let mut c = Command::new("bash");
     let mut tmpf = tempfile::tempfile()?;
     tmpf.write_all(args.as_bytes())?;
     tmpf.write_all(script.as_bytes())?;
@@ -175,25 +244,89 @@ fn impl_render(script: &str, args: String) -> Result<Command, std::io::Error> {
             Ok(())
         });
     }
-    Ok(c)
+    Ok(())
+}
+
+/// Set up `cmd` to execute the fully rendered `script` from a descriptor
+/// passed as `/dev/fd/N`, leaving standard input free for the caller.  Where
+/// `/dev/fd` only covers the standard streams (e.g. FreeBSD without
+/// `fdescfs`), a named temporary file is used instead.
+pub(crate) fn render_into_fd(cmd: &mut Command, script: &str) -> Result<(), std::io::Error> {
+    use std::io::{Seek, Write};
+    let tmpf = crate::tempfiles::file("script")?;
+    let mut file = tmpf.as_file();
+    file.write_all(script.as_bytes())?;
+    file.seek(std::io::SeekFrom::Start(0))?;
+    if Path::new(&format!("/dev/fd/{}", file.as_raw_fd())).exists() {
+        // The name isn't needed, so the file is removed right away
+        let fd = pass_fd(cmd, file.try_clone()?.into());
+        cmd.arg(format!("/dev/fd/{}", fd));
+    } else {
+        cmd.arg(tmpf.path());
+        keep_alive(cmd, tmpf);
+    }
+    Ok(())
+}
+
+/// Tie the lifetime of `value` (e.g. a temporary directory) to `cmd`.
+pub(crate) fn keep_alive<T: Send + Sync + 'static>(cmd: &mut Command, value: T) {
+    // SAFETY: The closure does nothing in the child
+    unsafe {
+        cmd.pre_exec(move || {
+            let _ = &value;
+            Ok(())
+        });
+    }
+}
+
+/// Make `fd` available to the script under the same number, returning it;
+/// the descriptor is closed in the parent along with `cmd`.
+pub(crate) fn pass_fd(cmd: &mut Command, fd: OwnedFd) -> RawFd {
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+    let raw = fd.as_raw_fd();
+    // SAFETY: fcntl() is async-signal-safe
+    unsafe {
+        cmd.pre_exec(move || {
+            fcntl(fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::empty())).map_err(nix_error)?;
+            Ok(())
+        });
+    }
+    raw
 }
 
 /// Create a [`CommandArg`]; implementation detail of the macros.
 #[doc(hidden)]
 pub fn render<S: AsRef<str>>(script: S, args: String) -> Result<Command, std::io::Error> {
-    impl_render(script.as_ref(), args)
+    let bash = bash_path(&Config::current()).unwrap_or_else(|_| "bash".into());
+    let mut c = Command::new(bash);
+    impl_render(&mut c, script.as_ref(), args)?;
+    Ok(c)
+}
+
+/// Set up `cmd` to execute the fully rendered `script`.
+pub(crate) fn render_into(cmd: &mut Command, script: &str) -> Result<(), std::io::Error> {
+    impl_render(cmd, script, String::new())
 }
 
 /// Execute a [`Command`] object.  Only intended
 ///
 /// [`Command`]: https://doc.rust-lang.org/std/process/struct.Command.html
 pub fn execute(mut cmd: Command) -> Result<(), std::io::Error> {
-    let r = cmd.status()?;
-    if !r.success() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("bash script failed: {}", r),
-        ));
+    check_status(cmd.status()?, None)
+}
+
+/// How a script is referred to in messages, given its name if any.
+pub(crate) fn describe(name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("bash script {:?}", name),
+        None => "bash script".to_string(),
     }
-    Ok(())
+}
+
+/// Return an error if `status` is unsuccessful.
+pub(crate) fn check_status(
+    status: std::process::ExitStatus,
+    name: Option<&str>,
+) -> Result<(), std::io::Error> {
+    crate::error::Error::check(status, name, None, None, &[], 0)
 }
@@ -28,19 +28,259 @@
 //! foo="variable with spaces"
 //! test ${foo} = 'variable with spaces'
 //! ```
+//!
+//! Slices, arrays and `Vec`s are bound as bash arrays, so each element stays a
+//! single word when expanded as `"${name[@]}"`:
+//!
+//! ```
+//! use sh_inline::*;
+//! let files = vec!["a b", "c"];
+//! assert_eq!(bash_output!(r#"printf '<%s>' "${files[@]}""#, files)?, "<a b><c>");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Similarly, `HashMap`s and `BTreeMap`s are bound as associative arrays:
+//!
+//! ```
+//! use sh_inline::*;
+//! let mut config = std::collections::BTreeMap::new();
+//! config.insert("user name", "O'Brien");
+//! assert_eq!(bash_output!(r#"echo "${config["user name"]}""#, config)?, "O'Brien\n");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Other types can be bound by implementing [`ToShellArg`].  Values are
+//! quoted according to a [`QuotingProfile`] for the target shell.
+//!
+//! [`ToShellArg`]: trait.ToShellArg.html
+//! [`QuotingProfile`]: quoting/trait.QuotingProfile.html
 
+mod artifacts;
+mod bytes;
+pub mod capture;
+mod config;
+pub mod decode;
+mod env;
+mod error;
+mod eval;
+mod fanout;
+mod fast;
+mod fingerprint;
+mod flight;
+mod float;
+mod heredoc;
+mod input;
 #[doc(hidden)]
 pub mod internals;
+mod limits;
+mod output;
+mod outputs;
+mod plan;
+mod policy;
+mod programs;
+pub mod quoting;
+mod recording;
+mod repl;
+mod requires;
+mod resolve;
+mod retry;
+mod script;
+mod seatbelt;
+mod secret;
+mod session;
+mod shell_arg;
+mod spawn;
+mod steps;
+mod strict;
+mod systemd;
+mod tempfiles;
+mod template;
+#[cfg(any(feature = "tera", feature = "askama"))]
+pub mod templates;
+mod timeout;
+mod transaction;
+mod version;
+
+pub use bytes::Bytes;
+pub use config::{Config, Glob};
+pub use env::EnvDiff;
+pub use error::Error;
+pub use eval::EvalError;
+pub use fanout::{fan_out, Distribution, FanOut};
+pub use fingerprint::Fingerprint;
+pub use float::Float;
+pub use heredoc::Heredoc;
+pub use input::ToStdin;
+pub use limits::{Limits, Resource};
+pub use output::BashOutput;
+pub use outputs::OutputCommands;
+pub use plan::Plan;
+pub use policy::{Policy, PolicyInput};
+pub use programs::{AwkProgram, SedExpr};
+pub use quoting::QuotingProfile;
+pub use recording::Recording;
+pub use repl::Repl;
+pub use resolve::resolve_tool;
+pub use retry::Retry;
+pub use script::{Rebase, Scratch, Script};
+pub use seatbelt::Seatbelt;
+pub use secret::{secret, Secret};
+pub use session::{sessions, terminate_session, BashSession, SessionInfo};
+pub use shell_arg::{ShellArg, ToShellArg};
+pub use spawn::{reap_finished, BashChild, Lines, Tail};
+pub use steps::Step;
+pub use strict::StrictMode;
+pub use systemd::SystemdScope;
+pub use tempfiles::{sweep_temp_files, temp_files, tempfile, TempFile};
+pub use timeout::TimeoutMethod;
+pub use transaction::{Transaction, TransactionError};
+pub use version::{BashFeature, BashVersion};
+
+#[cfg(feature = "macros")]
+pub use sh_inline_macros::{bash_checked, bash_fmt};
 
 /// Create a [`Command`] object that will execute a fragment of (Bash) shell script
 /// in "strict mode", i.e. with `set -euo pipefail`.  The first argument is the
 /// script, and additional arguments should be Rust variable identifiers.  The
 /// provided Rust variables will become shell script variables with their values
-/// quoted.
+/// quoted.  Arbitrary expressions can be bound with `name = expr`, such as
+/// `dir = self.tmpdir.path()`.
+///
+/// Binding an open `File` or `OwnedFd`, such as a directory, passes a
+/// duplicate of the descriptor to the script and binds its `/dev/fd/N` path.
+/// Scripts can then `cd "${dir}"` or use options such as `tar -C "${dir}"`
+/// against the directory which was opened, even if its path has since been
+/// replaced.
 ///
 /// This macro will allocate a temporary file for the script; this can (in very
 /// unusual cases such as file descriptior exhaustion) fail.
 ///
+/// Options may be given before the script as `key = value` pairs:
+///
+/// - `daemonize = bool`: Detach the script via a double fork and `setsid()`,
+///   running it from `/` with its output sent to `/dev/null`.
+/// - `parent_death_signal = i32`: Signal delivered to the script when the
+///   thread which spawned it exits (Linux and FreeBSD only).
+/// - `jail = i32`: Attach the script to a FreeBSD jail by its identifier
+///   (FreeBSD only).
+/// - `private_tmp = bool`: Point `TMPDIR` at a private directory which is
+///   removed afterwards.
+/// - `isolated_home = bool`: Point `HOME` and the XDG base directories at a
+///   private directory which is removed afterwards.
+/// - `rebase_paths = Rebase`: Make relative `Path` bindings absolute against
+///   an explicit directory, or canonicalize them; see [`Rebase`](./enum.Rebase.html).
+/// - `scratch = Scratch`: Provide a tmpfs scratch directory as `${scratch}`;
+///   see [`Scratch`](./enum.Scratch.html).
+/// - `helpers = bool`: Inject a library of helper functions (`die`, `log`,
+///   `retry`, `require_cmd` and `tmpfile_tracked`) into the prelude.
+/// - `strict = StrictMode`: Relax or extend strict mode, e.g. to allow unset
+///   variables or trace the script; see [`StrictMode`](./struct.StrictMode.html).
+/// - `shopt = ["inherit_errexit", ...]`: Enable additional bash options after
+///   strict mode; see also [`Config`](./struct.Config.html) for global defaults.
+/// - `safe_ifs = bool`: Set `IFS=$'\n\t'` so words are not split on spaces.
+/// - `glob = Glob`: Make unmatched globs an error or disable globbing; see
+///   [`Glob`](./enum.Glob.html).
+/// - `xtrace = fd`: Trace the script (`set -x`) to a file or pipe instead of
+///   standard error, via `BASH_XTRACEFD`.
+/// - `systemd_scope = SystemdScope`: Apply resource limits by running inside
+///   a transient systemd scope; see [`SystemdScope`](./struct.SystemdScope.html).
+/// - `seatbelt = Seatbelt`: Restrict filesystem and network access via
+///   `sandbox-exec` (macOS only); see [`Seatbelt`](./struct.Seatbelt.html).
+/// - `timeout = Duration`: Terminate the script and the commands it started
+///   if it runs longer, returning an error of kind `TimedOut` wrapping
+///   [`Error::TimedOut`](./enum.Error.html#variant.TimedOut).
+///   `timeout_grace = Duration` sets the time between `SIGTERM` and
+///   `SIGKILL`, and `timeout_method = TimeoutMethod` selects how it is
+///   enforced; see [`TimeoutMethod`](./enum.TimeoutMethod.html).
+/// - `deadline = Instant`: Like `timeout`, but with an absolute deadline which
+///   can be shared by several scripts run in sequence.
+/// - `approve = |plan: &Plan| -> bool`: Callback deciding whether the script
+///   may be executed, given its [`Plan`](./struct.Plan.html).
+/// - `name = &str`: A stable name for the script, used in error messages,
+///   policies and plans.
+/// - `on_stderr = |stderr: &str| -> Option<io::Error>`: Convert the standard
+///   error of a failed script into a more specific error.
+/// - `single_flight = bool`: Share one execution between concurrent
+///   invocations of the identical script.
+/// - `policy = Policy`: Rules restricting which scripts may be executed; see
+///   [`Policy`](./struct.Policy.html).
+/// - `prefix = &str`: Bind variables with a prefix to avoid clobbering
+///   environment variables, rewriting references to them in the script.
+/// - `dedent = bool`: Strip indentation common to all lines of the script.
+/// - `template = bool`: Expand `{{#if name}}`/`{{else}}`/`{{/if}}` and
+///   `{{#each name}}`/`{{/each}}` blocks using the bound values, with
+///   `{{this}}` and `{{@index}}` referring to the current element.
+/// - `stdin = Stdio`, `stdout = Stdio`, `stderr = Stdio`: Redirect the standard
+///   streams of the script to anything convertible into a `Stdio`, such as a
+///   `File` or `OwnedFd`.  `stdin` also accepts data to feed to the script:
+///   a `&str`, `&[u8]` or `&mut impl Read`; see [`ToStdin`](./trait.ToStdin.html).
+/// - `bash = Path`: Execute the script with a specific bash executable; see
+///   also [`bash_with`](./macro.bash_with.html).  By default, the bash set
+///   with [`Config::bash`](./struct.Config.html#method.bash) or the
+///   `SH_INLINE_BASH` environment variable is used, or else the one in `PATH`.
+/// - `shell = &str`: Execute the script with an interpreter given as in a
+///   shebang line, such as `"/usr/bin/env bash"`, looked up when it runs.
+/// - `bash_features = [BashFeature, ...]`: Fail early with a clear error if
+///   the installed bash lacks features used by the script; see
+///   [`BashVersion`](./struct.BashVersion.html).
+/// - `requires = ["jq", "rsync >= 3.2", ...]`: Check that the commands the
+///   script needs are installed, in versions which satisfy the constraints,
+///   failing with one error listing everything missing before it runs.
+/// - `fast_path = bool`: Execute a script consisting of a single simple
+///   command directly without bash, where this doesn't change its meaning.
+/// - `cwd = AsRef<Path>`: Run the script in this directory.
+/// - `env = { NAME: value, ... }`: Set environment variables, which unlike
+///   bindings are inherited by the commands the script runs.  Values may be
+///   anything implementing `AsRef<OsStr>`; a map or other iterator of pairs
+///   can be given instead of the braces.
+/// - `env_keep = [PATH, HOME, ...]`: Clear the environment except for the
+///   listed variables.  Names may be given as identifiers or strings.
+/// - `prelude = &str`, `epilogue = &str`: Lines to run before the script
+///   (after strict mode) and when it exits (via `trap ... EXIT`); may be
+///   repeated.
+/// - `on_exit = &str`: A cleanup snippet run when the script exits, even if
+///   it fails; repeated cleanups are combined into one `trap` and run in
+///   reverse order.
+/// - `recording = Recording`: Record the execution, or replay it without
+///   running anything; see [`Recording`](./enum.Recording.html).
+/// - `trim = bool`: Trim surrounding whitespace from the output returned by
+///   [`bash_output`](./macro.bash_output.html).
+/// - `reproducible = bool`: Run with a sanitized environment, `LC_ALL=C`,
+///   `TZ=UTC`, umask `022` and `--norc --noprofile`, for identical behavior
+///   across machines.
+/// - `stderr_tail = usize`: Capture up to this many bytes from the end of
+///   standard error, still showing it as usual, and include them in the
+///   [`Error`](./enum.Error.html) if the script fails.
+/// - `steps = bool`, `on_step = FnMut(&str)`: Parse `::step::NAME` marker
+///   lines from standard output into [`Step`](./struct.Step.html)s, calling
+///   `on_step` as each one starts.
+/// - `outputs = OutputCommands`: Collect named values set by the script,
+///   GitHub Actions style; see [`OutputCommands`](./struct.OutputCommands.html).
+/// - `time_vars = bool`: Export `SH_INLINE_START_EPOCH` and, with a
+///   `timeout` or `deadline`, `SH_INLINE_DEADLINE_EPOCH`, so the script can
+///   check its remaining time budget.
+/// - `limits = Limits`: Apply a hardening profile of resource limits,
+///   `no_new_privs` and environment sanitization, such as one of the
+///   presets; see [`Limits`](./struct.Limits.html).
+/// - `posix = bool`: Run the script with `/bin/sh` rather than bash, as
+///   [`sh`](./macro.sh.html) does.
+/// - `powershell = bool`: Run the script with PowerShell rather than bash,
+///   as [`pwsh`](./macro.pwsh.html) does.
+/// - `artifacts = AsRef<Path>`: If the script fails, write the rendered
+///   script, its environment, its output, exit status and timings into a
+///   new subdirectory of this directory, e.g. for CI to collect.
+/// - `trace = bool`: Print the fully rendered script, with the variable
+///   bindings, to standard error before it runs, as `SH_INLINE_TRACE=1` in
+///   the environment does for all scripts.  `redact = ["name", ...]` hides
+///   the values of secret bindings.
+/// - `bind_env = bool`: Pass the values of bindings through environment
+///   variables rather than writing them into the script text, keeping them
+///   out of error messages and traces.  Values wrapped with
+///   [`secret`](./fn.secret.html) are always passed this way, and are also
+///   redacted from plans and artifacts.
+/// - `pipe_stderr = bool`: With [`bash_spawn`](./macro.bash_spawn.html),
+///   pipe standard error so that it can be read from the returned handle.
+///
 /// ```
 /// use sh_inline::*;
 /// let a = "foo";
@@ -55,16 +295,8 @@ pub mod internals;
 /// [`Command`]: https://doc.rust-lang.org/std/process/struct.Command.html
 #[macro_export]
 macro_rules! bash_command {
-    ($s:expr) => { $crate::bash_command!($s,) };
-    ($s:expr, $( $id:ident ),*) => {
-        {
-            use std::fmt::Write;
-            let mut script: String = "set -euo pipefail\n".into();
-            $(
-                write!(&mut script, "{}={}\n", stringify!($id), $crate::internals::command_arg(&$id)).unwrap();
-            )*
-            $crate::internals::render(&$s, script)
-        }
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [] $( $t )*).command()
     };
 }
 
@@ -85,8 +317,450 @@ macro_rules! bash_command {
 /// ```
 #[macro_export]
 macro_rules! bash {
-    ($s:expr) => { $crate::bash!($s,) };
-    ($s:expr, $( $id:ident ),*) => {
-        $crate::internals::execute($crate::bash_command!($s, $( $id ),*).expect("failed to create temporary script"))
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [] $( $t )*).run()
+    };
+}
+
+/// Spawn a fragment of Bash shell script as a child process, returning a
+/// [`BashChild`](./struct.BashChild.html) handle.  The standard output of the
+/// script is piped and can be inspected while it is running, for example to
+/// wait until a server is ready.  The script runs in its own process group,
+/// which is killed when the handle is dropped unless it was detached.
+///
+/// For more details on usage, see the [`bash_command`](./macro.bash_command.html) macro.
+///
+/// ```
+/// use sh_inline::*;
+/// let mut child = bash_spawn!(r"echo ready; exec sleep 30")?;
+/// child.wait_for_output("^ready$", std::time::Duration::from_secs(10))?;
+/// child.kill()?;
+/// child.wait()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[macro_export]
+macro_rules! bash_spawn {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [] $( $t )*).spawn()
+    };
+}
+
+/// Run a fragment of Bash shell script, returning an iterator over the
+/// lines of its standard output as they are printed, e.g. to show the
+/// progress of a long-running command.  Once the output ends the iterator
+/// waits for the script, yielding an error if it failed, so a loop over the
+/// lines which propagates errors also checks the exit status.
+///
+/// For more details on usage, see the [`bash_command`](./macro.bash_command.html) macro.
+///
+/// ```
+/// use sh_inline::*;
+/// let mut progress = Vec::new();
+/// for line in bash_lines!(r"for i in 1 2 3; do echo ${i}/3; done")? {
+///     progress.push(line?);
+/// }
+/// assert_eq!(progress, ["1/3", "2/3", "3/3"]);
+/// let lines: Result<Vec<String>, _> = bash_lines!(r"echo partial; exit 1")?.collect();
+/// assert!(lines.is_err());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[macro_export]
+macro_rules! bash_lines {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [] $( $t )*).lines()
+    };
+}
+
+/// Create a [`tokio::process::Command`] for a fragment of Bash shell script,
+/// like [`bash_command`](./macro.bash_command.html) (feature `tokio`).  Its
+/// `status()` and `output()` methods return futures.
+///
+/// ```
+/// # #[cfg(feature = "tokio")]
+/// # tokio::runtime::Builder::new_current_thread().enable_all().build()?.block_on(async {
+/// use sh_inline::*;
+/// let name = "world";
+/// let out = bash_command_async!(r#"echo "hello ${name}""#, name)?.output().await?;
+/// assert_eq!(out.stdout, b"hello world\n");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # })?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// [`tokio::process::Command`]: https://docs.rs/tokio/1/tokio/process/struct.Command.html
+#[cfg(feature = "tokio")]
+#[macro_export]
+macro_rules! bash_command_async {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [] $( $t )*).command_async()
+    };
+}
+
+/// Execute a fragment of Bash shell script asynchronously with tokio, like
+/// [`bash`](./macro.bash.html) (feature `tokio`).  This returns a future
+/// resolving to an error if the script exits unsuccessfully.
+///
+/// ```
+/// # #[cfg(feature = "tokio")]
+/// # tokio::runtime::Builder::new_current_thread().enable_all().build()?.block_on(async {
+/// use sh_inline::*;
+/// let dir = "/tmp";
+/// bash_async!(r#"test -d "${dir}""#, dir).await?;
+/// assert!(bash_async!(r"exit 1").await.is_err());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// # })?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[cfg(feature = "tokio")]
+#[macro_export]
+macro_rules! bash_async {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [] $( $t )*).run_async()
+    };
+}
+
+/// Execute a fragment of Bash shell script, capturing its standard output
+/// and error and timing it; the result is a
+/// [`BashOutput`](./struct.BashOutput.html).  An unsuccessful exit is not an
+/// error here: inspect `status`, or call `check()` on the result.
+///
+/// For more details on usage, see the [`bash_command`](./macro.bash_command.html) macro.
+///
+/// ```
+/// use sh_inline::*;
+/// let r = bash_run!(r"echo out; echo err >&2; exit 2")?;
+/// assert_eq!(r.status.code(), Some(2));
+/// assert_eq!(r.stdout, b"out\n");
+/// assert_eq!(r.stderr_str()?, "err\n");
+/// println!("took {:?}", r.duration);
+/// assert!(r.check().is_err());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[macro_export]
+macro_rules! bash_run {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [] $( $t )*).run_output()
+    };
+}
+
+/// Execute a fragment of Bash shell script and return its standard output as
+/// a `String`, failing like [`bash`](./macro.bash.html) if it exits
+/// unsuccessfully or with an error of kind `InvalidData` if the output is
+/// not UTF-8.  With the `trim = true` option, surrounding whitespace such as
+/// the trailing newline is removed.
+///
+/// For more details on usage, see the [`bash_command`](./macro.bash_command.html) macro.
+///
+/// ```
+/// use sh_inline::*;
+/// let name = "world";
+/// assert_eq!(bash_output!(r#"echo "hello ${name}""#, name)?, "hello world\n");
+/// assert_eq!(bash_output!(trim = true, r"echo hello")?, "hello");
+/// assert!(bash_output!(r"echo partial; false").is_err());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[macro_export]
+macro_rules! bash_output {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [] $( $t )*).output()
+    };
+}
+
+/// Execute a fragment of Bash shell script and parse its standard output,
+/// with surrounding whitespace trimmed, via `FromStr`.  The returned error
+/// type is [`EvalError`](./enum.EvalError.html), which distinguishes failure
+/// to execute the script from failure to parse its output.
+///
+/// For more details on usage, see the [`bash_command`](./macro.bash_command.html) macro.
+///
+/// ```
+/// use sh_inline::*;
+/// let n: u64 = bash_eval!(r"printf '%s' 12345 | wc -c")?;
+/// assert_eq!(n, 5);
+/// let r: Result<u64, _> = bash_eval!(r"echo not a number");
+/// assert!(matches!(r, Err(EvalError::Parse { .. })));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[macro_export]
+macro_rules! bash_eval {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [] $( $t )*).eval()
+    };
+}
+
+/// Execute a fragment of Bash shell script, returning the changes it made
+/// to its exported environment as an [`EnvDiff`](./struct.EnvDiff.html).
+/// This allows the `export`s of setup scripts to be imported into the
+/// process or passed on to later scripts.
+///
+/// For more details on usage, see the [`bash_command`](./macro.bash_command.html) macro.
+///
+/// ```
+/// use sh_inline::*;
+/// let prefix = "/opt/tool";
+/// let diff = bash_env!(r#"export TOOL_HOME=${prefix}; export PATH="${TOOL_HOME}/bin:${PATH}""#, prefix)?;
+/// assert_eq!(diff.set["TOOL_HOME"], "/opt/tool");
+/// assert!(diff.set["PATH"].to_str().unwrap().starts_with("/opt/tool/bin:"));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[macro_export]
+macro_rules! bash_env {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [] $( $t )*).env_diff()
+    };
+}
+
+/// Execute a fragment of Bash shell script with a dedicated channel for its
+/// result: whatever it writes to `"${ret}"` is returned as bytes, separately
+/// from its standard output and error, which are inherited as usual.  This
+/// avoids having to pick the result out of the output of the tools the
+/// script runs.
+///
+/// For more details on usage, see the [`bash_command`](./macro.bash_command.html) macro.
+///
+/// ```
+/// use sh_inline::*;
+/// let ret = bash_with_return_fd!(r#"echo "building..."; printf '{"ok":true}' >"${ret}""#)?;
+/// assert_eq!(ret, br#"{"ok":true}"#);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[macro_export]
+macro_rules! bash_with_return_fd {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [] $( $t )*).run_with_return_fd()
+    };
+}
+
+/// Execute a fragment of Bash shell script and apply the changes it made to
+/// its exported environment to this process, like `source` in a shell; the
+/// changes are returned as an [`EnvDiff`](./struct.EnvDiff.html).  To import
+/// only some variables, use [`bash_env`](./macro.bash_env.html) followed by
+/// [`EnvDiff::select`](./struct.EnvDiff.html#method.select) and `apply`.
+///
+/// Modifying the environment is only sound while no other threads may be
+/// accessing it; see `std::env::set_var`.
+///
+/// For more details on usage, see the [`bash_command`](./macro.bash_command.html) macro.
+///
+/// ```
+/// use sh_inline::*;
+/// bash_source!(r"export SH_INLINE_EXAMPLE=sourced")?;
+/// assert_eq!(std::env::var("SH_INLINE_EXAMPLE").unwrap(), "sourced");
+/// bash_env!(r"export A=1 B=2")?.select(&["B"]).apply();
+/// assert!(std::env::var_os("A").is_none());
+/// assert_eq!(std::env::var("B").unwrap(), "2");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[macro_export]
+macro_rules! bash_source {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [] $( $t )*).source()
+    };
+}
+
+/// Execute a fragment of Bash shell script like [`bash`](./macro.bash.html),
+/// but using the bash executable given as the first argument rather than
+/// the one found in `PATH`.
+///
+/// ```
+/// use sh_inline::*;
+/// bash_with!("/bin/bash", r#"test "${BASH}" = /bin/bash"#)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[macro_export]
+macro_rules! bash_with {
+    ($bash:expr, $( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [.bash($bash)] $( $t )*).run()
+    };
+}
+
+/// Create a [`Command`] object that will execute a fragment of POSIX shell
+/// script with `/bin/sh`, for minimal systems such as Alpine, busybox-based
+/// containers and initramfs images which don't have bash.  Strict mode is
+/// `set -eu`, and bindings which would need bash syntax, such as arrays and
+/// binary data, are rejected; see the `posix` option of
+/// [`Script`](./struct.Script.html).
+///
+/// For more details on usage, see the [`bash_command`](./macro.bash_command.html) macro.
+///
+/// ```
+/// use sh_inline::*;
+/// let name = "world's";
+/// let r = sh_command!(r#"test "hello ${name}" = "hello world's""#, name)?.status()?;
+/// assert!(r.success());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// [`Command`]: https://doc.rust-lang.org/std/process/struct.Command.html
+#[macro_export]
+macro_rules! sh_command {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [.posix(true)] $( $t )*).command()
+    };
+}
+
+/// Execute a fragment of POSIX shell script with `/bin/sh`, returning an
+/// error if it exits unsuccessfully; see [`sh_command`](./macro.sh_command.html).
+///
+/// ```
+/// use sh_inline::*;
+/// let dir = tempfile::tempdir()?;
+/// let dir = dir.path();
+/// sh!(r#"cd "${dir}"; touch a; test -f "${dir}/a""#, dir)?;
+/// let files = vec!["a", "b"];
+/// assert!(sh!(r"true", files).is_err());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[macro_export]
+macro_rules! sh {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [.posix(true)] $( $t )*).run()
+    };
+}
+
+/// Execute a fragment of PowerShell script with `pwsh`, returning an error if
+/// it exits unsuccessfully.  Bindings become PowerShell variables, and
+/// `$ErrorActionPreference = 'Stop'` makes errors terminate the script; see
+/// the `powershell` option of [`Script`](./struct.Script.html).
+///
+/// ```no_run
+/// use sh_inline::*;
+/// let name = "it's";
+/// pwsh!(r#"if ($name -ne "it's") { exit 1 }"#, name)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[macro_export]
+macro_rules! pwsh {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [.powershell(true)] $( $t )*).run()
+    };
+}
+
+/// Parse the arguments shared by all the macros into a `Script` builder.
+/// Options of the form `key = value,` preceding the script are applied
+/// as calls to the builder method `key`; the bindings following it are
+/// either `name = value` or a bare identifier.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __sh_inline_script {
+    (@opts [$( $o:tt )*] env_keep = [$( $var:ident ),* $(,)?], $( $rest:tt )*) => {
+        $crate::__sh_inline_script!(@opts [$( $o )* .env_keep(&[$( stringify!($var) ),*])] $( $rest )*)
+    };
+    (@opts [$( $o:tt )*] env = { $( $var:ident : $value:expr ),* $(,)? }, $( $rest:tt )*) => {
+        $crate::__sh_inline_script!(@opts [$( $o )* $( .env_var(stringify!($var), $value) )*] $( $rest )*)
+    };
+    (@opts [$( $o:tt )*] $key:ident = $val:expr, $( $rest:tt )*) => {
+        $crate::__sh_inline_script!(@opts [$( $o )* .$key($val)] $( $rest )*)
+    };
+    (@opts [$( $o:tt )*] $s:expr $(, $( $rest:tt )* )?) => {
+        $crate::__sh_inline_script!(@bind [$crate::internals::Script::new(&$s) $( $o )*] $( $( $rest )* )?)
+    };
+    (@bind [$( $b:tt )*]) => {
+        $( $b )*
+    };
+    (@bind [$( $b:tt )*] $id:ident = $val:expr $(, $( $rest:tt )* )?) => {
+        $crate::__sh_inline_script!(@bind [$( $b )* .bind(stringify!($id), &$val)] $( $( $rest )* )?)
+    };
+    (@bind [$( $b:tt )*] $id:ident $(, $( $rest:tt )* )?) => {
+        $crate::__sh_inline_script!(@bind [$( $b )* .bind(stringify!($id), &$id)] $( $( $rest )* )?)
+    };
+}
+
+/// Describe what executing a fragment of Bash shell script would do without
+/// running it, returning a [`Plan`](./struct.Plan.html) with the program,
+/// arguments, environment changes and fully rendered script.  This is useful
+/// for `--dry-run` modes and confirmation prompts.
+///
+/// Nothing is executed or set up: temporary and scratch directories aren't
+/// created, so their paths are placeholders, `requires` and version checks
+/// are skipped, and a shell which isn't installed is shown by name.
+///
+/// For more details on usage, see the [`bash_command`](./macro.bash_command.html) macro.
+///
+/// ```
+/// use sh_inline::*;
+/// let dest = "/srv/some dir";
+/// let plan = bash_plan!(r"rm -rf ${dest}", dest)?;
+/// assert!(std::path::Path::new(&plan.program).ends_with("bash"));
+/// assert!(plan.script.contains(r#"dest="/srv/some dir""#));
+/// println!("Would run:\n{}", plan);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[macro_export]
+macro_rules! bash_plan {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [] $( $t )*).plan()
+    };
+}
+
+/// Render a fragment of Bash shell script without executing it, returning
+/// the complete text which would be run: the strict mode header, the quoted
+/// variable assignments and the script itself.  This is useful for dry
+/// runs, golden tests, or running the script elsewhere, e.g. over `ssh`.
+/// As with [`bash_plan`](./macro.bash_plan.html), nothing is executed or set
+/// up, and bash needn't be installed.
+///
+/// For more details on usage, see the [`bash_command`](./macro.bash_command.html) macro.
+///
+/// ```
+/// use sh_inline::*;
+/// let user = "O'Brien";
+/// let script = bash_script!(r#"id "${user}""#, user)?;
+/// assert!(script.starts_with("set -euo pipefail\nuser=\"O'Brien\"\n"));
+/// assert!(script.ends_with("\nid \"${user}\""));
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[macro_export]
+macro_rules! bash_script {
+    ($( $t:tt )*) => {
+        $crate::__sh_inline_script!(@opts [] $( $t )*).render()
+    };
+}
+
+/// Create an [`AwkProgram`](./struct.AwkProgram.html) to bind into a
+/// script, so that neither the program nor the values of its variables need
+/// to be quoted by hand.  Variables are given as bindings are to
+/// [`bash`](./macro.bash.html): `name = value` or a bare identifier, with
+/// values formatted with `Display`.
+///
+/// ```
+/// use sh_inline::*;
+/// let greeting = "it's \"quoted\"";
+/// let prog = awk_prog!(r#"{ print greeting ", " $1 }"#, greeting);
+/// let out = bash_output!(r#"echo world | awk "${prog[@]}""#, prog)?;
+/// assert_eq!(out, "it's \"quoted\", world\n");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[macro_export]
+macro_rules! awk_prog {
+    (@value $name:ident = $value:expr) => {
+        $value
+    };
+    (@value $name:ident) => {
+        $name
+    };
+    ($program:expr $(, $name:ident $(= $value:expr)?)* $(,)?) => {
+        $crate::AwkProgram::new($program)
+            $( .var(stringify!($name), &$crate::awk_prog!(@value $name $(= $value)?)) )*
+    };
+}
+
+/// Create a [`SedExpr`](./struct.SedExpr.html) to bind into a script, with
+/// the values substituted for the `{}` placeholders of the sed script
+/// escaped so that they are matched or inserted literally; see
+/// [`SedExpr::format`](./struct.SedExpr.html#method.format).
+///
+/// ```
+/// use sh_inline::*;
+/// let (from, to) = ("/usr/local/lib", "/opt/app & co/lib");
+/// let expr = sed_expr!("s/{}/{}/g", from, to)?;
+/// let out = bash_output!(r#"echo /usr/local/lib/libfoo.so | sed -e "${expr}""#, expr)?;
+/// assert_eq!(out, "/opt/app & co/lib/libfoo.so\n");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[macro_export]
+macro_rules! sed_expr {
+    ($template:expr $(, $value:expr)* $(,)?) => {
+        $crate::SedExpr::format($template, &[$( &$value as &dyn ::std::fmt::Display ),*])
     };
 }
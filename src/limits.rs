@@ -0,0 +1,194 @@
+use std::str::FromStr;
+
+/// Variables kept from the calling process when the environment is
+/// sanitized by a [`Limits`] profile.
+pub(crate) const SANITIZED_ENV_KEEP: &[&str] = &[
+    "PATH", "HOME", "USER", "LOGNAME", "LANG", "LC_ALL", "TERM", "TMPDIR", "TZ",
+];
+
+/// A resource which can be limited, as with `setrlimit(2)` or the `ulimit`
+/// builtin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// CPU time in seconds (`RLIMIT_CPU`)
+    Cpu,
+    /// Size of files written, in bytes (`RLIMIT_FSIZE`)
+    FileSize,
+    /// Size of core dumps, in bytes (`RLIMIT_CORE`)
+    Core,
+    /// Number of open file descriptors (`RLIMIT_NOFILE`)
+    OpenFiles,
+    /// Size of the virtual address space, in bytes (`RLIMIT_AS`)
+    AddressSpace,
+    /// Size of the stack, in bytes (`RLIMIT_STACK`)
+    Stack,
+    /// Number of processes of the user (`RLIMIT_NPROC`)
+    Processes,
+}
+
+impl Resource {
+    fn raw(self) -> nix::libc::c_int {
+        use nix::libc;
+        (match self {
+            Resource::Cpu => libc::RLIMIT_CPU,
+            Resource::FileSize => libc::RLIMIT_FSIZE,
+            Resource::Core => libc::RLIMIT_CORE,
+            Resource::OpenFiles => libc::RLIMIT_NOFILE,
+            Resource::AddressSpace => libc::RLIMIT_AS,
+            Resource::Stack => libc::RLIMIT_STACK,
+            Resource::Processes => libc::RLIMIT_NPROC,
+        }) as nix::libc::c_int
+    }
+}
+
+/// A hardening profile for scripts, combining resource limits,
+/// `no_new_privs` and a sanitized environment; see the `limits` option and
+/// [`Config::limits`](struct.Config.html#method.limits).
+///
+/// The presets give a consistent baseline, and can be adjusted further:
+///
+/// - `untrusted`: At most 60 seconds of CPU time, 1 GiB files, 256 open
+///   files and 4 GiB of address space, no core dumps, `no_new_privs`, and
+///   only a few basic environment variables such as `PATH` and `HOME`.
+/// - `build`: Up to 65536 open files for large parallel builds, no core
+///   dumps, and `no_new_privs`.
+/// - `unlimited-core`: Core dumps of any size, for debugging crashes.
+///
+/// Each limit is set as both the soft and hard limit, but never above the
+/// current hard limit, which unprivileged processes can't raise.
+///
+/// ```
+/// use sh_inline::*;
+/// let limits: Limits = "untrusted".parse()?;
+/// bash!(limits = limits.rlimit(Resource::OpenFiles, 64), r"test $(ulimit -n) = 64")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Limits {
+    rlimits: Vec<(Resource, u64)>,
+    no_new_privs: bool,
+    sanitize_env: bool,
+}
+
+impl Limits {
+    /// A profile which changes nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `untrusted` preset.
+    pub fn untrusted() -> Self {
+        const GIB: u64 = 1 << 30;
+        Self::new()
+            .rlimit(Resource::Cpu, 60)
+            .rlimit(Resource::FileSize, GIB)
+            .rlimit(Resource::Core, 0)
+            .rlimit(Resource::OpenFiles, 256)
+            .rlimit(Resource::AddressSpace, 4 * GIB)
+            .no_new_privs(true)
+            .sanitize_env(true)
+    }
+
+    /// The `build` preset.
+    pub fn build() -> Self {
+        Self::new()
+            .rlimit(Resource::OpenFiles, 65536)
+            .rlimit(Resource::Core, 0)
+            .no_new_privs(true)
+    }
+
+    /// The `unlimited-core` preset.
+    pub fn unlimited_core() -> Self {
+        Self::new().rlimit(Resource::Core, u64::MAX)
+    }
+
+    /// Limit `resource` to `limit`, replacing any previous limit for it;
+    /// `u64::MAX` means unlimited.
+    pub fn rlimit(mut self, resource: Resource, limit: u64) -> Self {
+        self.rlimits.retain(|(r, _)| *r != resource);
+        self.rlimits.push((resource, limit));
+        self
+    }
+
+    /// Set `no_new_privs`, so that neither the script nor the commands it
+    /// runs can gain privileges, e.g. via setuid executables (Linux only).
+    pub fn no_new_privs(mut self, no_new_privs: bool) -> Self {
+        self.no_new_privs = no_new_privs;
+        self
+    }
+
+    /// Clear the environment except for basic variables such as `PATH`,
+    /// `HOME` and `LANG`, as well as those set with the `env` option.
+    pub fn sanitize_env(mut self, sanitize_env: bool) -> Self {
+        self.sanitize_env = sanitize_env;
+        self
+    }
+
+    /// Whether the environment is sanitized.
+    pub(crate) fn sanitizes_env(&self) -> bool {
+        self.sanitize_env
+    }
+
+    /// Apply the limits to the current process; called in the child after
+    /// `fork()`, so it has to be async-signal-safe.
+    pub(crate) fn apply(&self) -> Result<(), std::io::Error> {
+        use nix::libc;
+        for &(resource, limit) in self.rlimits.iter() {
+            let mut current = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            // SAFETY: getrlimit() and setrlimit() only access the struct
+            unsafe {
+                if libc::getrlimit(resource.raw() as _, &mut current) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let limit = match limit {
+                    u64::MAX => libc::RLIM_INFINITY,
+                    limit => limit as libc::rlim_t,
+                };
+                let limit = if current.rlim_max == libc::RLIM_INFINITY {
+                    limit
+                } else if limit == libc::RLIM_INFINITY {
+                    current.rlim_max
+                } else {
+                    limit.min(current.rlim_max)
+                };
+                let new = libc::rlimit {
+                    rlim_cur: limit,
+                    rlim_max: limit,
+                };
+                if libc::setrlimit(resource.raw() as _, &new) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+        }
+        #[cfg(target_os = "linux")]
+        {
+            // SAFETY: prctl() is async-signal-safe
+            if self.no_new_privs
+                && unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Limits {
+    type Err = std::io::Error;
+
+    /// Look up a preset by name.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "untrusted" => Ok(Self::untrusted()),
+            "build" => Ok(Self::build()),
+            "unlimited-core" => Ok(Self::unlimited_core()),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("unknown limits preset: {:?}", name),
+            )),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::decode::Decoder;
+use crate::eval::EvalError;
+use crate::steps::Step;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::process::ExitStatus;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The complete result of running a script with
+/// [`bash_run`](macro.bash_run.html): both output streams, byte for byte,
+/// along with the exit status and how long the script took.
+#[derive(Debug, Clone)]
+pub struct BashOutput {
+    /// Everything the script wrote to standard output
+    pub stdout: Vec<u8>,
+    /// Everything the script wrote to standard error
+    pub stderr: Vec<u8>,
+    /// Exit status of the script
+    pub status: ExitStatus,
+    /// Time from spawning the script until it exited
+    pub duration: Duration,
+    /// The steps marked in standard output, if the `steps` option was given
+    pub steps: Vec<Step>,
+    /// The values set by the script, if the `outputs` option was given
+    pub outputs: BTreeMap<String, String>,
+}
+
+impl BashOutput {
+    /// Whether the script exited successfully.
+    pub fn success(&self) -> bool {
+        self.status.success()
+    }
+
+    /// Return an error like [`bash`](macro.bash.html) would if the script
+    /// exited unsuccessfully, including the end of standard error as
+    /// configured with [`Config::stderr_tail`].
+    ///
+    /// [`Config::stderr_tail`]: struct.Config.html#method.stderr_tail
+    pub fn check(self) -> Result<Self, std::io::Error> {
+        let tail = crate::Config::current().stderr_tail;
+        crate::Error::check(self.status, None, None, None, &self.stderr, tail)?;
+        Ok(self)
+    }
+
+    /// Parse the output `key` set by the script; see the `outputs` option.
+    /// A missing output is an error of kind `NotFound`.
+    pub fn parse_output<T: FromStr>(&self, key: &str) -> Result<T, EvalError<T::Err>> {
+        let output = self.outputs.get(key).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("bash script did not set output {:?}", key),
+            )
+        })?;
+        output.parse().map_err(|error| EvalError::Parse {
+            output: output.clone(),
+            error,
+        })
+    }
+
+    /// Decode standard output into rows with `decoder`, such as
+    /// [`decode::JsonLines`](decode/struct.JsonLines.html).
+    pub fn decode<D: Decoder>(&self, decoder: D) -> Result<Vec<D::Row>, std::io::Error> {
+        decoder.decode(&self.stdout)
+    }
+
+    /// Standard output as text, with invalid UTF-8 replaced by `U+FFFD`.
+    pub fn stdout_utf8_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stdout)
+    }
+
+    /// Standard error as text, with invalid UTF-8 replaced by `U+FFFD`.
+    pub fn stderr_utf8_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stderr)
+    }
+
+    /// Standard output as text, if it is valid UTF-8.
+    pub fn stdout_str(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.stdout)
+    }
+
+    /// Standard error as text, if it is valid UTF-8.
+    pub fn stderr_str(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.stderr)
+    }
+}
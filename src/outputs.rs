@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+/// Conventions for returning named values from a script, modelled on
+/// GitHub Actions; see the `outputs` option.
+///
+/// A script can set an output either by printing a command line such as
+/// `::set-output name=KEY::VALUE` to standard output, which is removed from
+/// the output, or by appending `KEY=VALUE` to the file named by the
+/// `SH_INLINE_OUTPUT` environment variable.  Values spanning several lines
+/// can be written to the file as `KEY<<DELIMITER`, the lines, and then
+/// `DELIMITER`; in commands, `%0A`, `%0D` and `%25` stand for a newline, a
+/// carriage return and `%`.  The outputs are returned in
+/// [`BashOutput::outputs`].
+///
+/// ```
+/// use sh_inline::*;
+/// let r = bash_run!(
+///     outputs = OutputCommands::new(),
+///     r#"echo "::set-output name=version::1.2.3"
+///     echo "count=42" >> "${SH_INLINE_OUTPUT}""#
+/// )?;
+/// assert!(r.stdout.is_empty());
+/// assert_eq!(r.outputs["version"], "1.2.3");
+/// assert_eq!(r.parse_output::<u32>("count")?, 42);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// [`BashOutput::outputs`]: struct.BashOutput.html#structfield.outputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputCommands {
+    pub(crate) marker: String,
+    pub(crate) file_var: String,
+}
+
+impl Default for OutputCommands {
+    fn default() -> Self {
+        Self {
+            marker: "::set-output".to_string(),
+            file_var: "SH_INLINE_OUTPUT".to_string(),
+        }
+    }
+}
+
+impl OutputCommands {
+    /// The default conventions, with the `::set-output` command and the
+    /// `SH_INLINE_OUTPUT` file.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Recognize `MARKER name=KEY::VALUE` lines rather than `::set-output`.
+    pub fn marker<S: Into<String>>(mut self, marker: S) -> Self {
+        self.marker = marker.into();
+        self
+    }
+
+    /// Name the output file with the environment variable `name`, such as
+    /// `GITHUB_OUTPUT` for running scripts written for GitHub Actions.
+    pub fn file_var<S: Into<String>>(mut self, name: S) -> Self {
+        self.file_var = name.into();
+        self
+    }
+
+    /// Parse an output command from a line of standard output, without its
+    /// newline.
+    pub(crate) fn parse_command(&self, line: &str) -> Option<(String, String)> {
+        let rest = line.strip_prefix(self.marker.as_str())?;
+        let rest = rest.strip_prefix(' ')?.strip_prefix("name=")?;
+        let (key, value) = rest.split_once("::")?;
+        let value = value
+            .replace("%0A", "\n")
+            .replace("%0D", "\r")
+            .replace("%25", "%");
+        Some((key.to_string(), value))
+    }
+}
+
+/// Parse the contents of an output file.
+pub(crate) fn parse_file(contents: &str) -> Result<BTreeMap<String, String>, std::io::Error> {
+    let invalid = |line: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid line in bash script output file: {:?}", line),
+        )
+    };
+    let mut outputs = BTreeMap::new();
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        if line.is_empty() {
+            continue;
+        }
+        let heredoc = line.split_once("<<").filter(|(key, _)| !key.contains('='));
+        if let Some((key, delimiter)) = heredoc {
+            let mut value: Vec<&str> = Vec::new();
+            loop {
+                match lines.next() {
+                    Some(l) if l == delimiter => break,
+                    Some(l) => value.push(l),
+                    None => return Err(invalid(line)),
+                }
+            }
+            outputs.insert(key.to_string(), value.join("\n"));
+        } else {
+            let (key, value) = line.split_once('=').ok_or_else(|| invalid(line))?;
+            outputs.insert(key.to_string(), value.to_string());
+        }
+    }
+    Ok(outputs)
+}
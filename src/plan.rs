@@ -0,0 +1,105 @@
+use crate::script::SECRET_ENV_PREFIX;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A description of what executing a script would do, without running it;
+/// see [`bash_plan`](./macro.bash_plan.html).
+///
+/// The `Display` implementation renders a human-readable preview suitable
+/// for `--dry-run` output or confirmation prompts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    /// The name given with the `name` option, if any
+    pub name: Option<String>,
+    /// The program which will be executed; usually `bash`, unless the script
+    /// is wrapped by e.g. `systemd-run` or `timeout` or run directly via the
+    /// `fast_path` option.
+    pub program: OsString,
+    /// Arguments to the program
+    pub args: Vec<OsString>,
+    /// Whether the environment of the calling process is cleared, e.g. by
+    /// the `env_keep` option or [`Limits::sanitize_env`], so that only the
+    /// variables in `env` are set.
+    ///
+    /// [`Limits::sanitize_env`]: struct.Limits.html#method.sanitize_env
+    pub env_cleared: bool,
+    /// Changes to the environment; `None` removes a variable.  The values of
+    /// [`Secret`](struct.Secret.html) bindings are replaced by `<redacted>`.
+    pub env: Vec<(OsString, Option<OsString>)>,
+    /// Working directory, if different from that of the current process
+    pub current_dir: Option<PathBuf>,
+    /// The full script, including the prelude and variable bindings
+    pub script: String,
+}
+
+impl Plan {
+    pub(crate) fn new(
+        cmd: &Command,
+        script: String,
+        name: Option<String>,
+        env_cleared: bool,
+    ) -> Self {
+        Plan {
+            name,
+            program: cmd.get_program().to_owned(),
+            args: cmd.get_args().map(|a| a.to_owned()).collect(),
+            env_cleared,
+            env: cmd
+                .get_envs()
+                .map(|(k, v)| (k.to_owned(), v.map(|v| redact(k, v))))
+                .collect(),
+            current_dir: cmd.get_current_dir().map(|p| p.to_owned()),
+            script,
+        }
+    }
+
+    /// The command line which will be executed, quoted for display.
+    pub fn command_line(&self) -> String {
+        std::iter::once(&self.program)
+            .chain(self.args.iter())
+            .map(|a| shlex::quote(&a.to_string_lossy()).into_owned())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// The value `value` of the variable `name`, unless it is a secret.
+pub(crate) fn redact(name: &OsStr, value: &OsStr) -> OsString {
+    match name.to_string_lossy().starts_with(SECRET_ENV_PREFIX) {
+        true => "<redacted>".into(),
+        false => value.to_owned(),
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = self.name.as_ref() {
+            writeln!(f, "# name: {}", name)?;
+        }
+        writeln!(f, "# command: {}", self.command_line())?;
+        if let Some(dir) = self.current_dir.as_ref() {
+            writeln!(f, "# directory: {}", dir.display())?;
+        }
+        if self.env_cleared {
+            writeln!(f, "# env: cleared")?;
+        }
+        for (k, v) in self.env.iter() {
+            match v {
+                Some(v) => writeln!(
+                    f,
+                    "# env: {}={}",
+                    k.to_string_lossy(),
+                    shlex::quote(&v.to_string_lossy())
+                )?,
+                None => writeln!(f, "# env: unset {}", k.to_string_lossy())?,
+            }
+        }
+        f.write_str(&self.script)?;
+        if !self.script.ends_with('\n') {
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
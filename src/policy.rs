@@ -0,0 +1,138 @@
+use regex::Regex;
+use std::sync::Arc;
+
+/// Rules deciding which scripts may be executed, for applications where
+/// script fragments or bound values come partly from configuration files.
+///
+/// A policy can be installed for all scripts with [`Config::policy`] or
+/// given to individual invocations with the `policy` option.  A script is
+/// denied if any `deny` pattern matches its text or one of its bindings, if
+/// there are `allow` patterns and none of them matches its text, or if any
+/// `check` callback rejects it.  Denied scripts fail with an error of kind
+/// `PermissionDenied` without being executed.
+///
+/// ```
+/// use sh_inline::*;
+/// let policy = Policy::new().deny(r"\brm\s+-rf\b")?.allow(r"^echo ")?;
+/// bash!(policy = policy.clone(), r"echo hello")?;
+/// let e = bash!(policy = policy, r"rm -rf /tmp/foo").unwrap_err();
+/// assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// [`Config::policy`]: struct.Config.html#method.policy
+#[derive(Clone, Default)]
+pub struct Policy {
+    rules: Vec<Rule>,
+}
+
+/// Callback rules, given the script and returning why it is denied if it is.
+type Check = Arc<dyn Fn(&PolicyInput) -> Result<(), String> + Send + Sync>;
+
+#[derive(Clone)]
+enum Rule {
+    Allow(Regex),
+    Deny(Regex),
+    Check(Check),
+}
+
+/// What a [`Policy`](struct.Policy.html) is evaluated against.
+#[derive(Debug)]
+pub struct PolicyInput<'a> {
+    /// The name given with the `name` option, if any
+    pub name: Option<&'a str>,
+    /// The script as given, without the prelude
+    pub script: &'a str,
+    /// The variable bindings, with values quoted as they are in the script;
+    /// those of secrets are `<redacted>`
+    pub bindings: &'a [(String, String)],
+}
+
+impl std::fmt::Debug for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let rules: Vec<String> = self
+            .rules
+            .iter()
+            .map(|r| match r {
+                Rule::Allow(re) => format!("allow {:?}", re.as_str()),
+                Rule::Deny(re) => format!("deny {:?}", re.as_str()),
+                Rule::Check(_) => "check".to_string(),
+            })
+            .collect();
+        f.debug_struct("Policy").field("rules", &rules).finish()
+    }
+}
+
+impl Policy {
+    /// A policy allowing everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow scripts whose text matches `pattern`; once any allow pattern is
+    /// given, scripts matching none of them are denied.
+    pub fn allow(mut self, pattern: &str) -> Result<Self, regex::Error> {
+        self.rules.push(Rule::Allow(Regex::new(pattern)?));
+        Ok(self)
+    }
+
+    /// Deny scripts whose text or any binding value matches `pattern`.
+    pub fn deny(mut self, pattern: &str) -> Result<Self, regex::Error> {
+        self.rules.push(Rule::Deny(Regex::new(pattern)?));
+        Ok(self)
+    }
+
+    /// Deny scripts for which `check` returns an error, whose message
+    /// becomes part of the resulting error.
+    pub fn check<F>(mut self, check: F) -> Self
+    where
+        F: Fn(&PolicyInput) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.rules.push(Rule::Check(Arc::new(check)));
+        self
+    }
+
+    /// Evaluate the policy, returning a `PermissionDenied` error if the
+    /// script is not allowed.
+    pub(crate) fn evaluate(&self, input: &PolicyInput) -> Result<(), std::io::Error> {
+        let denied = |why: String| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!(
+                    "{} denied by policy: {}",
+                    crate::internals::describe(input.name),
+                    why
+                ),
+            ))
+        };
+        let mut allowed = None;
+        for rule in self.rules.iter() {
+            match rule {
+                Rule::Allow(re) => {
+                    if re.is_match(input.script) {
+                        allowed = Some(true);
+                    } else {
+                        allowed.get_or_insert(false);
+                    }
+                }
+                Rule::Deny(re) => {
+                    if re.is_match(input.script) {
+                        return denied(format!("script matches {:?}", re.as_str()));
+                    }
+                    if let Some((name, _)) = input.bindings.iter().find(|(_, v)| re.is_match(v)) {
+                        return denied(format!("{} matches {:?}", name, re.as_str()));
+                    }
+                }
+                Rule::Check(check) => {
+                    if let Err(why) = check(input) {
+                        return denied(why);
+                    }
+                }
+            }
+        }
+        match allowed {
+            Some(false) => denied("script matches no allowed pattern".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
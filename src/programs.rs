@@ -0,0 +1,208 @@
+use crate::internals::CommandArg;
+use crate::shell_arg::{ShellArg, ToShellArg};
+use std::fmt;
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+}
+
+/// An awk program along with values for its variables, bound as the array
+/// of arguments `-v name=value ... -- program`, to be expanded as
+/// `awk "${prog[@]}"`; see [`awk_prog`](./macro.awk_prog.html).
+///
+/// The program is passed as a single argument however it is quoted, and the
+/// values are escaped for `-v`, which interprets backslash escapes; like
+/// fields of the input, values which look like numbers compare as numbers.
+/// As an array, it can't be bound by the `posix` option.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AwkProgram {
+    program: String,
+    vars: Vec<(String, String)>,
+}
+
+impl AwkProgram {
+    /// A program without variables.
+    pub fn new<S: Into<String>>(program: S) -> Self {
+        AwkProgram {
+            program: program.into(),
+            vars: Vec::new(),
+        }
+    }
+
+    /// Set the awk variable `name` to `value` before the program starts.
+    ///
+    /// Panics if `name` isn't a valid awk variable name.
+    pub fn var<V: fmt::Display + ?Sized>(mut self, name: &str, value: &V) -> Self {
+        let valid = name
+            .bytes()
+            .enumerate()
+            .all(|(i, c)| c == b'_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+        assert!(
+            valid && !name.is_empty(),
+            "invalid awk variable name: {:?}",
+            name
+        );
+        self.vars.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// The arguments for awk.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for (name, value) in self.vars.iter() {
+            let value = value.replace('\\', r"\\").replace('\n', r"\n");
+            args.push("-v".to_string());
+            args.push(format!("{}={}", name, value));
+        }
+        args.push("--".to_string());
+        args.push(self.program.clone());
+        args
+    }
+}
+
+impl ToShellArg for AwkProgram {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg(CommandArg::Array(self.args()))
+    }
+}
+
+/// A sed script, bound as a single string for `sed -e "${expr}"`, into which
+/// values were substituted literally; see [`sed_expr`](./macro.sed_expr.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SedExpr(String);
+
+/// Where in a sed script a placeholder is.
+#[derive(Clone, Copy)]
+enum Field {
+    /// Commands, flags and addresses given by line number
+    Command,
+    /// A regular expression ended by the delimiter, which is that of an `s`
+    /// command if followed by a replacement
+    Regex(char, bool),
+    Replacement(char),
+}
+
+impl SedExpr {
+    /// Substitute `values` for the `{}` placeholders in `template`, escaped
+    /// so that they match literally in a regular expression, whether an
+    /// address or the pattern of an `s` command, or are inserted literally
+    /// by the replacement of an `s` command, even if they contain the
+    /// delimiter.  `{{` and `}}` stand for braces.
+    ///
+    /// Fails with an error of kind `InvalidInput` if the number of values
+    /// doesn't match, or if a placeholder is elsewhere.
+    pub fn format(template: &str, values: &[&dyn fmt::Display]) -> Result<Self, std::io::Error> {
+        let mut out = String::with_capacity(template.len());
+        let mut values = values.iter();
+        let mut field = Field::Command;
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek(), field) {
+                ('{', Some('{'), _) | ('}', Some('}'), _) => {
+                    chars.next();
+                    out.push(c);
+                }
+                ('{', Some('}'), Field::Command) => {
+                    return Err(invalid(format!(
+                        "placeholder outside a regular expression or replacement in sed script {:?}",
+                        template
+                    )));
+                }
+                ('{', Some('}'), Field::Regex(delim, _))
+                | ('{', Some('}'), Field::Replacement(delim)) => {
+                    chars.next();
+                    let value = values.next().ok_or_else(|| {
+                        invalid(format!("too few values for sed script {:?}", template))
+                    })?;
+                    let value = value.to_string();
+                    match field {
+                        Field::Replacement(_) => escape_replacement(&mut out, &value, delim),
+                        _ => escape_regex(&mut out, &value, delim),
+                    }
+                }
+                ('\\', Some(_), Field::Regex(..)) | ('\\', Some(_), Field::Replacement(_)) => {
+                    out.push(c);
+                    out.push(chars.next().unwrap());
+                }
+                (_, _, Field::Regex(delim, s)) if c == delim => {
+                    out.push(c);
+                    field = match s {
+                        true => Field::Replacement(delim),
+                        false => Field::Command,
+                    };
+                }
+                (_, _, Field::Replacement(delim)) if c == delim => {
+                    out.push(c);
+                    field = Field::Command;
+                }
+                ('/', _, Field::Command) => {
+                    out.push(c);
+                    field = Field::Regex('/', false);
+                }
+                ('\\', Some(&delim), Field::Command) | ('s', Some(&delim), Field::Command)
+                    if delim != '\n' && delim != '\\' =>
+                {
+                    chars.next();
+                    out.push(c);
+                    out.push(delim);
+                    field = Field::Regex(delim, c == 's');
+                }
+                _ => out.push(c),
+            }
+        }
+        if values.next().is_some() {
+            return Err(invalid(format!(
+                "too many values for sed script {:?}",
+                template
+            )));
+        }
+        Ok(SedExpr(out))
+    }
+
+    /// The script.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Append `value` to a regular expression ended by `delim`, escaped to
+/// match literally.
+fn escape_regex(out: &mut String, value: &str, delim: char) {
+    for c in value.chars() {
+        match c {
+            '\n' => out.push_str(r"\n"),
+            '\\' | '.' | '*' | '[' | ']' | '^' | '$' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ if c == delim => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+}
+
+/// Append `value` to the replacement of an `s` command ended by `delim`,
+/// escaped to be inserted literally.
+fn escape_replacement(out: &mut String, value: &str, delim: char) {
+    for c in value.chars() {
+        if matches!(c, '\\' | '&' | '\n') || c == delim {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+impl fmt::Display for SedExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl ToShellArg for SedExpr {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg::string(self.0.as_str())
+    }
+}
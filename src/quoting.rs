@@ -0,0 +1,158 @@
+//! Quoting rules for the shells scripts are rendered for.
+//!
+//! Bindings are rendered through a [`QuotingProfile`], so that the bash and
+//! POSIX sh backends and the macros for other shells share one set of
+//! escaping rules.  The profiles can also be used directly to quote values
+//! when generating scripts by other means:
+//!
+//! ```
+//! use sh_inline::quoting::{Fish, PowerShell, QuotingProfile};
+//! assert_eq!(Fish.quote(r"it's a\b"), r"'it\'s a\\b'");
+//! let value = PowerShell.quote("it's");
+//! assert_eq!(PowerShell.assign("name", &value), "$name = 'it''s'");
+//! ```
+
+/// The quoting rules of a shell.
+pub trait QuotingProfile {
+    /// Quote `value` so that the shell reads it as a single word with
+    /// exactly that value.
+    fn quote(&self, value: &str) -> String;
+
+    /// Quote arbitrary bytes as a single word, or return `None` if the shell
+    /// can't express them; by default only valid UTF-8 is supported.
+    fn quote_bytes(&self, value: &[u8]) -> Option<String> {
+        std::str::from_utf8(value).ok().map(|s| self.quote(s))
+    }
+
+    /// Quote `items` as a list to assign to an array variable, or return
+    /// `None` if the shell has no arrays.
+    fn quote_list(&self, items: &[String]) -> Option<String> {
+        let _ = items;
+        None
+    }
+
+    /// Quote `entries` as a map to assign to a variable, or return `None`
+    /// if the shell has no associative arrays.
+    fn quote_map(&self, entries: &[(String, String)]) -> Option<String> {
+        let _ = entries;
+        None
+    }
+
+    /// A statement assigning the already quoted `value` to the variable
+    /// `name`.
+    fn assign(&self, name: &str, value: &str) -> String {
+        format!("{}={}", name, value)
+    }
+}
+
+/// Quote `value` in single quotes even if it needs no quoting, e.g. as an
+/// associative array key where `]` would otherwise end the subscript.
+fn single_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Bash, which adds `$'...'` strings for arbitrary bytes, arrays and
+/// associative arrays to POSIX sh.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bash;
+
+impl QuotingProfile for Bash {
+    fn quote(&self, value: &str) -> String {
+        Posix.quote(value)
+    }
+
+    // https://wiki.bash-hackers.org/syntax/quoting#ansi_c_like_strings
+    fn quote_bytes(&self, value: &[u8]) -> Option<String> {
+        let mut r = Vec::new();
+        r.extend(b"$'".iter());
+        r.extend(value.iter().flat_map(|&c| std::ascii::escape_default(c)));
+        r.extend(b"'".iter());
+        Some(String::from_utf8(r).expect("bash_binary quote should have output utf8"))
+    }
+
+    fn quote_list(&self, items: &[String]) -> Option<String> {
+        let items: Vec<String> = items.iter().map(|x| self.quote(x)).collect();
+        Some(format!("({})", items.join(" ")))
+    }
+
+    fn quote_map(&self, entries: &[(String, String)]) -> Option<String> {
+        let entries: Vec<String> = entries
+            .iter()
+            .map(|(k, v)| format!("[{}]={}", single_quote(k), single_quote(v)))
+            .collect();
+        Some(format!("({})", entries.join(" ")))
+    }
+}
+
+/// POSIX sh, e.g. dash, as used by the `posix` option.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Posix;
+
+impl QuotingProfile for Posix {
+    fn quote(&self, value: &str) -> String {
+        shlex::quote(value).to_string()
+    }
+
+    // There is no portable escape for arbitrary bytes
+    fn quote_bytes(&self, _: &[u8]) -> Option<String> {
+        None
+    }
+}
+
+/// The fish shell, where variables are assigned with `set` and every
+/// variable is a list.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fish;
+
+impl QuotingProfile for Fish {
+    fn quote(&self, value: &str) -> String {
+        format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+    }
+
+    fn quote_list(&self, items: &[String]) -> Option<String> {
+        let items: Vec<String> = items.iter().map(|x| self.quote(x)).collect();
+        Some(items.join(" "))
+    }
+
+    fn assign(&self, name: &str, value: &str) -> String {
+        format!("set {} {}", name, value)
+    }
+}
+
+/// PowerShell, with values in verbatim single-quoted strings.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PowerShell;
+
+impl QuotingProfile for PowerShell {
+    fn quote(&self, value: &str) -> String {
+        // The typographic single quotes end strings too; any of them is
+        // escaped by doubling it
+        let mut quoted = String::with_capacity(value.len() + 2);
+        quoted.push('\'');
+        for c in value.chars() {
+            if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+                quoted.push(c);
+            }
+            quoted.push(c);
+        }
+        quoted.push('\'');
+        quoted
+    }
+
+    fn quote_list(&self, items: &[String]) -> Option<String> {
+        let items: Vec<String> = items.iter().map(|x| self.quote(x)).collect();
+        Some(format!("@({})", items.join(", ")))
+    }
+
+    fn quote_map(&self, entries: &[(String, String)]) -> Option<String> {
+        let entries: Vec<String> = entries
+            .iter()
+            .map(|(k, v)| format!("{} = {}", self.quote(k), self.quote(v)))
+            .collect();
+        Some(format!("[ordered]@{{{}}}", entries.join("; ")))
+    }
+
+    fn assign(&self, name: &str, value: &str) -> String {
+        format!("${} = {}", name, value)
+    }
+}
@@ -0,0 +1,114 @@
+use crate::output::BashOutput;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// Recording of script executions for replay, allowing deterministic tests
+/// of code whose behavior depends on the results of scripts.
+///
+/// Executions are identified by a hash of the fully rendered script, along
+/// with any values passed through the environment by the `bind_env` option,
+/// so scripts must render identically (e.g. with the same bound values) to
+/// be replayed; files bound with [`tempfile`](fn.tempfile.html) count by
+/// their contents rather than their paths.  For each, the directory holds the rendered script as
+/// `<hash>.sh` for reference, its output as `<hash>.stdout` and
+/// `<hash>.stderr`, and its exit status and duration as `<hash>.status`.
+///
+/// While recording or replaying, output is passed on only once the script
+/// has exited.  Scripts run via `bash_command!` or `bash_spawn!` are not
+/// recorded.
+///
+/// ```
+/// use sh_inline::*;
+/// let dir = tempfile::tempdir()?;
+/// let record = Recording::Record(dir.path().into());
+/// let replay = Recording::Replay(dir.path().into());
+/// let when = bash_output!(recording = record, r"date +%s.%N")?;
+/// assert_eq!(bash_output!(recording = replay.clone(), r"date +%s.%N")?, when);
+/// let e = bash!(recording = replay, r"echo not recorded").unwrap_err();
+/// assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recording {
+    /// Execute scripts as usual, saving their results in the directory
+    Record(PathBuf),
+    /// Serve executions from the recordings in the directory without
+    /// running anything; scripts which were not recorded fail with an error
+    /// of kind `NotFound`
+    Replay(PathBuf),
+}
+
+/// A stable (FNV-1a) hash of `script` and the values `bound` through the
+/// environment, used to name its recording.
+fn key(script: &str, bound: &str) -> String {
+    let hash = crate::internals::fnv1a(script.bytes().chain(bound.bytes()));
+    format!("{:016x}", hash)
+}
+
+impl Recording {
+    /// Record the output of `run` for `script` with the values `bound`
+    /// through the environment, or replay it.
+    pub(crate) fn execute<F>(
+        &self,
+        script: &str,
+        bound: &str,
+        run: F,
+    ) -> Result<BashOutput, std::io::Error>
+    where
+        F: FnOnce() -> Result<BashOutput, std::io::Error>,
+    {
+        let key = key(script, bound);
+        match self {
+            Recording::Record(dir) => {
+                let output = run()?;
+                let path = |ext: &str| dir.join(format!("{}.{}", key, ext));
+                std::fs::create_dir_all(dir)?;
+                std::fs::write(path("sh"), script)?;
+                std::fs::write(path("stdout"), &output.stdout)?;
+                std::fs::write(path("stderr"), &output.stderr)?;
+                let status = format!(
+                    "{}\n{}\n",
+                    output.status.into_raw(),
+                    output.duration.as_nanos()
+                );
+                std::fs::write(path("status"), status)?;
+                Ok(output)
+            }
+            Recording::Replay(dir) => replay(dir, &key),
+        }
+    }
+}
+
+fn replay(dir: &Path, key: &str) -> Result<BashOutput, std::io::Error> {
+    let path = |ext: &str| dir.join(format!("{}.{}", key, ext));
+    let status = match std::fs::read_to_string(path("status")) {
+        Ok(status) => status,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no recording of bash script {} in {}", key, dir.display()),
+            ))
+        }
+        Err(e) => return Err(e),
+    };
+    let invalid = || {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid recording {}", path("status").display()),
+        )
+    };
+    let mut lines = status.lines();
+    let raw = lines.next().and_then(|l| l.parse::<i32>().ok());
+    let nanos = lines.next().and_then(|l| l.parse::<u64>().ok());
+    let (raw, nanos) = raw.zip(nanos).ok_or_else(invalid)?;
+    Ok(BashOutput {
+        stdout: std::fs::read(path("stdout"))?,
+        stderr: std::fs::read(path("stderr"))?,
+        status: ExitStatus::from_raw(raw),
+        duration: Duration::from_nanos(nanos),
+        steps: Vec::new(),
+        outputs: Default::default(),
+    })
+}
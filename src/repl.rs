@@ -0,0 +1,150 @@
+use crate::config::Config;
+use crate::internals;
+use crate::policy::{Policy, PolicyInput};
+use crate::session::BashSession;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+
+/// Called with each complete command entered.
+type HistoryHook = Box<dyn FnMut(&str)>;
+
+/// A simple interactive shell on top of a [`BashSession`], reading commands
+/// from one stream and writing prompts and output to another, for
+/// embedding e.g. a debug console in an application.
+///
+/// Input which is not yet a complete command, such as an unterminated
+/// quote or `if` statement, or a line ending in a backslash, is continued
+/// on the next line after a continuation prompt.  Each command is checked
+/// against the [`Policy`] if one is given, as well as the one installed
+/// with [`Config`], and its standard output and
+/// error are written once it has finished.  The REPL ends at the end of
+/// input or when `exit` is entered.
+///
+/// ```
+/// use sh_inline::*;
+/// let mut repl = Repl::new(BashSession::new()?).prompt("> ");
+/// let mut output = Vec::new();
+/// repl.run(&b"x=1\nif true; then\necho $x\nfi\n"[..], &mut output)?;
+/// assert_eq!(String::from_utf8_lossy(&output), "> > ... ... 1\n> ");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// [`BashSession`]: struct.BashSession.html
+/// [`Policy`]: struct.Policy.html
+/// [`Config`]: struct.Config.html
+pub struct Repl {
+    session: BashSession,
+    prompt: String,
+    continuation: String,
+    history: Option<HistoryHook>,
+    policy: Option<Policy>,
+}
+
+impl Repl {
+    /// A REPL running commands in `session`, with the prompts `$ ` and `... `.
+    pub fn new(session: BashSession) -> Self {
+        Repl {
+            session,
+            prompt: "$ ".to_string(),
+            continuation: "... ".to_string(),
+            history: None,
+            policy: None,
+        }
+    }
+
+    /// Show `prompt` when waiting for a command.
+    pub fn prompt<S: Into<String>>(mut self, prompt: S) -> Self {
+        self.prompt = prompt.into();
+        self
+    }
+
+    /// Show `prompt` when waiting for the rest of an incomplete command.
+    pub fn continuation<S: Into<String>>(mut self, prompt: S) -> Self {
+        self.continuation = prompt.into();
+        self
+    }
+
+    /// Call `hook` with each complete command before it is run, e.g. to
+    /// record a history.
+    pub fn on_history<F: FnMut(&str) + 'static>(mut self, hook: F) -> Self {
+        self.history = Some(Box::new(hook));
+        self
+    }
+
+    /// Only run commands allowed by `policy`; denied commands are reported
+    /// in the output.
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// The session commands are run in.
+    pub fn session(&mut self) -> &mut BashSession {
+        &mut self.session
+    }
+
+    /// Read and run commands from `input` until it ends or `exit` is
+    /// entered.
+    pub fn run<R: Read, W: Write>(
+        &mut self,
+        input: R,
+        mut output: W,
+    ) -> Result<(), std::io::Error> {
+        let mut input = BufReader::new(input);
+        let mut command = String::new();
+        loop {
+            let prompt = if command.is_empty() {
+                &self.prompt
+            } else {
+                &self.continuation
+            };
+            output.write_all(prompt.as_bytes())?;
+            output.flush()?;
+            if input.read_line(&mut command)? == 0 {
+                return Ok(());
+            }
+            if !complete(&command)? {
+                continue;
+            }
+            let entered = std::mem::take(&mut command);
+            let entered = entered.trim_end_matches('\n');
+            match entered.trim() {
+                "" => continue,
+                "exit" => return Ok(()),
+                _ => {}
+            }
+            if let Some(hook) = self.history.as_mut() {
+                hook(entered);
+            }
+            let input = PolicyInput {
+                name: None,
+                script: entered,
+                bindings: &[],
+            };
+            let config = Config::current();
+            let mut policies = config.policy.iter().chain(self.policy.iter());
+            if let Some(e) = policies.find_map(|p| p.evaluate(&input).err()) {
+                writeln!(output, "{}", e)?;
+                continue;
+            }
+            let r = self.session.run_output(entered)?;
+            output.write_all(&r.stdout)?;
+            output.write_all(&r.stderr)?;
+        }
+    }
+}
+
+/// Whether `command` is complete, rather than ending within a quoted
+/// string, compound command or line continuation.
+fn complete(command: &str) -> Result<bool, std::io::Error> {
+    if command.ends_with("\\\n") {
+        return Ok(false);
+    }
+    let check = Command::new(internals::bash_path(&Config::current())?)
+        .args(["-n", "-c", command])
+        .env("LC_ALL", "C")
+        .stdin(Stdio::null())
+        .output()?;
+    let errors = String::from_utf8_lossy(&check.stderr);
+    Ok(!(errors.contains("unexpected end of file") || errors.contains("unexpected EOF")))
+}
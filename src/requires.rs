@@ -0,0 +1,171 @@
+use crate::internals::describe;
+use crate::resolve::resolve_in;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+/// Versions probed with `--version`, by executable; `None` if no version
+/// number could be found in the output.
+static PROBED: Mutex<Option<HashMap<PathBuf, Option<Vec<u64>>>>> = Mutex::new(None);
+
+/// A comparison with a required version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Ge,
+    Gt,
+    Eq,
+    Le,
+    Lt,
+}
+
+impl Op {
+    const ALL: [(&'static str, Op); 5] = [
+        (">=", Op::Ge),
+        ("<=", Op::Le),
+        (">", Op::Gt),
+        ("<", Op::Lt),
+        ("=", Op::Eq),
+    ];
+
+    fn matches(self, found: &[u64], required: &[u64]) -> bool {
+        let len = found.len().max(required.len());
+        let pad = |v: &[u64]| {
+            let mut v = v.to_vec();
+            v.resize(len, 0);
+            v
+        };
+        let ordering = pad(found).cmp(&pad(required));
+        match self {
+            Op::Ge => ordering.is_ge(),
+            Op::Gt => ordering.is_gt(),
+            Op::Eq => ordering.is_eq(),
+            Op::Le => ordering.is_le(),
+            Op::Lt => ordering.is_lt(),
+        }
+    }
+}
+
+/// A parsed requirement such as `rsync >= 3.2`.
+struct Requirement<'a> {
+    command: &'a str,
+    version: Option<(Op, Vec<u64>)>,
+}
+
+fn parse(requirement: &str) -> Result<Requirement<'_>, std::io::Error> {
+    let invalid = || {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid requirement: {:?}", requirement),
+        )
+    };
+    let requirement = requirement.trim();
+    let split = Op::ALL
+        .iter()
+        .filter_map(|(s, op)| requirement.find(s).map(|i| (i, *s, *op)))
+        .min_by_key(|(i, s, _)| (*i, std::cmp::Reverse(s.len())));
+    let (command, version) = match split {
+        Some((i, s, op)) => {
+            let version = parse_version(requirement[i + s.len()..].trim()).ok_or_else(invalid)?;
+            (requirement[..i].trim(), Some((op, version)))
+        }
+        None => (requirement, None),
+    };
+    if command.is_empty() || command.contains(char::is_whitespace) {
+        return Err(invalid());
+    }
+    Ok(Requirement { command, version })
+}
+
+/// Parse a dotted version number such as `3.2.7`.
+fn parse_version(s: &str) -> Option<Vec<u64>> {
+    s.split('.').map(|c| c.parse().ok()).collect()
+}
+
+fn format_version(v: &[u64]) -> String {
+    v.iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// The version reported by `path --version`, cached for the lifetime of the
+/// process.
+fn probe(path: &Path) -> Option<Vec<u64>> {
+    let mut probed = PROBED.lock().unwrap();
+    let probed = probed.get_or_insert_with(HashMap::new);
+    if let Some(version) = probed.get(path) {
+        return version.clone();
+    }
+    let output = Command::new(path)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .env("LC_ALL", "C")
+        .output()
+        .ok();
+    let re = regex::Regex::new(r"\b\d+(\.\d+)+\b").expect("valid regex");
+    let version = output.and_then(|output| {
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        re.find(&text).and_then(|m| parse_version(m.as_str()))
+    });
+    probed.insert(path.to_path_buf(), version.clone());
+    version
+}
+
+/// Check that the commands a script requires are installed, in the
+/// directories listed in `search` as in `PATH`, reporting all the problems
+/// together.
+pub(crate) fn check(
+    name: Option<&str>,
+    requirements: &[String],
+    search: &OsStr,
+) -> Result<(), std::io::Error> {
+    let mut problems = Vec::new();
+    let mut missing = false;
+    for requirement in requirements {
+        let parsed = parse(requirement)?;
+        let path = match resolve_in(search, parsed.command) {
+            Ok(path) => path,
+            Err(_) => {
+                missing = true;
+                problems.push(format!("{}: command not found", parsed.command));
+                continue;
+            }
+        };
+        if let Some((op, required)) = parsed.version.as_ref() {
+            match probe(&path) {
+                Some(found) if op.matches(&found, required) => {}
+                Some(found) => problems.push(format!(
+                    "{}: version {} at {} does not satisfy {}",
+                    parsed.command,
+                    format_version(&found),
+                    path.display(),
+                    requirement.trim()
+                )),
+                None => problems.push(format!(
+                    "{}: could not determine the version of {} with --version",
+                    parsed.command,
+                    path.display()
+                )),
+            }
+        }
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let kind = if missing {
+        std::io::ErrorKind::NotFound
+    } else {
+        std::io::ErrorKind::Unsupported
+    };
+    Err(std::io::Error::new(
+        kind,
+        format!(
+            "{} requirements not met:\n  {}",
+            describe(name),
+            problems.join("\n  ")
+        ),
+    ))
+}
@@ -0,0 +1,54 @@
+use crate::internals::{find_in, is_executable};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Executables found so far, by `PATH` and name.
+static RESOLVED: Mutex<Option<HashMap<(OsString, String), PathBuf>>> = Mutex::new(None);
+
+/// Find the executable that a script would run for the command `name`,
+/// searching the `PATH` scripts inherit from this process, so that Rust
+/// code and scripts agree on which binary is used.
+///
+/// Names containing a `/` are returned as they are if they refer to an
+/// executable.  Results are cached for the lifetime of the process, as long
+/// as `PATH` is unchanged and the executable still exists; an error of kind
+/// `NotFound` is returned if there is no such command.  See
+/// [`Script::resolve_tool`](struct.Script.html#method.resolve_tool) to
+/// honor the `env` option of a script.
+///
+/// ```
+/// use sh_inline::*;
+/// let sh = resolve_tool("sh")?;
+/// assert_eq!(bash_output!(r"command -v sh")?.trim_end(), sh.to_str().unwrap());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn resolve_tool(name: &str) -> Result<PathBuf, std::io::Error> {
+    resolve_in(&std::env::var_os("PATH").unwrap_or_default(), name)
+}
+
+/// Find the executable for `name` in the directories listed in `path`.
+pub(crate) fn resolve_in(path: &OsStr, name: &str) -> Result<PathBuf, std::io::Error> {
+    let not_found = || {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("command not found: {}", name),
+        )
+    };
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return Some(path)
+            .filter(|p| is_executable(p))
+            .ok_or_else(not_found);
+    }
+    let key = (path.to_os_string(), name.to_string());
+    let mut resolved = RESOLVED.lock().unwrap();
+    let resolved = resolved.get_or_insert_with(HashMap::new);
+    if let Some(found) = resolved.get(&key).filter(|p| is_executable(p)) {
+        return Ok(found.clone());
+    }
+    let found = find_in(path, name).ok_or_else(not_found)?;
+    resolved.insert(key, found.clone());
+    Ok(found)
+}
@@ -0,0 +1,120 @@
+use crate::error::Error;
+use regex::Regex;
+use std::time::Duration;
+
+/// Standard error output of common transient network failures.
+const TRANSIENT: &[&str] = &[
+    "Connection reset",
+    "Connection timed out",
+    "Temporary failure in name resolution",
+    "Could not resolve host",
+    "TLS handshake timeout",
+];
+
+/// Runs a script again when it fails in a way which looks transient, such
+/// as a dropped network connection, while failing fast on other errors.
+///
+/// A failure is transient if the standard error of the script matches one
+/// of the patterns, which include messages such as `Connection reset` and
+/// `Temporary failure in name resolution` unless cleared with
+/// [`no_default_patterns`](#method.no_default_patterns).  Only the part of
+/// standard error kept in the [`Error`] is checked, so scripts have to be
+/// run with the `stderr_tail` option or [`Config::stderr_tail`].
+///
+/// This retries whole scripts from Rust; the `retry` shell function of the
+/// `helpers` option retries a single command regardless of how it failed.
+///
+/// ```
+/// use sh_inline::*;
+/// use std::time::Duration;
+/// let dir = tempfile::tempdir()?;
+/// let count = dir.path().join("count");
+/// let retry = Retry::new(3).delay(Duration::from_millis(10));
+/// retry.run(|| {
+///     bash!(
+///         stderr_tail = 4096,
+///         r#"echo x >> "${count}"
+///         if [ "$(wc -l < "${count}")" -lt 2 ]; then
+///             echo "curl: (56) Connection reset by peer" >&2; exit 56
+///         fi"#,
+///         count
+///     )
+/// })?;
+/// assert_eq!(std::fs::read_to_string(&count)?, "x\nx\n");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// [`Error`]: enum.Error.html
+/// [`Config::stderr_tail`]: struct.Config.html#method.stderr_tail
+#[derive(Debug, Clone)]
+pub struct Retry {
+    attempts: u32,
+    delay: Duration,
+    /// Whether the `TRANSIENT` messages are matched
+    defaults: bool,
+    patterns: Vec<Regex>,
+}
+
+impl Retry {
+    /// Make at most `attempts` attempts, one second apart, retrying the
+    /// default transient failures.
+    pub fn new(attempts: u32) -> Self {
+        Retry {
+            attempts,
+            delay: Duration::from_secs(1),
+            defaults: true,
+            patterns: Vec::new(),
+        }
+    }
+
+    /// Wait `delay` between attempts.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Also treat failures whose standard error matches the regular
+    /// expression `pattern` as transient.
+    pub fn pattern(mut self, pattern: &str) -> Result<Self, std::io::Error> {
+        let re = Regex::new(pattern)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        self.patterns.push(re);
+        Ok(self)
+    }
+
+    /// Forget the default patterns, retrying only failures matching those
+    /// added with [`pattern`](#method.pattern).
+    pub fn no_default_patterns(mut self) -> Self {
+        self.defaults = false;
+        self
+    }
+
+    /// Whether `e`, as returned by running a script, is a transient failure
+    /// worth retrying.
+    pub fn is_transient(&self, e: &std::io::Error) -> bool {
+        let stderr = match Error::of(e) {
+            Some(e) => String::from_utf8_lossy(e.stderr()),
+            None => return false,
+        };
+        (self.defaults && TRANSIENT.iter().any(|m| stderr.contains(m)))
+            || self.patterns.iter().any(|p| p.is_match(&stderr))
+    }
+
+    /// Call `f`, which runs a script, again while it fails transiently and
+    /// attempts remain, returning the first success or the last error.
+    pub fn run<T, F>(&self, mut f: F) -> Result<T, std::io::Error>
+    where
+        F: FnMut() -> Result<T, std::io::Error>,
+    {
+        let mut attempt = 1;
+        loop {
+            match f() {
+                Err(e) if attempt < self.attempts && self.is_transient(&e) => {
+                    attempt += 1;
+                    std::thread::sleep(self.delay);
+                }
+                r => return r,
+            }
+        }
+    }
+}
@@ -0,0 +1,2416 @@
+use crate::artifacts::{Bundle, Tee};
+use crate::config::{Config, Glob};
+use crate::env::{self, EnvDiff};
+use crate::error::Error;
+use crate::eval::EvalError;
+use crate::fast;
+use crate::flight;
+use crate::input::ToStdin;
+use crate::internals::{self, CommandArg};
+use crate::limits::{Limits, SANITIZED_ENV_KEEP};
+use crate::output::BashOutput;
+use crate::outputs::{self, OutputCommands};
+use crate::plan::Plan;
+use crate::policy::{Policy, PolicyInput};
+use crate::quoting::{self, QuotingProfile};
+use crate::recording::Recording;
+use crate::requires;
+use crate::resolve;
+use crate::seatbelt::Seatbelt;
+use crate::secret::Secret;
+use crate::shell_arg::ToShellArg;
+use crate::spawn::{BashChild, Lines};
+use crate::steps::Markers;
+use crate::strict::StrictMode;
+use crate::systemd::SystemdScope;
+use crate::tempfiles::{self, Temp, TempFile};
+use crate::template;
+use crate::timeout::{Timeout, TimeoutMethod};
+use crate::version::{self, BashFeature};
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Default time between `SIGTERM` and `SIGKILL` for the `timeout` option.
+const DEFAULT_TIMEOUT_GRACE: Duration = Duration::from_secs(5);
+
+/// Callback deciding whether a script may be executed.
+type Approval = Box<dyn FnOnce(&Plan) -> bool>;
+
+/// Callback converting the standard error of a failed script into an error.
+type StderrHook = Box<dyn FnOnce(&str) -> Option<std::io::Error>>;
+
+/// A fragment of shell script along with its variable bindings and options.
+/// The macros expand to calls on this builder; options given as `key = value`
+/// before the script map onto the method of the same name.
+///
+/// Using it directly is useful when the script text is only known at
+/// runtime, for instance when it is loaded from a file or assembled per
+/// platform.  Values are bound with the same conversions as in the macros.
+///
+/// ```
+/// use sh_inline::*;
+/// let mut script = String::from("set -- \"${dir}\"/*\n");
+/// if cfg!(target_os = "linux") {
+///     script.push_str("test \"$#\" -ge 1\n");
+/// }
+/// script.push_str(r#"echo "${greeting}""#);
+/// let dir = std::path::Path::new("/");
+/// let out = Script::new(&script)
+///     .bind("dir", dir)
+///     .bind("greeting", "hello")
+///     .output()?;
+/// assert_eq!(out, "hello\n");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Default)]
+pub struct Script {
+    script: String,
+    bindings: Vec<(String, CommandArg)>,
+    daemonize: bool,
+    parent_death_signal: Option<i32>,
+    private_tmp: bool,
+    isolated_home: bool,
+    scratch: Option<Scratch>,
+    helpers: bool,
+    shopt: Vec<String>,
+    safe_ifs: Option<bool>,
+    glob: Option<Glob>,
+    xtrace: Option<OwnedFd>,
+    systemd_scope: Option<SystemdScope>,
+    timeout: Option<Duration>,
+    timeout_grace: Option<Duration>,
+    timeout_method: TimeoutMethod,
+    deadline: Option<Instant>,
+    approve: Option<Approval>,
+    policy: Option<Policy>,
+    prefix: Option<String>,
+    dedent: bool,
+    template: bool,
+    stdin: Option<Result<Stdio, std::io::Error>>,
+    stdout: Option<Stdio>,
+    stderr: Option<Stdio>,
+    env_keep: Option<Vec<String>>,
+    bash_features: Vec<BashFeature>,
+    fast_path: Option<bool>,
+    bash: Option<PathBuf>,
+    seatbelt: Option<Seatbelt>,
+    rebase_paths: Option<Rebase>,
+    on_stderr: Option<StderrHook>,
+    name: Option<String>,
+    single_flight: bool,
+    env_capture: Option<OwnedFd>,
+    reproducible: bool,
+    trim: bool,
+    prelude: Vec<String>,
+    epilogue: Vec<String>,
+    on_exit: Vec<String>,
+    recording: Option<Recording>,
+    time_vars: bool,
+    stderr_tail: Option<usize>,
+    markers: Markers,
+    cwd: Option<PathBuf>,
+    env: Vec<(OsString, OsString)>,
+    pipe_stderr: bool,
+    limits: Option<Limits>,
+    requires: Vec<String>,
+    tool_dirs: Option<Vec<PathBuf>>,
+    posix: bool,
+    powershell: bool,
+    shell: Option<String>,
+    artifacts: Option<PathBuf>,
+    strict: Option<StrictMode>,
+    trace: Option<bool>,
+    redact: Vec<String>,
+    bind_env: bool,
+    #[cfg(target_os = "freebsd")]
+    jail: Option<i32>,
+}
+
+/// Variables which are readonly or have special meaning to bash, and so
+/// cannot (usefully) be bound.
+const SPECIAL_VARIABLES: &[&str] = &[
+    "BASH",
+    "BASHOPTS",
+    "BASHPID",
+    "BASH_ALIASES",
+    "BASH_ARGC",
+    "BASH_ARGV",
+    "BASH_ARGV0",
+    "BASH_CMDS",
+    "BASH_COMMAND",
+    "BASH_ENV",
+    "BASH_LINENO",
+    "BASH_REMATCH",
+    "BASH_SOURCE",
+    "BASH_SUBSHELL",
+    "BASH_VERSINFO",
+    "BASH_VERSION",
+    "BASH_XTRACEFD",
+    "DIRSTACK",
+    "EPOCHREALTIME",
+    "EPOCHSECONDS",
+    "EUID",
+    "FUNCNAME",
+    "GROUPS",
+    "HISTCMD",
+    "HOSTNAME",
+    "HOSTTYPE",
+    "IFS",
+    "LINENO",
+    "MACHTYPE",
+    "OLDPWD",
+    "OPTARG",
+    "OPTIND",
+    "OSTYPE",
+    "PIPESTATUS",
+    "PPID",
+    "PS4",
+    "PWD",
+    "RANDOM",
+    "REPLY",
+    "SECONDS",
+    "SHELLOPTS",
+    "SHLVL",
+    "SRANDOM",
+    "UID",
+];
+
+/// How relative `Path` bindings are resolved; see the `rebase_paths` option.
+///
+/// By default paths are bound as given, so relative paths are interpreted
+/// against the working directory of the script, which may differ from that
+/// of the calling process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rebase {
+    /// Make relative paths absolute against the working directory of the
+    /// calling process.
+    Parent,
+    /// Make relative paths absolute against `dir`, e.g. the working directory
+    /// the script will run in.
+    Dir(PathBuf),
+    /// Resolve all paths with `canonicalize()`, following symbolic links;
+    /// this fails if a path doesn't exist.
+    Canonical,
+}
+
+impl Rebase {
+    fn apply(&self, path: &Path) -> Result<PathBuf, std::io::Error> {
+        match self {
+            _ if path.is_absolute() && *self != Rebase::Canonical => Ok(path.to_path_buf()),
+            Rebase::Parent => Ok(std::env::current_dir()?.join(path)),
+            Rebase::Dir(dir) => Ok(dir.join(path)),
+            Rebase::Canonical => path.canonicalize(),
+        }
+    }
+}
+
+/// Execute a rendered script, collecting its output streams.
+fn collect(built: Built) -> Result<BashOutput, std::io::Error> {
+    use std::io::Read;
+    fn read<R: Read + Send + 'static>(
+        r: Option<R>,
+    ) -> std::thread::JoinHandle<Result<Vec<u8>, std::io::Error>> {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut r) = r {
+                r.read_to_end(&mut buf)?;
+            }
+            Ok(buf)
+        })
+    }
+    let Built {
+        mut cmd,
+        timeout,
+        rendered,
+        name,
+        markers,
+        output_file,
+        ..
+    } = built;
+    let start = Instant::now();
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| Error::spawn(e, name.as_deref(), &rendered))?;
+    let stdout = child.stdout.take();
+    let stdout = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let parsed = match stdout {
+            Some(stdout) if markers.active() => markers.filter(stdout, &mut buf, start)?,
+            Some(mut stdout) => {
+                stdout.read_to_end(&mut buf)?;
+                Default::default()
+            }
+            None => Default::default(),
+        };
+        Ok::<_, std::io::Error>((buf, parsed))
+    });
+    let stderr = read(child.stderr.take());
+    let status = wait_status(&mut child, timeout, name.as_deref(), &rendered)?;
+    let duration = start.elapsed();
+    let (stdout, mut parsed) = stdout.join().expect("output reader thread panicked")?;
+    if let Some(file) = output_file {
+        let contents = std::fs::read_to_string(file.path())?;
+        parsed.outputs.extend(outputs::parse_file(&contents)?);
+    }
+    Ok(BashOutput {
+        stdout,
+        stderr: stderr.join().expect("output reader thread panicked")?,
+        status,
+        duration,
+        steps: parsed.steps,
+        outputs: parsed.outputs,
+    })
+}
+
+/// Wait for a spawned script, enforcing its timeout if any.
+fn wait_status(
+    child: &mut std::process::Child,
+    timeout: Option<Timeout>,
+    name: Option<&str>,
+    rendered: &str,
+) -> Result<std::process::ExitStatus, std::io::Error> {
+    let start = Instant::now();
+    let status = match timeout {
+        None => Some(child.wait()?),
+        Some(t) if t.method == TimeoutMethod::Builtin => t.wait(child)?,
+        Some(t) => t.check_coreutils(child.wait()?, start.elapsed()),
+    };
+    status.ok_or_else(|| Error::timed_out(timeout.unwrap().duration, name, rendered))
+}
+
+/// Variables kept from the calling process by the `reproducible` option.
+const REPRODUCIBLE_ENV_KEEP: &[&str] = &["PATH", "SOURCE_DATE_EPOCH"];
+
+/// The library injected by the `helpers` option.
+const HELPERS: &str = include_str!("helpers.sh");
+
+/// The environment variable naming the file the `ERR` trap writes to.
+const ERR_FILE_VAR: &str = "SH_INLINE_ERR_FILE";
+
+/// The arguments for PowerShell, which is then given the path of the script.
+const PWSH_ARGS: &[&str] = &["-NoLogo", "-NoProfile", "-NonInteractive", "-File"];
+
+/// The prefix of the environment variables for the `bind_env` option,
+/// which are numbered.
+const BIND_ENV_PREFIX: &str = "SH_INLINE_BIND_";
+
+/// The prefix of the environment variables passing [`Secret`] bindings,
+/// whose values are redacted from plans and artifacts.
+///
+/// [`Secret`]: struct.Secret.html
+pub(crate) const SECRET_ENV_PREFIX: &str = "SH_INLINE_SECRET_";
+
+/// Records the line and status of the last failing command; `errtrace`
+/// makes it apply within functions and subshells too.
+const ERR_TRAP: &str = r#"set -E
+trap '{ printf "%s %s\n" "${LINENO}" "$?" >"${SH_INLINE_ERR_FILE:-/dev/null}"; } 2>/dev/null' ERR
+"#;
+
+/// A scratch directory on tmpfs which is exposed to the script as
+/// `${scratch}` and torn down after execution; see the `scratch` option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scratch {
+    /// A directory created under `/dev/shm`; this works unprivileged but
+    /// is not size-capped beyond the limit of `/dev/shm` itself.
+    Shm,
+    /// A dedicated tmpfs mount limited to `size` bytes.  Mounting requires
+    /// `CAP_SYS_ADMIN` (Linux only).
+    Tmpfs {
+        /// Maximum size of the filesystem in bytes
+        size: u64,
+    },
+}
+
+impl Script {
+    /// Create a new script from its source text.
+    pub fn new<S: AsRef<str>>(script: S) -> Self {
+        Script {
+            script: script.as_ref().to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Bind `value` as the shell variable `name`, quoting as necessary.
+    /// Names of special or readonly bash variables such as `UID` or `IFS`
+    /// are rejected when the script is rendered.
+    pub fn bind<T: ToShellArg + ?Sized>(mut self, name: &str, value: &T) -> Self {
+        self.bindings
+            .push((name.to_string(), value.to_shell_arg().0));
+        self
+    }
+
+    /// Run the script in the directory `dir`, rather than the current
+    /// directory of the calling process.
+    pub fn cwd<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.cwd = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Detach the script from the calling process via the classic double
+    /// `fork()`, `setsid()` and `chdir("/")` sequence, with standard output
+    /// and error redirected to `/dev/null`.  The script is still read from
+    /// standard input, so only the output streams are redirected.
+    ///
+    /// Execution "succeeds" as soon as the intermediate process has exited;
+    /// the script keeps running in the background.
+    pub fn daemonize(mut self, daemonize: bool) -> Self {
+        self.daemonize = daemonize;
+        self
+    }
+
+    /// Have the kernel send `signal` (e.g. `libc::SIGTERM`) to the script when
+    /// its parent exits, so that helper scripts don't outlive a crashed
+    /// process.  Note that on Linux the "parent" is the thread which spawned
+    /// the script, not the whole process.
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    pub fn parent_death_signal(mut self, signal: i32) -> Self {
+        self.parent_death_signal = Some(signal);
+        self
+    }
+
+    /// Point `TMPDIR` at a fresh private directory, so that files created
+    /// via e.g. `mktemp` can't collide with other scripts.  The directory is
+    /// removed along with the returned `Command` (or once execution finishes
+    /// for the other macros), or by [`sweep_temp_files`] if the process is
+    /// killed first.
+    ///
+    /// [`sweep_temp_files`]: fn.sweep_temp_files.html
+    pub fn private_tmp(mut self, private_tmp: bool) -> Self {
+        self.private_tmp = private_tmp;
+        self
+    }
+
+    /// Point `HOME`, `XDG_CONFIG_HOME`, `XDG_CACHE_HOME`, `XDG_DATA_HOME` and
+    /// `XDG_STATE_HOME` at fresh directories so that scripts touching dotfiles
+    /// can't read or modify the real ones, e.g. in tests.  The directories are
+    /// removed like those of `private_tmp`.
+    pub fn isolated_home(mut self, isolated_home: bool) -> Self {
+        self.isolated_home = isolated_home;
+        self
+    }
+
+    /// Provide a tmpfs-backed scratch directory for I/O-heavy intermediate
+    /// work, bound as the shell variable `scratch`.
+    pub fn scratch(mut self, scratch: Scratch) -> Self {
+        self.scratch = Some(scratch);
+        self
+    }
+
+    /// Inject a small library of helper functions into the prelude:
+    ///
+    /// - `die <message...>`: Print an error to stderr and exit unsuccessfully.
+    /// - `log <message...>`: Print a timestamped message to stderr.
+    /// - `retry <attempts> <delay> <command...>`: Run a command until it
+    ///   succeeds, sleeping `<delay>` seconds between tries.  See
+    ///   [`Retry`](struct.Retry.html) for retrying only transient failures
+    ///   of a whole script.
+    /// - `require_cmd <command...>`: Exit unless all the commands exist.
+    /// - `tmpfile_tracked <variable>`: Create a temporary file, storing its
+    ///   path in the named variable; it is removed when the script exits.
+    ///
+    /// Note that the library relies on a `trap` on `EXIT` for its cleanup;
+    /// use the `epilogue` option rather than replacing it.
+    pub fn helpers(mut self, helpers: bool) -> Self {
+        self.helpers = helpers;
+        self
+    }
+
+    /// Enable additional bash options (as with `shopt -s`, e.g.
+    /// `inherit_errexit`, `nullglob`, `failglob` or `lastpipe`) after strict
+    /// mode is set.  These are added to those from the global [`Config`].
+    ///
+    /// [`Config`]: struct.Config.html
+    pub fn shopt<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.shopt
+            .extend(names.into_iter().map(|s| s.as_ref().to_string()));
+        self
+    }
+
+    /// Set `IFS=$'\n\t'` so that word splitting only happens on newlines and
+    /// tabs, overriding the global [`Config`].
+    ///
+    /// [`Config`]: struct.Config.html
+    pub fn safe_ifs(mut self, safe_ifs: bool) -> Self {
+        self.safe_ifs = Some(safe_ifs);
+        self
+    }
+
+    /// Control pathname expansion, overriding the global [`Config`].
+    ///
+    /// [`Config`]: struct.Config.html
+    pub fn glob(mut self, glob: Glob) -> Self {
+        self.glob = Some(glob);
+        self
+    }
+
+    /// Trace the script body (`set -x`) to `fd` (e.g. a log file or the
+    /// write end of a pipe) via `BASH_XTRACEFD`, so that the trace doesn't
+    /// end up in the standard error of the script.  The variable bindings
+    /// are not traced.
+    ///
+    /// When passing a pipe, make sure to read from it while the script runs;
+    /// a full pipe blocks the script.
+    pub fn xtrace<F: Into<OwnedFd>>(mut self, fd: F) -> Self {
+        self.xtrace = Some(fd.into());
+        self
+    }
+
+    /// Run the script inside a transient systemd scope with the given
+    /// resource limits, if systemd is available.
+    pub fn systemd_scope(mut self, scope: SystemdScope) -> Self {
+        self.systemd_scope = Some(scope);
+        self
+    }
+
+    /// Terminate the script (with `SIGTERM`, then `SIGKILL` after
+    /// `timeout_grace`) if it runs longer than `timeout`, returning an error
+    /// of kind `TimedOut`.  How this is enforced depends on `timeout_method`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Time between `SIGTERM` and `SIGKILL` when the `timeout` expires;
+    /// defaults to 5 seconds.
+    pub fn timeout_grace(mut self, grace: Duration) -> Self {
+        self.timeout_grace = Some(grace);
+        self
+    }
+
+    /// How the `timeout` is enforced; see [`TimeoutMethod`].
+    ///
+    /// [`TimeoutMethod`]: enum.TimeoutMethod.html
+    pub fn timeout_method(mut self, method: TimeoutMethod) -> Self {
+        self.timeout_method = method;
+        self
+    }
+
+    /// Terminate the script if it is still running at `deadline`, as with
+    /// `timeout`.  This allows a single overall budget to be shared by
+    /// several scripts run in sequence; if both are given the earlier limit
+    /// applies, and if the deadline has already passed the script is not
+    /// run at all.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Call `approve` with the [`Plan`] for the script before anything is
+    /// executed; if it returns `false`, execution fails with an error of
+    /// kind `PermissionDenied`.  This allows e.g. interactive confirmation
+    /// or policy checks before running privileged scripts.  It is not called
+    /// by `bash_plan!`.
+    ///
+    /// [`Plan`]: struct.Plan.html
+    pub fn approve<F: FnOnce(&Plan) -> bool + 'static>(mut self, approve: F) -> Self {
+        self.approve = Some(Box::new(approve));
+        self
+    }
+
+    /// Only execute the script if allowed by `policy`, in addition to any
+    /// policy installed globally.
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Bind variables as `{prefix}{name}` so they cannot clobber environment
+    /// or special variables such as `PATH` or `UID`.  References to bound
+    /// variables in the script (`$name`, `${name}`, `${name:-...}`, `${#name}`
+    /// etc.) are rewritten to use the prefixed name; note that this also
+    /// applies inside single quotes and after a backslash.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Remove the leading whitespace common to all non-blank lines of the
+    /// script, along with a leading newline, so that scripts can be indented
+    /// along with the surrounding Rust code without affecting e.g. heredocs.
+    pub fn dedent(mut self, dedent: bool) -> Self {
+        self.dedent = dedent;
+        self
+    }
+
+    /// Expand `{{#if name}}...{{else}}...{{/if}}` and
+    /// `{{#each name}}...{{/each}}` blocks in the script using the values of
+    /// the bindings.  A binding is true unless it is empty or `false`; within
+    /// `{{#each}}`, `{{this}}` is the quoted current element of a list or
+    /// value of a map (or the value itself otherwise) and `{{@index}}` its
+    /// index.
+    pub fn template(mut self, template: bool) -> Self {
+        self.template = template;
+        self
+    }
+
+    /// Connect the standard input of the script to `stdin`, e.g. a `File`,
+    /// `OwnedFd` or `Stdio`, or feed it data such as a `&str`, `&[u8]` or
+    /// the contents of a `&mut impl Read`; see [`ToStdin`].  The script
+    /// itself is then passed to bash as a `/dev/fd/N` path instead of on
+    /// standard input.  Errors reading the data are returned when the script
+    /// is run.
+    ///
+    /// [`ToStdin`]: trait.ToStdin.html
+    pub fn stdin<T: ToStdin>(mut self, stdin: T) -> Self {
+        self.stdin = Some(stdin.to_stdin());
+        self
+    }
+
+    /// Connect the standard output of the script to `stdout`.
+    pub fn stdout<T: Into<Stdio>>(mut self, stdout: T) -> Self {
+        self.stdout = Some(stdout.into());
+        self
+    }
+
+    /// Connect the standard error of the script to `stderr`.
+    pub fn stderr<T: Into<Stdio>>(mut self, stderr: T) -> Self {
+        self.stderr = Some(stderr.into());
+        self
+    }
+
+    /// Add `lines` to be run before the script, after strict mode is set
+    /// up and the variables are bound; may be given more than once.
+    pub fn prelude<S: Into<String>>(mut self, lines: S) -> Self {
+        self.prelude.push(lines.into());
+        self
+    }
+
+    /// Add `lines` to be run when the script exits, whether or not it
+    /// succeeds, such as mandatory cleanup; may be given more than once.  The
+    /// lines run from a `trap` on `EXIT` in a function, where `$?` initially
+    /// holds the exit status of the script.  Scripts must not replace that
+    /// trap.
+    pub fn epilogue<S: Into<String>>(mut self, lines: S) -> Self {
+        self.epilogue.push(lines.into());
+        self
+    }
+
+    /// Register a cleanup `snippet`, such as `rm -rf "${tmp}"`, to run when
+    /// the script exits; may be given more than once.  All the cleanups are
+    /// combined into a single `trap` on `EXIT` and run in reverse order of
+    /// registration, after any `epilogue`.  Each runs in a subshell on a
+    /// best-effort basis: failures are ignored and don't prevent the others
+    /// from running, nor change the exit status of the script.
+    pub fn on_exit<S: Into<String>>(mut self, snippet: S) -> Self {
+        self.on_exit.push(snippet.into());
+        self
+    }
+
+    /// Record the execution or replay it from a recording, overriding
+    /// [`Config::recording`](struct.Config.html#method.recording); see
+    /// [`Recording`](enum.Recording.html).
+    pub fn recording(mut self, recording: Recording) -> Self {
+        self.recording = Some(recording);
+        self
+    }
+
+    /// Trim surrounding whitespace, such as the trailing newline, from the
+    /// output returned by [`bash_output`](macro.bash_output.html).
+    pub fn trim(mut self, trim: bool) -> Self {
+        self.trim = trim;
+        self
+    }
+
+    /// Make the behavior of the script independent of the calling
+    /// environment as far as possible, for build systems needing identical
+    /// output across machines: the environment is cleared except for `PATH`,
+    /// `SOURCE_DATE_EPOCH` and any `env_keep` variables, `LC_ALL=C` and
+    /// `TZ=UTC` are set, the umask is `022`, and bash is run with `--norc
+    /// --noprofile`.
+    pub fn reproducible(mut self, reproducible: bool) -> Self {
+        self.reproducible = reproducible;
+        self
+    }
+
+    /// If the script fails, write what is needed to debug it into a new
+    /// subdirectory of `dir`, e.g. for a CI system to collect; overrides
+    /// [`Config::artifacts`](struct.Config.html#method.artifacts).  The
+    /// subdirectory is named after the `name` option, or `script`, with a
+    /// number appended to make it unique, e.g. `dir/deploy-1`, and contains:
+    ///
+    /// - `script.sh`: The script as rendered, including the prelude.
+    /// - `env`: The environment the script was started with, as
+    ///   `NAME=value` lines with the values quoted.  Note that this includes
+    ///   any secrets passed in the environment, other than the values of
+    ///   [`Secret`](struct.Secret.html) bindings.
+    /// - `stdout` and `stderr`: The output of the script, unless redirected.
+    /// - `status`: `KEY=value` lines: `name`, `exit_code` or `signal`, `line`
+    ///   if the failing line is known, and `started` (seconds since the Unix
+    ///   epoch) and `duration` (seconds).
+    /// - `environment`: The [`Fingerprint`](struct.Fingerprint.html) of the
+    ///   environment, with [`Config::fingerprint`](struct.Config.html#method.fingerprint).
+    ///
+    /// Errors writing the artifacts are ignored in favor of the failure of
+    /// the script.  Artifacts are written by the macros which wait for the
+    /// script, such as [`bash`](macro.bash.html) and
+    /// [`bash_output`](macro.bash_output.html).
+    ///
+    /// ```
+    /// use sh_inline::*;
+    /// let dir = tempfile::tempdir()?;
+    /// let e = bash!(artifacts = dir.path(), name = "deploy", r"echo deploying; exit 3");
+    /// assert!(e.is_err());
+    /// let status = std::fs::read_to_string(dir.path().join("deploy-1/status"))?;
+    /// assert!(status.starts_with("name=deploy\nexit_code=3\n"));
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn artifacts<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.artifacts = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the shell options at the start of the script, rather than those
+    /// from the global [`Config`](struct.Config.html); see
+    /// [`StrictMode`](struct.StrictMode.html).
+    pub fn strict(mut self, strict: StrictMode) -> Self {
+        self.strict = Some(strict);
+        self
+    }
+
+    /// Print the fully rendered script, including the variable bindings, to
+    /// standard error before it runs; by default this is done if the
+    /// `SH_INLINE_TRACE` environment variable is set to anything but `0`.
+    /// See also `redact`.
+    pub fn trace(mut self, trace: bool) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Show the values of the named bindings as `<redacted>` when the
+    /// script is traced.  The names must be bound, so that a typo can't
+    /// reveal a secret.
+    ///
+    /// ```
+    /// use sh_inline::*;
+    /// let token = "hunter2";
+    /// bash!(trace = true, redact = ["token"], r#"test -n "${token}""#, token)?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn redact<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.redact
+            .extend(names.into_iter().map(|s| s.as_ref().to_string()));
+        self
+    }
+
+    /// Pass the values of bindings to the script through environment
+    /// variables rather than writing them into its text, which then only
+    /// refers to them; the variables are unset again before the script
+    /// proper runs, so the commands it starts don't inherit them.  This
+    /// keeps the values out of error messages, traces and plans of the
+    /// script, and avoids limits on the size of scripts for large values.
+    /// The fast path is not used.
+    ///
+    /// ```
+    /// use sh_inline::*;
+    /// let password = "hunter2";
+    /// let script = bash_script!(bind_env = true, r#"test -n "${password}""#, password)?;
+    /// assert!(!script.contains(password));
+    /// bash!(bind_env = true, r#"test "${password}" = hunter2"#, password)?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn bind_env(mut self, bind_env: bool) -> Self {
+        self.bind_env = bind_env;
+        self
+    }
+
+    /// Apply the hardening profile `limits`, such as
+    /// [`Limits::untrusted`](struct.Limits.html#method.untrusted), rather
+    /// than the one from the global [`Config`](struct.Config.html), if any.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Export `SH_INLINE_START_EPOCH`, the time the script was started in
+    /// seconds since the Unix epoch, and if there is a `timeout` or
+    /// `deadline`, `SH_INLINE_DEADLINE_EPOCH`, the time at which it will be
+    /// terminated.  Long-running scripts can use these to check their
+    /// remaining budget and stop cleanly before the hard limit.
+    pub fn time_vars(mut self, time_vars: bool) -> Self {
+        self.time_vars = time_vars;
+        self
+    }
+
+    /// Capture the last `bytes` of standard error, which is still shown
+    /// unless redirected, to include in the [`Error`] if the script fails.
+    /// Overrides [`Config::stderr_tail`].
+    ///
+    /// [`Error`]: enum.Error.html
+    /// [`Config::stderr_tail`]: struct.Config.html#method.stderr_tail
+    pub fn stderr_tail(mut self, bytes: usize) -> Self {
+        self.stderr_tail = Some(bytes);
+        self
+    }
+
+    /// Remove `::step::NAME` marker lines from standard output, recording
+    /// the steps they delimit in [`BashOutput::steps`]; see [`Step`].
+    /// Standard output is passed on as usual unless redirected.
+    ///
+    /// [`BashOutput::steps`]: struct.BashOutput.html#structfield.steps
+    /// [`Step`]: struct.Step.html
+    pub fn steps(mut self, steps: bool) -> Self {
+        self.markers.steps = steps;
+        self
+    }
+
+    /// Call `hook` with the name of each step as it starts, for showing
+    /// progress; implies `steps`.
+    pub fn on_step<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        self.markers.steps = true;
+        self.markers.on_step = Some(Box::new(hook));
+        self
+    }
+
+    /// Collect named values set by the script, with commands on standard
+    /// output or through a file, into [`BashOutput::outputs`]; see
+    /// [`OutputCommands`].
+    ///
+    /// [`BashOutput::outputs`]: struct.BashOutput.html#structfield.outputs
+    /// [`OutputCommands`]: struct.OutputCommands.html
+    pub fn outputs(mut self, outputs: OutputCommands) -> Self {
+        self.markers.outputs = Some(outputs);
+        self
+    }
+
+    /// Clear the environment of the script except for the variables `names`,
+    /// which keep their values from the calling process if set.
+    pub fn env_keep<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.env_keep = Some(names.into_iter().map(|s| s.as_ref().to_string()).collect());
+        self
+    }
+
+    /// Set environment variables for the script, which unlike bindings are
+    /// inherited by the commands it runs.  Applied after `env_keep`, so the
+    /// variables are set even if the rest of the environment is cleared.
+    pub fn env<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.env.extend(
+            vars.into_iter()
+                .map(|(k, v)| (k.as_ref().to_os_string(), v.as_ref().to_os_string())),
+        );
+        self
+    }
+
+    /// Set the environment variable `name` for the script; see `env`.
+    pub fn env_var<K: AsRef<OsStr>, V: AsRef<OsStr>>(self, name: K, value: V) -> Self {
+        self.env(std::iter::once((name, value)))
+    }
+
+    /// For [`bash_spawn`](macro.bash_spawn.html), pipe standard error
+    /// rather than inheriting it, so that it can be read from
+    /// [`BashChild::stderr`](struct.BashChild.html#method.stderr).
+    pub fn pipe_stderr(mut self, pipe_stderr: bool) -> Self {
+        self.pipe_stderr = pipe_stderr;
+        self
+    }
+
+    /// Declare bash features used by the script, failing with an error of
+    /// kind `Unsupported` before execution if the installed bash is too old.
+    pub fn bash_features<I: IntoIterator<Item = BashFeature>>(mut self, features: I) -> Self {
+        self.bash_features.extend(features);
+        self
+    }
+
+    /// Declare external commands the script needs, optionally with a
+    /// version, as in `["jq", "rsync >= 3.2"]`.  Before the script runs,
+    /// each command is looked up as by [`resolve_tool`](#method.resolve_tool)
+    /// and, if a version is given,
+    /// probed with `--version`; all unmet requirements are reported in one
+    /// error, of kind `NotFound` if a command is missing and `Unsupported`
+    /// otherwise.  The operators `>=`, `>`, `=`, `<=` and `<` are supported.
+    pub fn requires<I, S>(mut self, commands: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.requires
+            .extend(commands.into_iter().map(|s| s.as_ref().to_string()));
+        self
+    }
+
+    /// Run the script with a `PATH` made up of exactly the directories
+    /// `dirs`, in order, ignoring both the `PATH` of the calling process and
+    /// any set with the `env` option, so that only vetted tools can be run
+    /// by name.  Builtins and commands given by path are unaffected, as are
+    /// wrappers such as `timeout` added by other options.  The
+    /// directories must be absolute and must not contain `:`, or the script
+    /// fails with an error of kind `InvalidInput` before running.
+    ///
+    /// ```
+    /// use sh_inline::*;
+    /// let tools = tempfile::tempdir()?;
+    /// let out = Script::new("command -v cat || echo 'no cat'")
+    ///     .tool_dirs([tools.path()])
+    ///     .output()?;
+    /// assert_eq!(out, "no cat\n");
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn tool_dirs<I, P>(mut self, dirs: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.tool_dirs = Some(dirs.into_iter().map(|d| d.as_ref().to_path_buf()).collect());
+        self
+    }
+
+    /// Find the executable the script would run for the command `name`, as
+    /// [`resolve_tool`](fn.resolve_tool.html) does, but searching the
+    /// `tool_dirs`, or the `PATH` set with the `env` option if there is one.
+    pub fn resolve_tool(&self, name: &str) -> Result<PathBuf, std::io::Error> {
+        resolve::resolve_in(&self.search_path()?, name)
+    }
+
+    /// The `PATH` the script will run with.
+    fn search_path(&self) -> Result<OsString, std::io::Error> {
+        if let Some(dirs) = self.tool_dirs.as_ref() {
+            return tool_path(dirs);
+        }
+        Ok(self
+            .env
+            .iter()
+            .rev()
+            .find(|(k, _)| k == "PATH")
+            .map(|(_, v)| v.clone())
+            .or_else(|| std::env::var_os("PATH"))
+            .unwrap_or_default())
+    }
+
+    /// If the script is a single simple command whose words are all plain or
+    /// references to bound variables (e.g. `cp "${src}" ${dest}`), execute
+    /// the program directly rather than via bash.  The program is looked up
+    /// in the `PATH` the script would have, e.g. from `tool_dirs`.  Scripts
+    /// which use any other shell syntax, shell builtins or keywords (even
+    /// those which also exist as executables, such as `echo`), exported
+    /// functions or options such as `helpers` or `xtrace` are run by bash as
+    /// usual.
+    pub fn fast_path(mut self, fast_path: bool) -> Self {
+        self.fast_path = Some(fast_path);
+        self
+    }
+
+    /// Execute the script with the bash at `path`, e.g. from a hermetic
+    /// toolchain, rather than the configured or default one.
+    pub fn bash<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.bash = Some(path.into());
+        self
+    }
+
+    /// Run the script with the POSIX shell `/bin/sh`, such as dash or
+    /// busybox `ash`, for systems without bash; the `bash` option can point
+    /// at a different shell.  Strict mode is then `set -eu`, as `pipefail`
+    /// isn't available everywhere and is left out of the `strict` option.
+    /// Bash-only options (`shopt`, failing globs, `helpers`, `xtrace` and
+    /// `bash_features`) and bindings which need bash syntax (arrays,
+    /// associative arrays, and bytes or paths which aren't valid UTF-8; see
+    /// [`quoting::Posix`](quoting/struct.Posix.html)) are rejected with an
+    /// error of kind `InvalidInput`.  Bash-only settings from the
+    /// global [`Config`](struct.Config.html) are ignored.
+    pub fn posix(mut self, posix: bool) -> Self {
+        self.posix = posix;
+        self
+    }
+
+    /// Run the script with PowerShell (`pwsh` in the `PATH` of the
+    /// script, unless the `shell` or `bash` option says otherwise), as
+    /// [`pwsh`](macro.pwsh.html) does.  Bindings become PowerShell
+    /// variables, quoted as by [`quoting::PowerShell`](quoting/struct.PowerShell.html),
+    /// and strict mode sets `$ErrorActionPreference = 'Stop'` (also for
+    /// native commands, with PowerShell 7.3 or later) and
+    /// `Set-StrictMode -Version Latest`.  The options which rely on bash
+    /// syntax, such as `on_exit`, `scratch` and `bind_env`, are rejected with
+    /// an error of kind `InvalidInput`.
+    pub fn powershell(mut self, powershell: bool) -> Self {
+        self.powershell = powershell;
+        self
+    }
+
+    /// Reject the options and bindings which `posix` or `powershell` don't
+    /// support.
+    fn check_dialect(&self) -> Result<(), std::io::Error> {
+        let (shell, profile): (&str, &dyn QuotingProfile) = match self.powershell {
+            true => ("PowerShell", &quoting::PowerShell),
+            false => ("POSIX sh", &quoting::Posix),
+        };
+        let unsupported = |what: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is not supported by {}", what, shell),
+            )
+        };
+        if self.powershell {
+            let options = [
+                ("posix", self.posix),
+                ("safe_ifs", self.safe_ifs.is_some()),
+                ("glob", self.glob.is_some()),
+                ("scratch", self.scratch.is_some()),
+                ("epilogue", !self.epilogue.is_empty()),
+                ("on_exit", !self.on_exit.is_empty()),
+                ("bind_env", self.bind_env),
+            ];
+            if let Some((option, _)) = options.iter().find(|(_, set)| *set) {
+                return Err(unsupported(&format!("the {} option", option)));
+            }
+        }
+        if !self.shopt.is_empty() {
+            return Err(unsupported("the shopt option"));
+        }
+        if self.glob == Some(Glob::Fail) {
+            return Err(unsupported("Glob::Fail"));
+        }
+        if self.helpers {
+            return Err(unsupported("the helpers option"));
+        }
+        if self.xtrace.is_some() {
+            return Err(unsupported("the xtrace option"));
+        }
+        if self.env_capture.is_some() {
+            return Err(unsupported("capturing the environment"));
+        }
+        if !self.bash_features.is_empty() {
+            return Err(unsupported("the bash_features option"));
+        }
+        if let Some((name, _)) = self.bindings.iter().find(|(_, v)| match v {
+            CommandArg::Secret(_) => self.powershell,
+            v => v.render(profile).is_none(),
+        }) {
+            return Err(unsupported(&format!("binding {}", name)));
+        }
+        Ok(())
+    }
+
+    /// Execute the script with the interpreter given as it would be in a
+    /// shebang line: a path, a name to look up in the `PATH` of the script,
+    /// or `/usr/bin/env NAME`, which does the same.  The lookup happens when
+    /// the script is run, honoring the `env` and `tool_dirs` options, and
+    /// fails with an error of kind `NotFound` if there is no such
+    /// executable; other forms are rejected with an error of kind
+    /// `InvalidInput`.  Takes precedence over the `bash` option.
+    ///
+    /// ```
+    /// use sh_inline::*;
+    /// let out = bash_output!(shell = "/usr/bin/env bash", r#"echo "${BASH_VERSINFO[0]}""#)?;
+    /// assert!(out.trim().parse::<u32>().is_ok());
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn shell<S: AsRef<str>>(mut self, command: S) -> Self {
+        self.shell = Some(command.as_ref().to_string());
+        self
+    }
+
+    /// Resolve the `shell` option against `search_path`.
+    fn resolve_shell(
+        shell: &str,
+        resolve: &dyn Fn(&str) -> Result<PathBuf, std::io::Error>,
+    ) -> Result<PathBuf, std::io::Error> {
+        let words = shlex::split(shell).unwrap_or_default();
+        let name = match &words[..] {
+            [env, name] if Path::new(env).file_name() == Some(OsStr::new("env")) => name,
+            [path] if path.contains('/') => return Ok(PathBuf::from(path)),
+            [name] => name,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("unsupported shell command: {:?}", shell),
+                ))
+            }
+        };
+        resolve(name)
+    }
+
+    /// Attach the script to the FreeBSD jail `jid` before it starts, which
+    /// requires privileges.  Capsicum capability mode can't be applied this
+    /// way, since it forbids executing bash itself; scripts can use a
+    /// capsicum-aware helper instead.
+    #[cfg(target_os = "freebsd")]
+    pub fn jail(mut self, jid: i32) -> Self {
+        self.jail = Some(jid);
+        self
+    }
+
+    /// Resolve relative `Path` bindings explicitly; see [`Rebase`].
+    ///
+    /// [`Rebase`]: enum.Rebase.html
+    pub fn rebase_paths(mut self, rebase: Rebase) -> Self {
+        self.rebase_paths = Some(rebase);
+        self
+    }
+
+    /// If the script fails, call `hook` with its standard error, which may
+    /// return a more specific error to be returned instead of the generic
+    /// one, e.g. for known failure messages of a tool.  Standard error is
+    /// still shown as usual.  This applies when executing the script directly
+    /// (as with `bash!`), not to a returned `Command`.
+    pub fn on_stderr<F>(mut self, hook: F) -> Self
+    where
+        F: FnOnce(&str) -> Option<std::io::Error> + 'static,
+    {
+        self.on_stderr = Some(Box::new(hook));
+        self
+    }
+
+    /// Give the script a stable name, e.g. `ostree-pull`, which is included
+    /// in error messages and passed to policies and `approve` callbacks.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Share one execution between concurrent invocations of the identical
+    /// rendered script, which all receive its result; this avoids repeating
+    /// expensive idempotent steps.  Errors are passed on to the other callers
+    /// with their kind and message only.  Note that options creating per-run
+    /// state such as `private_tmp` make each rendering distinct.  Scripts
+    /// with the `stdin`, `stdout` or `stderr` options can't be shared, and
+    /// fail with an error of kind `InvalidInput`.
+    pub fn single_flight(mut self, single_flight: bool) -> Self {
+        self.single_flight = single_flight;
+        self
+    }
+
+    /// Run the script in a macOS sandbox; see [`Seatbelt`].
+    ///
+    /// [`Seatbelt`]: struct.Seatbelt.html
+    pub fn seatbelt(mut self, seatbelt: Seatbelt) -> Self {
+        self.seatbelt = Some(seatbelt);
+        self
+    }
+
+    /// Render the script into a [`Command`] which will execute it.
+    ///
+    /// [`Command`]: https://doc.rust-lang.org/std/process/struct.Command.html
+    pub fn command(self) -> Result<Command, std::io::Error> {
+        let Built { cmd, timeout, .. } = self.prepare(Purpose::Execute)?;
+        match timeout {
+            Some(t) if t.method == TimeoutMethod::Builtin => Err(builtin_timeout_unsupported()),
+            _ => Ok(cmd),
+        }
+    }
+
+    /// Create a `tokio::process::Command` to execute the script.  Like
+    /// [`command`](#method.command), this doesn't support the builtin
+    /// timeout method.
+    #[cfg(feature = "tokio")]
+    pub fn command_async(self) -> Result<tokio::process::Command, std::io::Error> {
+        self.command().map(tokio::process::Command::from)
+    }
+
+    /// Execute the script asynchronously, resolving to an error if it exits
+    /// unsuccessfully.  The script is rendered, and checked against policies
+    /// and the `approve` callback, before the future is returned.  The
+    /// `on_stderr` and `single_flight` options are not supported.
+    #[cfg(feature = "tokio")]
+    pub fn run_async(self) -> impl std::future::Future<Output = Result<(), std::io::Error>> + Send {
+        let prepared = if self.on_stderr.is_some() || self.single_flight || self.markers.active() {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "on_stderr, single_flight, steps and outputs are not supported for asynchronous execution",
+            ))
+        } else {
+            self.prepare(Purpose::Execute).map(|b| {
+                (
+                    tokio::process::Command::from(b.cmd),
+                    b.timeout,
+                    b.name,
+                    b.rendered,
+                )
+            })
+        };
+        async move {
+            let (mut cmd, timeout, name, rendered) = prepared?;
+            let start = Instant::now();
+            let mut child = cmd
+                .spawn()
+                .map_err(|e| Error::spawn(e, name.as_deref(), &rendered))?;
+            let status = match timeout {
+                None => Some(child.wait().await?),
+                Some(t) if t.method == TimeoutMethod::Builtin => t.wait_async(&mut child).await?,
+                Some(t) => t.check_coreutils(child.wait().await?, start.elapsed()),
+            };
+            let status = status.ok_or_else(|| {
+                Error::timed_out(timeout.unwrap().duration, name.as_deref(), &rendered)
+            })?;
+            Error::check(status, name.as_deref(), Some(&rendered), None, &[], 0)
+        }
+    }
+
+    /// Render the script for execution, checking it against the policies and
+    /// with the `approve` callback.
+    fn prepare(mut self, purpose: Purpose) -> Result<Built, std::io::Error> {
+        let config = Config::current();
+        let policies = config.policy.iter().chain(self.policy.iter());
+        let bindings: Vec<(String, String)> = self
+            .bindings
+            .iter()
+            .map(|(name, value)| match value {
+                CommandArg::Secret(_) => (name.clone(), "<redacted>".to_string()),
+                value => (name.clone(), value.to_string()),
+            })
+            .collect();
+        let input = PolicyInput {
+            name: self.name.as_deref(),
+            script: &self.script,
+            bindings: &bindings,
+        };
+        for policy in policies {
+            policy.evaluate(&input)?;
+        }
+        let approve = self.approve.take();
+        let built = self.build(purpose)?;
+        if let Some(approve) = approve {
+            let plan = Plan::new(
+                &built.cmd,
+                built.rendered.clone(),
+                built.name.clone(),
+                built.env_cleared,
+            );
+            if !approve(&plan) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    format!(
+                        "execution of {} was not approved",
+                        internals::describe(built.name.as_deref())
+                    ),
+                ));
+            }
+        }
+        Ok(built)
+    }
+
+    /// Render the script into a [`Command`] for `purpose`, along with its
+    /// time limit if any; a `Builtin` one has to be enforced by the caller.
+    fn build(mut self, purpose: Purpose) -> Result<Built, std::io::Error> {
+        if let Some(rebase) = self.rebase_paths.as_ref() {
+            for (_, value) in self.bindings.iter_mut() {
+                if let CommandArg::Path(path) = value {
+                    *path = rebase.apply(path)?;
+                }
+            }
+        }
+        // Temporary files stay alive along with the command
+        let mut files = Vec::new();
+        let mut fds = Vec::new();
+        for (_, value) in self.bindings.iter_mut() {
+            if let CommandArg::TempFile(file) = value {
+                let file = file.clone();
+                *value = CommandArg::Path(file.path().to_path_buf());
+                files.push(file);
+            }
+            if let CommandArg::Fd(fd) = value {
+                let fd = std::mem::replace(fd, Err(std::io::ErrorKind::Other.into()))?;
+                *value = CommandArg::Path(format!("/dev/fd/{}", fd.as_raw_fd()).into());
+                fds.push(fd);
+            }
+        }
+        let mut timeout = self.timeout;
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "deadline passed before the bash script was run",
+                ));
+            }
+            timeout = Some(timeout.map_or(remaining, |t| t.min(remaining)));
+        }
+        let timeout = timeout.map(|d| {
+            let grace = self.timeout_grace.unwrap_or(DEFAULT_TIMEOUT_GRACE);
+            Timeout::new(self.timeout_method, d, grace)
+        });
+        let config = Config::current();
+        // The options which only apply to bash
+        let bash_only = !self.posix && !self.powershell;
+        if !bash_only {
+            self.check_dialect()?;
+        }
+        let strict = self.strict.unwrap_or(config.strict);
+        let mut args = String::new();
+        if self.powershell {
+            args.push_str(&strict.powershell_prelude());
+        } else if let Some(line) = strict.prelude(self.posix) {
+            writeln!(&mut args, "{}", line).unwrap();
+        }
+        let shopt: Vec<&String> = config
+            .shopt
+            .iter()
+            .filter(|_| bash_only)
+            .chain(self.shopt.iter())
+            .collect();
+        if !shopt.is_empty() {
+            if let Some(bad) = shopt
+                .iter()
+                .find(|s| s.is_empty() || !s.bytes().all(|c| c.is_ascii_lowercase() || c == b'_'))
+            {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("invalid shopt option name: {:?}", bad),
+                ));
+            }
+            writeln!(
+                &mut args,
+                "shopt -s {}",
+                shopt
+                    .iter()
+                    .map(|s| s.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )
+            .unwrap();
+        }
+        let mut features = self.bash_features.clone();
+        if shopt.iter().any(|s| *s == "inherit_errexit") {
+            features.push(BashFeature::InheritErrexit);
+        }
+        if self
+            .bindings
+            .iter()
+            .any(|(_, v)| matches!(v, CommandArg::Assoc(_)))
+        {
+            features.push(BashFeature::AssocArrays);
+        }
+        let search_path = self.search_path()?;
+        let render = purpose == Purpose::Render;
+        // A plan may be made where the shell isn't installed, so it shows the
+        // name of a shell which isn't found
+        let missing = |e: std::io::Error, name: &str| match e.kind() {
+            std::io::ErrorKind::NotFound if render => Ok(PathBuf::from(name)),
+            _ => Err(e),
+        };
+        let resolve =
+            |name: &str| resolve::resolve_in(&search_path, name).or_else(|e| missing(e, name));
+        let bash = match (self.shell.as_deref(), self.bash.as_ref()) {
+            (Some(shell), _) => Self::resolve_shell(shell, &resolve)?,
+            (None, Some(bash)) => bash.clone(),
+            (None, None) if self.posix => PathBuf::from("/bin/sh"),
+            (None, None) if self.powershell => resolve("pwsh")?,
+            (None, None) => internals::bash_path(&config).or_else(|e| missing(e, "bash"))?,
+        };
+        if !render {
+            if bash_only {
+                version::require(&bash, &features)?;
+            }
+            requires::check(self.name.as_deref(), &self.requires, &search_path)?;
+        }
+        if self.safe_ifs.unwrap_or(config.safe_ifs) && !self.powershell {
+            if self.posix {
+                args.push_str("IFS='\n\t'\n");
+            } else {
+                args.push_str("IFS=$'\\n\\t'\n");
+            }
+        }
+        let glob = match self.glob {
+            Some(glob) => glob,
+            None if self.posix && config.glob == Glob::Fail => Glob::Enabled,
+            None if self.powershell => Glob::Enabled,
+            None => config.glob,
+        };
+        if let Some(line) = glob.prelude() {
+            writeln!(&mut args, "{}", line).unwrap();
+        }
+        if self.helpers {
+            args.push_str(HELPERS);
+        }
+        let scratch = match self.scratch {
+            Some(kind) => {
+                let (dir, path) = match render {
+                    true => (None, ScratchDir::placeholder(kind)),
+                    false => {
+                        let dir = ScratchDir::new(kind)?;
+                        let path = dir.path().to_path_buf();
+                        (Some(dir), path)
+                    }
+                };
+                writeln!(&mut args, "scratch={}", CommandArg::Path(path)).unwrap();
+                dir
+            }
+            None => None,
+        };
+        let mut script = std::borrow::Cow::Borrowed(self.script.as_str());
+        if self.dedent {
+            script = dedent(&script).into();
+        }
+        if self.template {
+            script = template::render(&script, &self.bindings)?.into();
+        }
+        if let Some(prefix) = self.prefix.as_deref() {
+            let valid = prefix.bytes().enumerate().all(|(i, c)| {
+                c == b'_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit())
+            });
+            if !valid {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("invalid variable prefix: {:?}", prefix),
+                ));
+            }
+            for (name, _) in self.bindings.iter() {
+                let re = regex::Regex::new(&format!(r"\$(\{{[#!]?)?{}\b", regex::escape(name)))
+                    .expect("valid regex");
+                let replacement = format!("$$${{1}}{}{}", prefix, name);
+                script = re
+                    .replace_all(&script, replacement.as_str())
+                    .into_owned()
+                    .into();
+            }
+        }
+        let prefix = self.prefix.as_deref().unwrap_or_default();
+        if let Some((name, _)) = self
+            .bindings
+            .iter()
+            .find(|(name, _)| SPECIAL_VARIABLES.contains(&format!("{}{}", prefix, name).as_str()))
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "binding {}{} collides with a special bash variable; consider the prefix option",
+                    prefix, name
+                ),
+            ));
+        }
+        if let Some(name) = self
+            .redact
+            .iter()
+            .find(|r| !self.bindings.iter().any(|(name, _)| name == *r))
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("cannot redact {}: no such binding", name),
+            ));
+        }
+        let profile: &dyn QuotingProfile = match (self.posix, self.powershell) {
+            (_, true) => &quoting::PowerShell,
+            (true, false) => &quoting::Posix,
+            (false, false) => &quoting::Bash,
+        };
+        // The binding lines to replace when the script is traced
+        let mut redacted: Vec<(String, String)> = Vec::new();
+        // The variables for the bind_env option
+        let mut bound_env: Vec<(String, OsString)> = Vec::new();
+        for (name, value) in self.bindings.iter() {
+            match (value, self.powershell) {
+                (CommandArg::Array(_), false) => args.push_str("declare -a "),
+                (CommandArg::Assoc(_), false) => args.push_str("declare -A "),
+                _ => {}
+            }
+            let var = format!("{}{}", prefix, name);
+            let env_line = match (value, self.bind_env) {
+                (CommandArg::Secret(secret), _) => {
+                    Some(secret_binding(profile, &var, secret, &mut bound_env))
+                }
+                (value, true) => env_binding(profile, &var, value, &mut bound_env),
+                (_, false) => None,
+            };
+            let inline = env_line.is_none();
+            let line = env_line.unwrap_or_else(|| {
+                let rendered = value.render(profile).expect("checked by check_dialect");
+                profile.assign(&var, &rendered)
+            });
+            if self.redact.contains(name) {
+                redacted.push((line.clone(), profile.assign(&var, "<redacted>")));
+            }
+            writeln!(&mut args, "{}", line).unwrap();
+            if let (CommandArg::Heredoc(_), true, false) = (value, inline, self.powershell) {
+                if self.posix {
+                    writeln!(&mut args, "{0}{1}=${{{0}{1}%??}}", prefix, name).unwrap();
+                } else {
+                    writeln!(&mut args, "{0}{1}=${{{0}{1}%$'\\n.'}}", prefix, name).unwrap();
+                }
+            }
+        }
+        if !bound_env.is_empty() {
+            let names: Vec<&str> = bound_env.iter().map(|(k, _)| k.as_str()).collect();
+            writeln!(&mut args, "unset {}", names.join(" ")).unwrap();
+        }
+        let direct = if self.fast_path.unwrap_or(config.fast_path)
+            && !self.powershell
+            && !self.bind_env
+            && !self.helpers
+            && self.xtrace.is_none()
+            && self.env_capture.is_none()
+            && self.prelude.is_empty()
+            && self.epilogue.is_empty()
+            && self.on_exit.is_empty()
+            && self.scratch.is_none()
+        {
+            fast::direct_argv(&script, |name| {
+                let name = name.strip_prefix(prefix)?;
+                self.bindings
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| v)
+            })
+            .filter(|argv| {
+                // Functions exported to bash take precedence over executables
+                let function = OsString::from(format!("BASH_FUNC_{}%%", argv[0]));
+                let exported = self.env.iter().any(|(k, _)| *k == function)
+                    || std::env::var_os(&function).is_some();
+                !exported
+                    && (argv[0].contains('/')
+                        || resolve::resolve_in(&search_path, &argv[0]).is_ok())
+            })
+        } else {
+            None
+        };
+        let xtrace = self.xtrace;
+        // The xtrace option turns on tracing itself
+        let trace = strict.traces() && xtrace.is_none();
+        let mut argv: Vec<OsString> = Vec::new();
+        if let Some(scope) = self.systemd_scope.as_ref() {
+            if SystemdScope::available() {
+                argv.extend(scope.wrapper());
+            }
+        }
+        if let Some(seatbelt) = self.seatbelt.as_ref() {
+            argv.extend(seatbelt.wrapper()?);
+        }
+        if let Some(t) = timeout.filter(|t| t.method == TimeoutMethod::Coreutils) {
+            argv.extend(t.coreutils_wrapper());
+        }
+        match direct.as_ref() {
+            Some(direct) => argv.extend(direct.iter().map(OsString::from)),
+            None => {
+                argv.push(bash.into());
+                if self.reproducible && bash_only {
+                    argv.extend(["--norc", "--noprofile"].iter().map(OsString::from));
+                }
+                if self.powershell {
+                    argv.extend(PWSH_ARGS.iter().map(OsString::from));
+                }
+            }
+        }
+        let mut cmd = Command::new(&argv[0]);
+        cmd.args(&argv[1..]);
+        if let Some(dir) = self.cwd.as_ref() {
+            cmd.current_dir(dir);
+        }
+        if timeout.is_some_and(|t| t.method == TimeoutMethod::Builtin) {
+            // So that the whole tree can be killed when the time is up
+            cmd.process_group(0);
+        }
+        for fd in fds {
+            internals::pass_fd(&mut cmd, fd);
+        }
+        if !files.is_empty() {
+            internals::keep_alive(&mut cmd, files.clone());
+        }
+        let mut env_keep = self.env_keep.clone();
+        let artifacts = self.artifacts.take().or_else(|| config.artifacts.clone());
+        if self.reproducible {
+            env_keep
+                .get_or_insert_with(Vec::new)
+                .extend(REPRODUCIBLE_ENV_KEEP.iter().map(|s| s.to_string()));
+        }
+        let limits = self.limits.take().or_else(|| config.limits.clone());
+        if limits.as_ref().is_some_and(Limits::sanitizes_env) {
+            env_keep
+                .get_or_insert_with(Vec::new)
+                .extend(SANITIZED_ENV_KEEP.iter().map(|s| s.to_string()));
+        }
+        if let Some(names) = env_keep.as_ref() {
+            cmd.env_clear();
+            for name in names {
+                if let Some(value) = std::env::var_os(name) {
+                    cmd.env(name, value);
+                }
+            }
+        }
+        cmd.envs(self.env.iter().map(|(k, v)| (k, v)));
+        cmd.envs(bound_env);
+        if self.tool_dirs.is_some() {
+            cmd.env("PATH", &search_path);
+        }
+        if self.time_vars {
+            let start = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            cmd.env("SH_INLINE_START_EPOCH", start.as_secs().to_string());
+            if let Some(t) = timeout.as_ref() {
+                let deadline = start + t.duration;
+                cmd.env("SH_INLINE_DEADLINE_EPOCH", deadline.as_secs().to_string());
+            }
+        }
+        if self.reproducible {
+            cmd.env("LC_ALL", "C").env("TZ", "UTC");
+            // SAFETY: umask() is async-signal-safe
+            unsafe {
+                cmd.pre_exec(|| {
+                    nix::sys::stat::umask(nix::sys::stat::Mode::from_bits_truncate(0o022));
+                    Ok(())
+                });
+            }
+        }
+        if let Some(limits) = limits {
+            // SAFETY: Limits::apply() only makes async-signal-safe calls
+            unsafe {
+                cmd.pre_exec(move || limits.apply());
+            }
+        }
+        // Record where the script fails for the excerpt in the error, if
+        // it is waited for here; the file is passed through the environment
+        // so that the rendered script doesn't change between runs
+        let err_file = if !bash_only || direct.is_some() || purpose != Purpose::Wait {
+            None
+        } else {
+            let file = tempfiles::file("err")?;
+            cmd.env(ERR_FILE_VAR, file.path());
+            args.push_str(ERR_TRAP);
+            Some(file)
+        };
+        if let Some(fd) = xtrace {
+            let fd = internals::pass_fd(&mut cmd, fd);
+            writeln!(&mut args, "BASH_XTRACEFD={}\nset -x", fd).unwrap();
+        }
+        // Commands for the single EXIT trap, which scripts must not replace
+        let mut on_exit: Vec<String> = Vec::new();
+        if !self.epilogue.is_empty() {
+            writeln!(
+                &mut args,
+                "__sh_inline_epilogue() {{\n{}\n}}",
+                self.epilogue.join("\n")
+            )
+            .unwrap();
+            on_exit.push("__sh_inline_epilogue".into());
+        }
+        // Cleanups run in reverse order of registration, each in a subshell so
+        // that a failure (even an unbound variable) doesn't prevent the rest
+        for (i, snippet) in self.on_exit.iter().enumerate().rev() {
+            writeln!(&mut args, "__sh_inline_on_exit_{}() {{\n{}\n}}", i, snippet).unwrap();
+            on_exit.push(format!("(__sh_inline_on_exit_{}) || true", i));
+        }
+        if self.helpers {
+            on_exit.push("__sh_inline_cleanup".into());
+        }
+        if let Some(fd) = self.env_capture {
+            let fd = internals::pass_fd(&mut cmd, fd);
+            args.push_str(&env::prelude(fd));
+            on_exit.push("{ set +x; } 2>/dev/null; __sh_inline_env".into());
+        }
+        if !on_exit.is_empty() {
+            let trap = internals::shell_quote(&on_exit.join("; "));
+            writeln!(&mut args, "trap {} EXIT", trap).unwrap();
+        }
+        for line in self.prelude.iter() {
+            writeln!(&mut args, "{}", line).unwrap();
+        }
+        match (trace, self.powershell) {
+            (true, true) => args.push_str("Set-PSDebug -Trace 1\n"),
+            (true, false) => args.push_str("set -x\n"),
+            (false, _) => {}
+        }
+        let mut rendered = args;
+        rendered.push_str(&script);
+        if self.trace.unwrap_or_else(trace_env) && !render {
+            let mut traced = rendered.clone();
+            for (line, replacement) in redacted.iter() {
+                traced = traced.replacen(line.as_str(), replacement, 1);
+            }
+            if !traced.ends_with('\n') {
+                traced.push('\n');
+            }
+            eprint!(
+                "sh-inline: {} rendered as:\n{}",
+                internals::describe(self.name.as_deref()),
+                traced
+            );
+        }
+        match (self.stdin.transpose()?, direct.is_some()) {
+            // PowerShell only runs scripts named *.ps1, so the script is
+            // always passed by name
+            (stdin, false) if self.powershell => {
+                if let Some(stdin) = stdin {
+                    cmd.stdin(stdin);
+                }
+                if render {
+                    cmd.arg(tempfiles::placeholder("script", None, ".ps1"));
+                } else {
+                    let file = tempfiles::file_with_suffix("script", ".ps1")?;
+                    std::io::Write::write_all(&mut file.as_file(), rendered.as_bytes())?;
+                    cmd.arg(file.path());
+                    internals::keep_alive(&mut cmd, file);
+                }
+            }
+            (Some(stdin), true) => {
+                cmd.stdin(stdin);
+            }
+            (Some(stdin), false) => {
+                cmd.stdin(stdin);
+                if render {
+                    cmd.arg(tempfiles::placeholder("script", None, ""));
+                } else {
+                    internals::render_into_fd(&mut cmd, &rendered)?;
+                }
+            }
+            (None, true) => {}
+            (None, false) if render => {}
+            (None, false) => internals::render_into(&mut cmd, &rendered)?,
+        }
+        if let Some(stdout) = self.stdout {
+            cmd.stdout(stdout);
+        }
+        if let Some(stderr) = self.stderr {
+            cmd.stderr(stderr);
+        }
+        if self.daemonize {
+            // SAFETY: Only async-signal-safe calls are made after fork
+            unsafe {
+                cmd.pre_exec(daemonize);
+            }
+        }
+        #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+        {
+            if let Some(signal) = self.parent_death_signal {
+                let parent = nix::unistd::getpid();
+                // SAFETY: prctl()/procctl() and getppid() are async-signal-safe
+                unsafe {
+                    cmd.pre_exec(move || set_parent_death_signal(signal, parent));
+                }
+            }
+        }
+        #[cfg(target_os = "freebsd")]
+        {
+            if let Some(jid) = self.jail {
+                // SAFETY: jail_attach() is a plain system call
+                unsafe {
+                    cmd.pre_exec(move || match nix::libc::jail_attach(jid) {
+                        0 => Ok(()),
+                        _ => Err(std::io::Error::last_os_error()),
+                    });
+                }
+            }
+        }
+        if self.private_tmp && render {
+            cmd.env("TMPDIR", tempfiles::placeholder("tmp", None, ""));
+        } else if self.private_tmp {
+            let tmpdir = tempfiles::dir("tmp", None)?;
+            cmd.env("TMPDIR", tmpdir.path());
+            internals::keep_alive(&mut cmd, tmpdir);
+        }
+        if self.isolated_home {
+            let home = match render {
+                true => None,
+                false => Some(tempfiles::dir("home", None)?),
+            };
+            let path = match home.as_ref() {
+                Some(home) => home.path().to_path_buf(),
+                None => tempfiles::placeholder("home", None, ""),
+            };
+            for (var, dir) in [
+                ("XDG_CONFIG_HOME", ".config"),
+                ("XDG_CACHE_HOME", ".cache"),
+                ("XDG_DATA_HOME", ".local/share"),
+                ("XDG_STATE_HOME", ".local/state"),
+            ] {
+                let path = path.join(dir);
+                if home.is_some() {
+                    std::fs::create_dir_all(&path)?;
+                }
+                cmd.env(var, path);
+            }
+            cmd.env("HOME", &path);
+            if let Some(home) = home {
+                internals::keep_alive(&mut cmd, home);
+            }
+        }
+        if let Some(scratch) = scratch {
+            internals::keep_alive(&mut cmd, scratch);
+        }
+        let name = self.name.as_deref();
+        let bundle = artifacts
+            .filter(|_| !render)
+            .map(|dir| Bundle::new(&dir, name, &cmd, env_keep.is_some()));
+        let output_file = match self.markers.outputs.as_ref() {
+            Some(outputs) if render => {
+                cmd.env(
+                    &outputs.file_var,
+                    tempfiles::placeholder("output", None, ""),
+                );
+                None
+            }
+            Some(outputs) => {
+                let file = tempfiles::file("output")?;
+                cmd.env(&outputs.file_var, file.path());
+                Some(file)
+            }
+            None => None,
+        };
+        Ok(Built {
+            cmd,
+            timeout,
+            rendered,
+            name: self.name,
+            markers: std::mem::take(&mut self.markers),
+            output_file,
+            err_file,
+            bundle,
+            files,
+            env_cleared: env_keep.is_some(),
+        })
+    }
+
+    /// Execute the script, returning an error if it exits unsuccessfully.
+    pub fn run(self) -> Result<(), std::io::Error> {
+        self.execute(false).map(|_| ())
+    }
+
+    /// Execute the script with the write end of a pipe bound as `${ret}`
+    /// (a `/dev/fd/N` path), returning everything written to it.  This is a
+    /// channel for results separate from standard output and error, which
+    /// are left alone.  Background processes which keep the pipe open
+    /// delay the return until they exit.
+    pub fn run_with_return_fd(self) -> Result<Vec<u8>, std::io::Error> {
+        use std::io::Read;
+        let (mut reader, writer) = internals::pipe()?;
+        // Only the script holds the write end, so the pipe ends with it
+        let script = self.bind("ret", &OwnedFd::from(writer));
+        let reader = std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf).map(|_| buf)
+        });
+        let result = script.run();
+        let ret = reader
+            .join()
+            .expect("return channel reader thread panicked")?;
+        result.map(|()| ret)
+    }
+
+    /// Execute the script, returning the changes it made to its exported
+    /// environment.
+    pub fn env_diff(mut self) -> Result<EnvDiff, std::io::Error> {
+        use std::io::{Read, Seek};
+        let mut recorded = tempfile::tempfile()?;
+        self.env_capture = Some(recorded.try_clone()?.into());
+        self.run()?;
+        let mut buf = Vec::new();
+        recorded.seek(std::io::SeekFrom::Start(0))?;
+        recorded.read_to_end(&mut buf)?;
+        env::diff(&buf)
+    }
+
+    /// Execute the script and apply the changes it made to its exported
+    /// environment to this process, returning them.
+    pub fn source(self) -> Result<EnvDiff, std::io::Error> {
+        let diff = self.env_diff()?;
+        diff.apply();
+        Ok(diff)
+    }
+
+    /// Execute the script, returning its standard output.  Standard error is
+    /// inherited unless redirected.
+    pub(crate) fn capture_stdout(mut self) -> Result<Vec<u8>, std::io::Error> {
+        self.stdout = Some(Stdio::piped());
+        self.execute(true)
+    }
+
+    /// Execute the script, with its standard output captured if `capture`
+    /// and passing its standard error to the `on_stderr` hook on failure.
+    fn execute(mut self, capture: bool) -> Result<Vec<u8>, std::io::Error> {
+        // The callers sharing an execution would each have their own streams
+        if self.single_flight
+            && (self.stdin.is_some()
+                || self.stderr.is_some()
+                || (self.stdout.is_some() && !capture))
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "single_flight can't be combined with the stdin, stdout or stderr options",
+            ));
+        }
+        let hook = self.on_stderr.take();
+        if let Some(recording) = self.recording.take().or(Config::current().recording) {
+            return self.execute_recorded(recording, capture, hook);
+        }
+        let tail = self.stderr_tail.unwrap_or(Config::current().stderr_tail);
+        if (hook.is_some() || tail > 0) && self.stderr.is_none() {
+            self.stderr = Some(Stdio::piped());
+        }
+        if self.markers.active() && self.stdout.is_none() {
+            self.stdout = Some(Stdio::piped());
+        }
+        if self.artifacts.is_some() || Config::current().artifacts.is_some() {
+            self.stdout.get_or_insert_with(Stdio::piped);
+            self.stderr.get_or_insert_with(Stdio::piped);
+        }
+        let single_flight = self.single_flight;
+        let built = self.prepare(Purpose::Wait)?;
+        if single_flight {
+            let mut plan = Plan::new(&built.cmd, built.rendered.clone(), None, built.env_cleared);
+            // Each execution has its own file for the ERR trap
+            plan.env.retain(|(k, _)| k != ERR_FILE_VAR);
+            // Plans don't show secrets, which distinguish executions too
+            let secrets: Vec<(&OsStr, Option<&OsStr>)> = built
+                .cmd
+                .get_envs()
+                .filter(|(k, _)| k.to_string_lossy().starts_with(SECRET_ENV_PREFIX))
+                .collect();
+            let key = flight::key(&(
+                secrets,
+                capture,
+                plan.program,
+                plan.args,
+                plan.env,
+                plan.current_dir,
+                plan.script,
+            ));
+            return flight::share(key, move || Self::wait(built, capture, hook, tail));
+        }
+        Self::wait(built, capture, hook, tail)
+    }
+
+    /// Execute the script with `recording`, passing on its output as it
+    /// would have been shown or captured if it were executed directly.
+    fn execute_recorded(
+        mut self,
+        recording: Recording,
+        capture: bool,
+        hook: Option<StderrHook>,
+    ) -> Result<Vec<u8>, std::io::Error> {
+        use std::io::Write;
+        let show_stdout = self.stdout.is_none();
+        let show_stderr = self.stderr.is_none();
+        let tail = self.stderr_tail.unwrap_or(Config::current().stderr_tail);
+        self.stdout.get_or_insert_with(Stdio::piped);
+        self.stderr.get_or_insert_with(Stdio::piped);
+        let built = self.prepare(Purpose::Execute)?;
+        let name = built.name.clone();
+        let rendered = built.rendered.clone();
+        let (script, bound) = recording_key(&built)?;
+        let output = recording.execute(&script, &bound, || collect(built))?;
+        if show_stdout {
+            std::io::stdout().write_all(&output.stdout)?;
+        }
+        if show_stderr {
+            std::io::stderr().write_all(&output.stderr)?;
+        }
+        if let (Some(hook), false) = (hook, output.success()) {
+            if let Some(e) = hook(&output.stderr_utf8_lossy()) {
+                return Err(e);
+            }
+        }
+        Error::check(
+            output.status,
+            name.as_deref(),
+            Some(&rendered),
+            None,
+            &output.stderr,
+            tail,
+        )?;
+        Ok(if capture { output.stdout } else { Vec::new() })
+    }
+
+    /// Execute a rendered script, keeping the last `tail` bytes of standard
+    /// error for the error if it fails; see `execute`.
+    fn wait(
+        built: Built,
+        capture: bool,
+        hook: Option<StderrHook>,
+        tail: usize,
+    ) -> Result<Vec<u8>, std::io::Error> {
+        use std::io::{Read, Write};
+        let Built {
+            mut cmd,
+            timeout,
+            name,
+            rendered,
+            markers,
+            err_file,
+            bundle,
+            ..
+        } = built;
+        let start = Instant::now();
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| Error::spawn(e, name.as_deref(), &rendered))?;
+        // All of standard error is needed for the hook and the artifacts,
+        // otherwise only the tail
+        let keep = if hook.is_some() || bundle.is_some() {
+            usize::MAX
+        } else {
+            tail
+        };
+        // Standard output which is shown is also kept for the artifacts
+        let tee = bundle.is_some() && !capture;
+        // Read concurrently so the script can't block on a full pipe while
+        // a builtin timeout is waiting for it
+        let stdout = child
+            .stdout
+            .take()
+            .filter(|_| capture || tee || markers.active())
+            .map(|mut stdout| {
+                std::thread::spawn(move || {
+                    let mut buf = Vec::new();
+                    match (capture, tee, markers.active()) {
+                        (true, _, false) => {
+                            stdout.read_to_end(&mut buf)?;
+                        }
+                        (true, _, true) => {
+                            markers.filter(stdout, &mut buf, start)?;
+                        }
+                        (false, true, false) => {
+                            std::io::copy(&mut stdout, &mut Tee(&mut buf))?;
+                        }
+                        (false, true, true) => {
+                            markers.filter(stdout, Tee(&mut buf), start)?;
+                        }
+                        (false, false, _) => {
+                            markers.filter(stdout, std::io::stdout(), start)?;
+                        }
+                    }
+                    Ok::<_, std::io::Error>(buf)
+                })
+            });
+        // Standard error is still shown as usual, as well as being captured
+        let stderr = child.stderr.take().map(|mut stderr| {
+            std::thread::spawn(move || {
+                let mut captured = Vec::new();
+                let mut buf = [0u8; 8192];
+                loop {
+                    let n = stderr.read(&mut buf)?;
+                    if n == 0 {
+                        return Ok(captured);
+                    }
+                    let _ = std::io::stderr().write_all(&buf[..n]);
+                    captured.extend_from_slice(&buf[..n]);
+                    if captured.len() > keep.saturating_mul(2) {
+                        captured.drain(..captured.len() - keep);
+                    }
+                }
+            })
+        });
+        let status = wait_status(&mut child, timeout, name.as_deref(), &rendered)?;
+        let output = match stdout {
+            Some(reader) => reader.join().expect("output reader thread panicked")?,
+            None => Vec::new(),
+        };
+        let errors: Result<Vec<u8>, std::io::Error> = match stderr {
+            Some(reader) => reader.join().expect("output reader thread panicked"),
+            None => Ok(Vec::new()),
+        };
+        let errors = errors?;
+        let line = failed_line(err_file.as_ref(), status);
+        if let (Some(bundle), false) = (bundle.as_ref(), status.success()) {
+            // The failure of the script matters more than the artifacts
+            let _ = bundle.write(&rendered, status, line, start.elapsed(), &output, &errors);
+        }
+        if let (Some(hook), false) = (hook, status.success()) {
+            if let Some(e) = hook(&String::from_utf8_lossy(&errors)) {
+                return Err(e);
+            }
+        }
+        Error::check(
+            status,
+            name.as_deref(),
+            Some(&rendered),
+            line,
+            &errors,
+            tail,
+        )?;
+        Ok(if capture { output } else { Vec::new() })
+    }
+
+    /// Execute the script, capturing both output streams unless redirected
+    /// and timing it.  Unlike the other ways of running a script, an
+    /// unsuccessful exit status is not an error.
+    pub fn run_output(mut self) -> Result<BashOutput, std::io::Error> {
+        let hook = self.on_stderr.take();
+        let recording = self.recording.take().or(Config::current().recording);
+        self.stdout.get_or_insert_with(Stdio::piped);
+        self.stderr.get_or_insert_with(Stdio::piped);
+        let built = self.prepare(Purpose::Execute)?;
+        let output = match recording {
+            Some(recording) => {
+                let (rendered, bound) = recording_key(&built)?;
+                recording.execute(&rendered, &bound, || collect(built))?
+            }
+            None => collect(built)?,
+        };
+        let status = output.status;
+        if let (Some(hook), false) = (hook, status.success()) {
+            if let Some(e) = hook(&output.stderr_utf8_lossy()) {
+                return Err(e);
+            }
+        }
+        Ok(output)
+    }
+
+    /// Execute the script and return its standard output as a string,
+    /// trimmed of surrounding whitespace if the `trim` option is set.
+    pub fn output(self) -> Result<String, std::io::Error> {
+        let trim = self.trim;
+        let output = self.capture_stdout()?;
+        let output = String::from_utf8(output)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        match trim {
+            true => Ok(output.trim().to_string()),
+            false => Ok(output),
+        }
+    }
+
+    /// Execute the script and parse its trimmed standard output as a `T`.
+    pub fn eval<T: std::str::FromStr>(self) -> Result<T, EvalError<T::Err>> {
+        let output = self.trim(false).output()?;
+        let trimmed = output.trim();
+        trimmed.parse().map_err(|error| EvalError::Parse {
+            output: trimmed.to_string(),
+            error,
+        })
+    }
+
+    /// Describe what executing the script would do, without running it or
+    /// setting anything up for it; the paths of the temporary files and
+    /// directories it would be given are placeholders.
+    pub fn plan(self) -> Result<Plan, std::io::Error> {
+        let built = self.build(Purpose::Render)?;
+        Ok(Plan::new(
+            &built.cmd,
+            built.rendered,
+            built.name,
+            built.env_cleared,
+        ))
+    }
+
+    /// The complete text of the script as it would be run, without running
+    /// it or setting anything up for it, as for [`plan`](#method.plan).
+    pub fn render(self) -> Result<String, std::io::Error> {
+        Ok(self.build(Purpose::Render)?.rendered)
+    }
+
+    /// Spawn the script as a child process.
+    pub fn spawn(self) -> Result<BashChild, std::io::Error> {
+        let pipe_stderr = self.pipe_stderr;
+        let mut cmd = self.command()?;
+        if pipe_stderr {
+            cmd.stderr(Stdio::piped());
+        }
+        internals::spawn(cmd)
+    }
+
+    /// Spawn the script, returning an iterator over the lines of its
+    /// standard output which ends with an error if the script failed.
+    pub fn lines(self) -> Result<Lines, std::io::Error> {
+        let name = self.name.clone();
+        Ok(self.spawn()?.lines().named(name))
+    }
+}
+
+/// What a script is built for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Purpose {
+    /// Only the script and command line are wanted, e.g. for a plan, so
+    /// that nothing is created, mounted, probed or printed; temporary paths
+    /// are placeholders
+    Render,
+    /// The command is returned or executed without reporting where the
+    /// script failed
+    Execute,
+    /// The command is executed and waited for by `wait`, which reports the
+    /// line at which the script failed as recorded by the `ERR` trap
+    Wait,
+}
+
+/// A script rendered into a `Command`.
+struct Built {
+    cmd: Command,
+    /// Time limit, which has to be enforced by the caller if `Builtin`
+    timeout: Option<Timeout>,
+    /// The full text of the script
+    rendered: String,
+    /// The name given with the `name` option
+    name: Option<String>,
+    /// The marker lines to parse from standard output
+    markers: Markers,
+    /// The file for the `outputs` option
+    output_file: Option<Temp<tempfile::NamedTempFile>>,
+    /// The file the `ERR` trap records the failing line in
+    err_file: Option<Temp<tempfile::NamedTempFile>>,
+    /// For the `artifacts` option
+    bundle: Option<Bundle>,
+    /// The temporary files bound by path
+    files: Vec<TempFile>,
+    /// Whether the environment is cleared but for the variables set
+    env_cleared: bool,
+}
+
+/// The line at which a script exiting with `status` failed, as recorded
+/// in `err_file` by the `ERR` trap; only a failure with the same exit
+/// code counts, as the script may have recovered from earlier ones.
+fn failed_line(
+    err_file: Option<&Temp<tempfile::NamedTempFile>>,
+    status: ExitStatus,
+) -> Option<usize> {
+    let recorded = std::fs::read_to_string(err_file?.path()).ok()?;
+    let (line, code) = recorded.trim_end().split_once(' ')?;
+    match code.parse::<i32>().ok() == status.code() {
+        true => line.parse().ok(),
+        false => None,
+    }
+}
+
+/// Bind `value` to `var` by reference to environment variables for the
+/// `bind_env` option, adding them to `env` and returning the assignment, or
+/// return `None` if the value is written into the script anyway.
+fn env_binding(
+    profile: &dyn QuotingProfile,
+    var: &str,
+    value: &CommandArg,
+    env: &mut Vec<(String, OsString)>,
+) -> Option<String> {
+    use std::os::unix::ffi::OsStringExt;
+    let mut reference = |value: OsString| {
+        let name = format!("{}{}", BIND_ENV_PREFIX, env.len());
+        let reference = format!("\"${{{}}}\"", name);
+        env.push((name, value));
+        reference
+    };
+    let rendered = match value {
+        CommandArg::Literal(s) | CommandArg::Heredoc(s) => reference(s.into()),
+        CommandArg::Bytes(b) => reference(OsString::from_vec(b.clone())),
+        CommandArg::Path(p) => reference(p.into()),
+        CommandArg::TempFile(f) => reference(f.path().into()),
+        CommandArg::Array(items) => {
+            let items: Vec<String> = items.iter().map(|s| reference(s.into())).collect();
+            format!("({})", items.join(" "))
+        }
+        CommandArg::Assoc(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(k, v)| format!("[{}]={}", reference(k.into()), reference(v.into())))
+                .collect();
+            format!("({})", entries.join(" "))
+        }
+        CommandArg::Empty | CommandArg::Fd(_) | CommandArg::Secret(_) => return None,
+    };
+    Some(profile.assign(var, &rendered))
+}
+
+/// Bind `secret` to `var` by reference to an environment variable, as
+/// [`env_binding`] does.
+fn secret_binding(
+    profile: &dyn QuotingProfile,
+    var: &str,
+    secret: &Secret,
+    env: &mut Vec<(String, OsString)>,
+) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    let name = format!("{}{}", SECRET_ENV_PREFIX, env.len());
+    let reference = format!("\"${{{}}}\"", name);
+    env.push((name, OsStr::from_bytes(secret.expose()).to_os_string()));
+    profile.assign(var, &reference)
+}
+
+/// The values of the variables set by the `bind_env` option for `cmd`, as
+/// `NAME=value` lines identifying a recording.
+fn bound_env(cmd: &Command) -> String {
+    cmd.get_envs()
+        .filter(|(k, _)| k.to_string_lossy().starts_with(BIND_ENV_PREFIX))
+        .map(|(k, v)| {
+            let v = v.unwrap_or_default().to_string_lossy();
+            format!("{}={}\n", k.to_string_lossy(), v)
+        })
+        .collect()
+}
+
+/// The script and the values bound through the environment identifying a
+/// recording of `built`; the paths of temporary files differ every time, so
+/// they are replaced by placeholders and their contents count instead.
+fn recording_key(built: &Built) -> Result<(String, String), std::io::Error> {
+    let mut script = built.rendered.clone();
+    let mut bound = bound_env(&built.cmd);
+    for (i, file) in built.files.iter().enumerate() {
+        let path = file.path().to_string_lossy();
+        let placeholder = format!("<tempfile {}>", i);
+        script = script.replace(&*path, &placeholder);
+        bound = bound.replace(&*path, &placeholder);
+        let contents = std::fs::read(file.path())?;
+        writeln!(
+            bound,
+            "{}={}",
+            placeholder,
+            String::from_utf8_lossy(&contents)
+        )
+        .unwrap();
+    }
+    Ok((script, bound))
+}
+
+/// Whether scripts are traced by default, according to `SH_INLINE_TRACE`.
+fn trace_env() -> bool {
+    std::env::var_os("SH_INLINE_TRACE").is_some_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Join `dirs` into a `PATH` for the `tool_dirs` option.
+fn tool_path(dirs: &[PathBuf]) -> Result<OsString, std::io::Error> {
+    if let Some(dir) = dirs.iter().find(|d| !d.is_absolute()) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("tool directory is not absolute: {}", dir.display()),
+        ));
+    }
+    std::env::join_paths(dirs).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+}
+
+/// Strip the common leading whitespace from the lines of `script`.
+fn dedent(script: &str) -> String {
+    let script = script.strip_prefix('\n').unwrap_or(script);
+    let indent = script
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+    let mut r = String::with_capacity(script.len());
+    for line in script.split_inclusive('\n') {
+        if line.trim().is_empty() {
+            r.push_str(line.trim_start_matches([' ', '\t']));
+        } else {
+            r.push_str(&line[indent..]);
+        }
+    }
+    r
+}
+
+fn builtin_timeout_unsupported() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "the builtin timeout method requires waiting for the script; use TimeoutMethod::Coreutils",
+    )
+}
+
+fn daemonize() -> Result<(), std::io::Error> {
+    use nix::fcntl::{open, OFlag};
+    use nix::sys::stat::Mode;
+    use nix::unistd::{chdir, close, dup2, fork, setsid, ForkResult};
+
+    let err = internals::nix_error;
+    if let ForkResult::Parent { .. } = fork().map_err(err)? {
+        // SAFETY: Exit the intermediate process without running any destructors
+        unsafe { nix::libc::_exit(0) };
+    }
+    setsid().map_err(err)?;
+    if let ForkResult::Parent { .. } = fork().map_err(err)? {
+        unsafe { nix::libc::_exit(0) };
+    }
+    chdir("/").map_err(err)?;
+    let devnull = open("/dev/null", OFlag::O_RDWR, Mode::empty()).map_err(err)?;
+    dup2(devnull, 1).map_err(err)?;
+    dup2(devnull, 2).map_err(err)?;
+    close(devnull).map_err(err)?;
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+fn set_parent_death_signal(signal: i32, parent: nix::unistd::Pid) -> Result<(), std::io::Error> {
+    use nix::libc;
+    #[cfg(target_os = "linux")]
+    use std::os::raw::c_ulong;
+    #[cfg(target_os = "linux")]
+    // SAFETY: PR_SET_PDEATHSIG only takes a signal number
+    let r = unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, signal as c_ulong, 0, 0, 0) };
+    #[cfg(target_os = "freebsd")]
+    // SAFETY: PROC_PDEATHSIG_CTL reads a signal number from the pointer
+    let r = unsafe {
+        let mut signal = signal;
+        libc::procctl(
+            libc::P_PID,
+            0,
+            libc::PROC_PDEATHSIG_CTL,
+            &mut signal as *mut i32 as *mut libc::c_void,
+        )
+    };
+    if r < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // The parent may have exited before the prctl() above took effect
+    if nix::unistd::getppid() != parent {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "parent exited before the script started",
+        ));
+    }
+    Ok(())
+}
+
+/// A scratch directory, unmounted (if necessary) and removed on drop.
+struct ScratchDir {
+    dir: Temp<tempfile::TempDir>,
+    mounted: bool,
+}
+
+impl ScratchDir {
+    fn new(kind: Scratch) -> Result<Self, std::io::Error> {
+        match kind {
+            Scratch::Shm => {
+                let dir = tempfiles::dir("scratch", Some(Path::new("/dev/shm")))?;
+                Ok(ScratchDir {
+                    dir,
+                    mounted: false,
+                })
+            }
+            #[cfg(target_os = "linux")]
+            Scratch::Tmpfs { size } => {
+                use nix::mount::{mount, MsFlags};
+                let dir = tempfiles::dir("scratch", None)?;
+                let data = format!("size={},mode=0700", size);
+                mount(
+                    Some("tmpfs"),
+                    dir.path(),
+                    Some("tmpfs"),
+                    MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                    Some(data.as_str()),
+                )
+                .map_err(internals::nix_error)?;
+                Ok(ScratchDir { dir, mounted: true })
+            }
+            #[cfg(not(target_os = "linux"))]
+            Scratch::Tmpfs { .. } => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "tmpfs scratch mounts are only supported on Linux",
+            )),
+        }
+    }
+
+    fn path(&self) -> &std::path::Path {
+        self.dir.path()
+    }
+
+    /// The path a directory of `kind` would have, for plans.
+    fn placeholder(kind: Scratch) -> PathBuf {
+        match kind {
+            Scratch::Shm => tempfiles::placeholder("scratch", Some(Path::new("/dev/shm")), ""),
+            Scratch::Tmpfs { .. } => tempfiles::placeholder("scratch", None, ""),
+        }
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        {
+            if self.mounted {
+                let _ = nix::mount::umount2(self.dir.path(), nix::mount::MntFlags::MNT_DETACH);
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// A macOS sandbox applied by running the script via `sandbox-exec` with a
+/// generated Seatbelt profile; see the `seatbelt` option.
+///
+/// By default the script may read everything except the paths given to
+/// [`deny_read`](#method.deny_read), may only write to the paths given to
+/// [`writable`](#method.writable) (along with `/dev/null` and the terminal),
+/// and has no network access.  Since this is a security boundary, executing
+/// a script with this option fails with an error of kind `Unsupported` on
+/// other platforms rather than running it unsandboxed.
+///
+/// ```
+/// use sh_inline::*;
+/// let sandbox = Seatbelt::new().writable("/tmp/build").deny_read("/Users/me/.ssh");
+/// assert!(sandbox.profile().contains(r#"(deny network*)"#));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Seatbelt {
+    network: bool,
+    writable: Vec<PathBuf>,
+    deny_read: Vec<PathBuf>,
+}
+
+/// Quote `path` as a Seatbelt profile string.  Profiles match resolved paths
+/// (e.g. `/private/tmp` rather than `/tmp`), so symlinks are resolved if the
+/// path exists.
+fn quote(path: &Path) -> String {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let s = path.to_string_lossy();
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Seatbelt {
+    /// A sandbox without network access or writable paths.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow network access.
+    pub fn network(mut self, network: bool) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Allow writing to `path` and anything below it.
+    pub fn writable<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.writable.push(path.into());
+        self
+    }
+
+    /// Deny reading `path` and anything below it.
+    pub fn deny_read<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.deny_read.push(path.into());
+        self
+    }
+
+    /// The generated Seatbelt profile.
+    pub fn profile(&self) -> String {
+        let mut profile = String::from("(version 1)\n(allow default)\n");
+        if !self.network {
+            profile.push_str("(deny network*)\n");
+        }
+        profile.push_str("(deny file-write*)\n");
+        profile.push_str("(allow file-write* (literal \"/dev/null\") (regex #\"^/dev/tty\"))\n");
+        for path in self.writable.iter() {
+            profile.push_str(&format!("(allow file-write* (subpath {}))\n", quote(path)));
+        }
+        for path in self.deny_read.iter() {
+            profile.push_str(&format!("(deny file-read* (subpath {}))\n", quote(path)));
+        }
+        profile
+    }
+
+    /// The command line prefix which runs a command in the sandbox.
+    pub(crate) fn wrapper(&self) -> Result<Vec<OsString>, std::io::Error> {
+        if cfg!(target_os = "macos") {
+            Ok(vec![
+                crate::internals::wrapper_program("sandbox-exec"),
+                "-p".into(),
+                self.profile().into(),
+            ])
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "seatbelt sandboxing is only available on macOS",
+            ))
+        }
+    }
+}
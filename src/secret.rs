@@ -0,0 +1,64 @@
+use crate::internals::CommandArg;
+use crate::shell_arg::{ShellArg, ToShellArg};
+use std::fmt;
+
+/// A binding for a password, token or other credential, which is passed to
+/// the script in an environment variable rather than written into it; see
+/// [`secret`].
+///
+/// Rendered scripts, plans, traces and artifacts only show the reference to
+/// the variable, which the script unsets after reading it, so that commands
+/// it runs don't inherit it.  The `Debug` implementation doesn't show the
+/// value either, and the buffer is overwritten with zeros when dropped.
+/// Copies made to set the environment of the script are beyond our control.
+///
+/// Secrets can't be bound by scripts for PowerShell, by templates or by
+/// [`BashSession::bind`](struct.BashSession.html#method.bind).
+pub struct Secret(Vec<u8>);
+
+/// Wrap `value` as a [`Secret`] binding.
+///
+/// ```
+/// use sh_inline::*;
+/// let token = secret(String::from("hunter2"));
+/// let plan = bash_plan!(r#"curl -fsS -u "admin:${token}" https://example.com"#, token)?;
+/// assert!(!plan.to_string().contains("hunter2"));
+/// assert_eq!(bash_output!(r#"echo "${#token}""#, token)?, "7\n");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn secret<T: Into<Vec<u8>>>(value: T) -> Secret {
+    Secret(value.into())
+}
+
+impl Secret {
+    /// The value.
+    pub fn expose(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub(crate) fn duplicate(&self) -> Self {
+        Secret(self.0.clone())
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        // Volatile writes, so that they aren't optimized away as dead stores
+        for b in self.0.iter_mut() {
+            unsafe { std::ptr::write_volatile(b, 0) };
+        }
+        std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+impl ToShellArg for Secret {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg(CommandArg::Secret(self.duplicate()))
+    }
+}
@@ -0,0 +1,324 @@
+use crate::config::Config;
+use crate::error::Error;
+use crate::internals::{self, CommandArg};
+use crate::output::BashOutput;
+use crate::shell_arg::ToShellArg;
+use crate::tempfiles::{self, TempFile};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::io::OwnedFd;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+/// The sessions which currently exist, by identifier.
+static SESSIONS: Mutex<BTreeMap<u64, Arc<Mutex<SessionInfo>>>> = Mutex::new(BTreeMap::new());
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A persistent bash process running a sequence of scripts, which share
+/// its working directory, variables and functions.
+///
+/// Each session is a separate process, so changes to the directory or
+/// environment made in one session never affect another one or the calling
+/// process.  Sessions are listed by [`sessions`](fn.sessions.html) while
+/// they exist, so that applications embedding many of them can inspect and
+/// clean them up.
+///
+/// Scripts run with `pipefail`, and stop at the first failing command as
+/// with `errexit`, but without ending the session; `set -u` is not enabled,
+/// since an unbound variable would.  Scripts run inside a function, so
+/// `declare` creates local variables unless given `-g`.  A script which
+/// calls `exit` or
+/// replaces the `ERR` trap ends the session or changes how failures are
+/// detected.  Standard input is `/dev/null`; standard output and error are
+/// inherited unless captured.
+///
+/// ```
+/// use sh_inline::*;
+/// let mut session = BashSession::new()?;
+/// session.run(r"cd /; export GREETING=hello; count=1")?;
+/// session.run(r"count=$((count + 1))")?;
+/// assert_eq!(session.output(r#"echo "${GREETING} ${count}""#)?, "hello 2\n");
+/// assert_eq!(session.cwd(), std::path::Path::new("/"));
+/// assert!(session.run(r"false; echo not reached").is_err());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct BashSession {
+    child: Child,
+    /// Closed when the session is dropped, ending it
+    commands: Option<File>,
+    status: BufReader<File>,
+    status_fd: i32,
+    info: Arc<Mutex<SessionInfo>>,
+    /// The temporary files bound into the session, which it may still use
+    files: Vec<TempFile>,
+}
+
+/// The state of a [`BashSession`](struct.BashSession.html), as listed by
+/// [`sessions`](fn.sessions.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// Identifies the session within this process
+    pub id: u64,
+    /// The name given with [`BashSession::named`], if any
+    ///
+    /// [`BashSession::named`]: struct.BashSession.html#method.named
+    pub name: Option<String>,
+    /// The process identifier of the bash process
+    pub pid: u32,
+    /// The working directory after the last script
+    pub cwd: PathBuf,
+    /// The number of scripts run so far
+    pub scripts: u64,
+    /// When the session was started
+    pub started: SystemTime,
+}
+
+/// Snapshots of the state of all sessions which currently exist, ordered
+/// by identifier.
+pub fn sessions() -> Vec<SessionInfo> {
+    SESSIONS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|info| info.lock().unwrap().clone())
+        .collect()
+}
+
+/// Kill the bash process of the session `id` with `SIGKILL`, returning
+/// whether it exists; its owner then gets errors from further scripts.
+pub fn terminate_session(id: u64) -> Result<bool, std::io::Error> {
+    let sessions = SESSIONS.lock().unwrap();
+    let info = match sessions.get(&id) {
+        Some(info) => info.lock().unwrap(),
+        None => return Ok(false),
+    };
+    // The process is only reaped once the session is removed from the
+    // registry, so the identifier cannot have been reused
+    let pid = nix::unistd::Pid::from_raw(info.pid as i32);
+    match nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGKILL) {
+        Ok(()) | Err(nix::Error::Sys(nix::errno::Errno::ESRCH)) => Ok(true),
+        Err(e) => Err(internals::nix_error(e)),
+    }
+}
+
+/// Run each script in a function, so that the `ERR` trap can stop it at
+/// the first failure as `errexit` would, without exiting the shell.
+const PRELUDE: &str = r#"set -o pipefail -o errtrace
+trap '__sh_inline_status=$?; if [ -n "${FUNCNAME-}" ]; then return "${__sh_inline_status}"; fi' ERR
+__sh_inline_session_run() {
+    eval "$1"
+}
+"#;
+
+impl BashSession {
+    /// Start a session in the current directory, with the environment of
+    /// the calling process.
+    pub fn new() -> Result<Self, std::io::Error> {
+        Self::start(None)
+    }
+
+    /// Start a session with a name, for identifying it in
+    /// [`sessions`](fn.sessions.html).
+    pub fn named(name: &str) -> Result<Self, std::io::Error> {
+        Self::start(Some(name.to_string()))
+    }
+
+    fn start(name: Option<String>) -> Result<Self, std::io::Error> {
+        let bash = internals::bash_path(&Config::current())?;
+        let cwd = std::env::current_dir()?;
+        let (commands_read, commands) = internals::pipe()?;
+        let (status, status_write) = internals::pipe()?;
+        let mut cmd = Command::new(bash);
+        cmd.args(["--norc", "--noprofile"]).stdin(Stdio::null());
+        let commands_fd = internals::pass_fd(&mut cmd, OwnedFd::from(commands_read));
+        let status_fd = internals::pass_fd(&mut cmd, OwnedFd::from(status_write));
+        cmd.arg(format!("/dev/fd/{}", commands_fd));
+        let child = cmd.spawn()?;
+        // Close our copies of the child's ends of the pipes
+        drop(cmd);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let info = Arc::new(Mutex::new(SessionInfo {
+            id,
+            name,
+            pid: child.id(),
+            cwd,
+            scripts: 0,
+            started: SystemTime::now(),
+        }));
+        SESSIONS.lock().unwrap().insert(id, info.clone());
+        // From here on, dropping the session reaps bash on failure
+        let mut session = BashSession {
+            child,
+            commands: Some(commands),
+            status: BufReader::new(status),
+            status_fd,
+            info,
+            files: Vec::new(),
+        };
+        session
+            .commands
+            .as_mut()
+            .unwrap()
+            .write_all(PRELUDE.as_bytes())?;
+        Ok(session)
+    }
+
+    /// The identifier of the session, as used by
+    /// [`terminate_session`](fn.terminate_session.html).
+    pub fn id(&self) -> u64 {
+        self.info.lock().unwrap().id
+    }
+
+    /// The process identifier of the bash process.
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// The working directory of the session after the last script.
+    pub fn cwd(&self) -> PathBuf {
+        self.info.lock().unwrap().cwd.clone()
+    }
+
+    /// A snapshot of the state of the session.
+    pub fn info(&self) -> SessionInfo {
+        self.info.lock().unwrap().clone()
+    }
+
+    /// Set the shell variable `name` in the session to `value`, quoted as
+    /// in the macros.  Secrets, [`Heredoc`](struct.Heredoc.html)s and open
+    /// files or descriptors can't be bound in a session, and fail with an
+    /// error of kind `InvalidInput`.  A [`tempfile`](fn.tempfile.html) is
+    /// kept until the session ends.
+    pub fn bind<T: ToShellArg + ?Sized>(
+        &mut self,
+        name: &str,
+        value: &T,
+    ) -> Result<(), std::io::Error> {
+        let valid = name
+            .bytes()
+            .enumerate()
+            .all(|(i, c)| c == b'_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+        if !valid || name.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid variable name: {:?}", name),
+            ));
+        }
+        let value = value.to_shell_arg().0;
+        let unsupported = match value {
+            CommandArg::Secret(_) => Some("secret"),
+            CommandArg::Heredoc(_) => Some("heredoc"),
+            // The descriptor isn't passed to the running shell
+            CommandArg::Fd(_) => Some("descriptor"),
+            _ => None,
+        };
+        if let Some(kind) = unsupported {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("cannot bind {} {} in a session", kind, name),
+            ));
+        }
+        if let CommandArg::TempFile(file) = &value {
+            self.files.push(file.clone());
+        }
+        let declare = match value {
+            CommandArg::Array(_) => "declare -ga ",
+            CommandArg::Assoc(_) => "declare -gA ",
+            _ => "",
+        };
+        self.run(&format!("{}{}={}", declare, name, value))
+    }
+
+    /// Run `script` in the session, returning an error if it fails.
+    pub fn run(&mut self, script: &str) -> Result<(), std::io::Error> {
+        let status = self.execute(script, None, None)?;
+        Error::check(status, None, Some(script), None, &[], 0)
+    }
+
+    /// Run `script` in the session, returning its standard output.
+    pub fn output(&mut self, script: &str) -> Result<String, std::io::Error> {
+        let stdout = tempfiles::file("stdout")?;
+        let status = self.execute(script, Some(stdout.path()), None)?;
+        Error::check(status, None, Some(script), None, &[], 0)?;
+        let output = std::fs::read(stdout.path())?;
+        String::from_utf8(output)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Run `script` in the session, capturing both output streams; as
+    /// with [`bash_run`](macro.bash_run.html), an unsuccessful exit status
+    /// is not an error.
+    pub fn run_output(&mut self, script: &str) -> Result<BashOutput, std::io::Error> {
+        let stdout = tempfiles::file("stdout")?;
+        let stderr = tempfiles::file("stderr")?;
+        let start = Instant::now();
+        let status = self.execute(script, Some(stdout.path()), Some(stderr.path()))?;
+        Ok(BashOutput {
+            stdout: std::fs::read(stdout.path())?,
+            stderr: std::fs::read(stderr.path())?,
+            status,
+            duration: start.elapsed(),
+            steps: Vec::new(),
+            outputs: Default::default(),
+        })
+    }
+
+    /// Send `script` to the session, with its output streams redirected to
+    /// the given files, and wait for its status.
+    fn execute(
+        &mut self,
+        script: &str,
+        stdout: Option<&Path>,
+        stderr: Option<&Path>,
+    ) -> Result<ExitStatus, std::io::Error> {
+        let mut command = format!("__sh_inline_session_run {}", internals::shell_quote(script));
+        if let Some(path) = stdout {
+            command.push_str(&format!(" >{}", CommandArg::Path(path.to_path_buf())));
+        }
+        if let Some(path) = stderr {
+            command.push_str(&format!(" 2>{}", CommandArg::Path(path.to_path_buf())));
+        }
+        command.push_str(&format!(
+            "\nprintf '%s\\0%s\\0' \"$?\" \"${{PWD}}\" >&{}\n",
+            self.status_fd
+        ));
+        let exited =
+            || std::io::Error::new(std::io::ErrorKind::BrokenPipe, "bash session has exited");
+        let commands = self.commands.as_mut().expect("session is open");
+        commands
+            .write_all(command.as_bytes())
+            .map_err(|_| exited())?;
+        let mut code = Vec::new();
+        let mut cwd = Vec::new();
+        self.status.read_until(0, &mut code)?;
+        self.status.read_until(0, &mut cwd)?;
+        if code.pop() != Some(0) || cwd.pop() != Some(0) {
+            return Err(exited());
+        }
+        let code: i32 = String::from_utf8_lossy(&code)
+            .parse()
+            .map_err(|_| exited())?;
+        let mut info = self.info.lock().unwrap();
+        info.cwd = OsString::from_vec(cwd).into();
+        info.scripts += 1;
+        Ok(ExitStatus::from_raw(code << 8))
+    }
+}
+
+impl Drop for BashSession {
+    fn drop(&mut self) {
+        let id = self.info.lock().unwrap().id;
+        SESSIONS.lock().unwrap().remove(&id);
+        // Bash exits at the end of its input
+        self.commands.take();
+        let _ = self.child.wait();
+    }
+}
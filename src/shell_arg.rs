@@ -0,0 +1,194 @@
+use crate::internals::{os_str_arg, CommandArg};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::os::unix::io::OwnedFd;
+use std::path::{Path, PathBuf};
+
+/// A value ready to be bound as a shell variable; see [`ToShellArg`].
+///
+/// [`ToShellArg`]: trait.ToShellArg.html
+pub struct ShellArg(pub(crate) CommandArg);
+
+impl ShellArg {
+    /// Bind `value` as a string, quoting as necessary.
+    pub fn string<S: Into<String>>(value: S) -> Self {
+        ShellArg(CommandArg::Literal(value.into()))
+    }
+
+    /// Bind arbitrary binary data using `$'...'` escapes, as with
+    /// [`Bytes`](struct.Bytes.html).
+    pub fn bytes<B: AsRef<[u8]>>(value: B) -> Self {
+        ShellArg(CommandArg::Bytes(value.as_ref().to_vec()))
+    }
+
+    /// Bind a path, which need not be valid UTF-8.
+    pub fn path<P: AsRef<Path>>(value: P) -> Self {
+        ShellArg(CommandArg::Path(value.as_ref().to_path_buf()))
+    }
+
+    /// Bind a bash array, with each item formatted with `Display`.
+    pub fn array<I>(items: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: fmt::Display,
+    {
+        ShellArg(CommandArg::Array(
+            items.into_iter().map(|x| x.to_string()).collect(),
+        ))
+    }
+
+    /// Bind the empty string, as for `None`.
+    pub fn empty() -> Self {
+        ShellArg(CommandArg::Empty)
+    }
+}
+
+/// Conversion of a value into a shell variable binding, as used by the
+/// macros and [`Script::bind`](struct.Script.html#method.bind).
+///
+/// This is implemented for strings, paths, numbers, files, collections and
+/// the wrappers of this crate such as [`Bytes`](struct.Bytes.html), and can
+/// be implemented to make other types bindable:
+///
+/// ```
+/// use sh_inline::*;
+///
+/// struct ImageRef {
+///     name: String,
+///     tag: String,
+/// }
+///
+/// impl ToShellArg for ImageRef {
+///     fn to_shell_arg(&self) -> ShellArg {
+///         ShellArg::string(format!("{}:{}", self.name, self.tag))
+///     }
+/// }
+///
+/// let image = ImageRef { name: "quay.io/fedora/fedora".into(), tag: "41".into() };
+/// assert_eq!(bash_output!(r#"echo "${image##*:}""#, image)?, "41\n");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub trait ToShellArg {
+    /// The binding for `self`.
+    fn to_shell_arg(&self) -> ShellArg;
+}
+
+impl<T: ToShellArg + ?Sized> ToShellArg for &T {
+    fn to_shell_arg(&self) -> ShellArg {
+        (**self).to_shell_arg()
+    }
+}
+
+impl ToShellArg for str {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg::string(self)
+    }
+}
+
+impl ToShellArg for String {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg::string(self.as_str())
+    }
+}
+
+impl ToShellArg for OsStr {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg(os_str_arg(self))
+    }
+}
+
+impl ToShellArg for OsString {
+    fn to_shell_arg(&self) -> ShellArg {
+        self.as_os_str().to_shell_arg()
+    }
+}
+
+impl ToShellArg for std::fs::File {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg(CommandArg::Fd(self.try_clone().map(OwnedFd::from)))
+    }
+}
+
+impl ToShellArg for OwnedFd {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg(CommandArg::Fd(self.try_clone()))
+    }
+}
+
+impl ToShellArg for Path {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg::path(self)
+    }
+}
+
+impl ToShellArg for PathBuf {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg::path(self)
+    }
+}
+
+macro_rules! display_shell_arg {
+    ($($t:ty),*) => {
+        $(
+            impl ToShellArg for $t {
+                fn to_shell_arg(&self) -> ShellArg {
+                    ShellArg::string(self.to_string())
+                }
+            }
+        )*
+    };
+}
+
+// A `u8` is bound as a number; use `Bytes([b])` to bind it as a character.
+display_shell_arg!(char, bool, u8, u64, usize, i32, i64, f32, f64);
+
+impl<T: fmt::Display> ToShellArg for [T] {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg::array(self)
+    }
+}
+
+impl<T: fmt::Display> ToShellArg for Vec<T> {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg::array(self)
+    }
+}
+
+impl<T: fmt::Display, const N: usize> ToShellArg for [T; N] {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg::array(self)
+    }
+}
+
+impl<K: fmt::Display, V: fmt::Display> ToShellArg for BTreeMap<K, V> {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg(CommandArg::Assoc(
+            self.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        ))
+    }
+}
+
+/// Entries are sorted by key, so that the rendered script is the same
+/// every time.
+impl<K: fmt::Display, V: fmt::Display, S> ToShellArg for HashMap<K, V, S> {
+    fn to_shell_arg(&self) -> ShellArg {
+        let mut entries: Vec<(String, String)> = self
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        entries.sort();
+        ShellArg(CommandArg::Assoc(entries))
+    }
+}
+
+impl<T: fmt::Display> ToShellArg for Option<T> {
+    fn to_shell_arg(&self) -> ShellArg {
+        match self {
+            Some(value) => ShellArg::string(value.to_string()),
+            None => ShellArg::empty(),
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use crate::internals::signal_group;
+use nix::sys::signal::Signal;
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ChildStderr, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Detached processes whose [`BashChild`] handle was dropped before they
+/// exited; see [`reap_finished`].
+static ABANDONED: Mutex<Vec<nix::unistd::Pid>> = Mutex::new(Vec::new());
+
+/// A handle to a script started via [`bash_spawn`](./macro.bash_spawn.html).
+///
+/// The standard output of the script is always piped so that it can be
+/// inspected while the child is running; standard error is inherited
+/// unless the `pipe_stderr` option is given.  A script which writes more
+/// than fits into the pipe blocks until it is read, e.g. via
+/// [`stdout`](#method.stdout) from another thread.
+///
+/// The script runs in a process group of its own.  When the handle is
+/// dropped, every process still in that group is killed with `SIGKILL`,
+/// so that helper scripts and the commands they started don't outlive it;
+/// after [`detach`](#method.detach), the script is left running instead,
+/// and remembered so that it can later be waited for by [`reap_finished`].
+///
+/// ```
+/// use sh_inline::*;
+/// let helper = bash_spawn!(r"sleep 30 & sleep 30 & wait")?;
+/// // Kills bash and both sleep processes
+/// drop(helper);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// [`reap_finished`]: fn.reap_finished.html
+pub struct BashChild {
+    child: Child,
+    /// Kept so that resources tied to the command outlive the script.
+    _cmd: Command,
+    stdout: ChildStdout,
+    stderr: Option<ChildStderr>,
+    /// Output which has been read from the pipe but not yet consumed.
+    buf: Vec<u8>,
+    eof: bool,
+    detached: bool,
+}
+
+/// Spawn a [`Command`] created by the macros, returning a [`BashChild`].
+/// Implementation detail of the macros.
+///
+/// [`Command`]: https://doc.rust-lang.org/std/process/struct.Command.html
+#[doc(hidden)]
+pub fn spawn(mut cmd: Command) -> Result<BashChild, std::io::Error> {
+    let mut child = cmd.stdout(Stdio::piped()).process_group(0).spawn()?;
+    let stdout = child.stdout.take().expect("stdout was piped");
+    let stderr = child.stderr.take();
+    Ok(BashChild {
+        child,
+        _cmd: cmd,
+        stdout,
+        stderr,
+        buf: Vec::new(),
+        eof: false,
+        detached: false,
+    })
+}
+
+impl BashChild {
+    /// The process identifier of the `bash` process.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Send `SIGKILL` to the `bash` process and every other process in its
+    /// process group.
+    pub fn kill(&mut self) -> Result<(), std::io::Error> {
+        let pgid = nix::unistd::Pid::from_raw(self.child.id() as i32);
+        match nix::sys::signal::killpg(pgid, Signal::SIGKILL) {
+            Ok(()) | Err(nix::Error::Sys(nix::errno::Errno::ESRCH)) => Ok(()),
+            Err(e) => Err(crate::internals::nix_error(e)),
+        }
+    }
+
+    /// Leave the script running when the handle is dropped, rather than
+    /// killing its process group.
+    pub fn detach(&mut self) {
+        self.detached = true;
+    }
+
+    /// The pipe from the standard output of the script, for reading it
+    /// directly.  Output which [`wait_for_output`](#method.wait_for_output)
+    /// read past the matching line is only returned by [`tail`](#method.tail).
+    pub fn stdout(&mut self) -> &mut ChildStdout {
+        &mut self.stdout
+    }
+
+    /// The pipe from the standard error of the script, if the
+    /// `pipe_stderr` option was given.  It has to be read, e.g. from
+    /// another thread, or the script blocks once the pipe is full.
+    pub fn stderr(&mut self) -> Option<&mut ChildStderr> {
+        self.stderr.as_mut()
+    }
+
+    /// Wait for the script to exit.
+    pub fn wait(&mut self) -> Result<ExitStatus, std::io::Error> {
+        self.child.wait()
+    }
+
+    /// Block until a line of standard output matches the regular expression
+    /// `pattern`, returning that line (without the trailing newline).
+    ///
+    /// Lines up to and including the matching one are consumed.  An error of
+    /// kind `TimedOut` is returned if no line matched within `timeout`, and
+    /// one of kind `UnexpectedEof` if the script closed its output first.
+    ///
+    /// ```
+    /// use sh_inline::*;
+    /// use std::time::Duration;
+    /// let port = 8080;
+    /// let mut child = bash_spawn!(r#"echo "Listening on port ${port}"; exec sleep 30"#, port)?;
+    /// let line = child.wait_for_output(r"Listening on port \d+", Duration::from_secs(10))?;
+    /// assert_eq!(line, "Listening on port 8080");
+    /// child.kill()?;
+    /// child.wait()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn wait_for_output(
+        &mut self,
+        pattern: &str,
+        timeout: Duration,
+    ) -> Result<String, std::io::Error> {
+        let re = regex::Regex::new(pattern)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            while let Some(line) = self.next_buffered_line() {
+                if re.is_match(&line) {
+                    return Ok(line);
+                }
+            }
+            if self.eof {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("script output ended without matching {:?}", pattern),
+                ));
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !self.fill_buf(Some(remaining))? {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("timed out waiting for output matching {:?}", pattern),
+                ));
+            }
+        }
+    }
+
+    /// Follow the standard output of the script, returning a blocking
+    /// iterator over its lines (without trailing newlines).  The iterator
+    /// ends once the script closes its output, which usually means it has
+    /// exited; any output buffered by a previous call such as
+    /// [`wait_for_output`](#method.wait_for_output) is yielded first.
+    ///
+    /// ```
+    /// use sh_inline::*;
+    /// let mut child = bash_spawn!(r"for i in 1 2 3; do echo line $i; done")?;
+    /// let lines = child.tail().collect::<Result<Vec<_>, _>>()?;
+    /// assert_eq!(lines, ["line 1", "line 2", "line 3"]);
+    /// assert!(child.wait()?.success());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn tail(&mut self) -> Tail<'_> {
+        Tail { child: self }
+    }
+
+    /// Turn the handle into an iterator over the lines of standard output,
+    /// like [`tail`](#method.tail), which once the output ends waits for the
+    /// script and yields an error if it failed.
+    pub fn lines(self) -> Lines {
+        Lines {
+            child: self,
+            name: None,
+            done: false,
+        }
+    }
+
+    /// Remove and return the next complete line from the buffer; at end of
+    /// file a trailing unterminated line is returned as well.
+    fn next_buffered_line(&mut self) -> Option<String> {
+        let end = match self.buf.iter().position(|&c| c == b'\n') {
+            Some(i) => i + 1,
+            None if self.eof && !self.buf.is_empty() => self.buf.len(),
+            None => return None,
+        };
+        let mut line: Vec<u8> = self.buf.drain(..end).collect();
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        Some(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    /// Read more output into the buffer, waiting at most `timeout` if
+    /// provided.  Returns `false` if the timeout expired without data.
+    fn fill_buf(&mut self, timeout: Option<Duration>) -> Result<bool, std::io::Error> {
+        use nix::poll::{poll, PollFd, PollFlags};
+        if let Some(timeout) = timeout {
+            let ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+            let mut fds = [PollFd::new(self.stdout.as_raw_fd(), PollFlags::POLLIN)];
+            let n = loop {
+                match poll(&mut fds, ms) {
+                    Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+                    r => break r.map_err(crate::internals::nix_error)?,
+                }
+            };
+            if n == 0 {
+                return Ok(false);
+            }
+        }
+        let mut chunk = [0u8; 4096];
+        let n = self.stdout.read(&mut chunk)?;
+        if n == 0 {
+            self.eof = true;
+        }
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(true)
+    }
+}
+
+impl Drop for BashChild {
+    fn drop(&mut self) {
+        if !self.detached {
+            signal_group(self.child.id(), Signal::SIGKILL);
+            let _ = self.child.wait();
+        } else if let Ok(None) = self.child.try_wait() {
+            let pid = nix::unistd::Pid::from_raw(self.child.id() as i32);
+            ABANDONED.lock().unwrap().push(pid);
+        }
+    }
+}
+
+/// Wait for any detached scripts whose [`BashChild`] handle was dropped
+/// while they were still running and which have exited since, so they don't linger as
+/// zombie processes.  Returns the number of processes reaped.
+///
+/// This never blocks; call it periodically (e.g. from a housekeeping thread)
+/// in programs which spawn and detach many scripts.
+///
+/// ```
+/// use sh_inline::*;
+/// let mut child = bash_spawn!("exit 0")?;
+/// child.detach();
+/// drop(child);
+/// std::thread::sleep(std::time::Duration::from_millis(100));
+/// reap_finished();
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn reap_finished() -> usize {
+    use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+    let mut abandoned = ABANDONED.lock().unwrap();
+    let before = abandoned.len();
+    abandoned.retain(|&pid| match waitpid(pid, Some(WaitPidFlag::WNOHANG)) {
+        Ok(WaitStatus::StillAlive) => true,
+        Ok(WaitStatus::Exited(..)) | Ok(WaitStatus::Signaled(..)) => false,
+        Ok(_) => true,
+        // Most likely ECHILD: someone else already waited for it
+        Err(_) => false,
+    });
+    before - abandoned.len()
+}
+
+/// Iterator over the output lines of a [`BashChild`]; see [`BashChild::tail`].
+///
+/// [`BashChild::tail`]: struct.BashChild.html#method.tail
+pub struct Tail<'a> {
+    child: &'a mut BashChild,
+}
+
+impl Iterator for Tail<'_> {
+    type Item = Result<String, std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.child.next_buffered_line() {
+                return Some(Ok(line));
+            }
+            if self.child.eof {
+                return None;
+            }
+            if let Err(e) = self.child.fill_buf(None) {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Iterator over the output lines of a script, ending with its exit status;
+/// see [`bash_lines`](macro.bash_lines.html).
+///
+/// Dropping the iterator before it has ended kills the script, as with
+/// [`BashChild`](struct.BashChild.html).
+pub struct Lines {
+    child: BashChild,
+    /// The name given with the `name` option, for the error
+    name: Option<String>,
+    done: bool,
+}
+
+impl Lines {
+    /// Refer to the script as `name` in the error if it fails.
+    pub(crate) fn named(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// The handle of the script.
+    pub fn child(&mut self) -> &mut BashChild {
+        &mut self.child
+    }
+}
+
+impl Iterator for Lines {
+    type Item = Result<String, std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(line) = self.child.tail().next() {
+            return Some(line);
+        }
+        self.done = true;
+        let status = match self.child.wait() {
+            Ok(status) => status,
+            Err(e) => return Some(Err(e)),
+        };
+        crate::Error::check(status, self.name.as_deref(), None, None, &[], 0)
+            .err()
+            .map(Err)
+    }
+}
@@ -0,0 +1,109 @@
+use crate::outputs::OutputCommands;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::time::{Duration, Instant};
+
+/// The line prefix marking the start of a step.
+const STEP_MARKER: &str = "::step::";
+
+/// A phase of a script, delimited by marker lines on its standard output;
+/// see the `steps` option.
+///
+/// A script enters a step by printing `::step::` followed by its name on a
+/// line of its own.  Each step lasts until the next one starts or the
+/// script exits; output before the first marker belongs to no step.
+///
+/// ```
+/// use sh_inline::*;
+/// let r = bash_run!(steps = true, r"echo ::step::fetch; echo data; echo ::step::build")?;
+/// assert_eq!(r.stdout, b"data\n");
+/// let names: Vec<&str> = r.steps.iter().map(|s| s.name.as_str()).collect();
+/// assert_eq!(names, ["fetch", "build"]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    /// The name given in the marker
+    pub name: String,
+    /// Time from spawning the script until the step started
+    pub start: Duration,
+    /// How long the step took
+    pub duration: Duration,
+}
+
+/// Called with the name of each step as it starts.
+pub(crate) type StepHook = Box<dyn FnMut(&str) + Send>;
+
+/// The marker lines to recognize in standard output.
+#[derive(Default)]
+pub(crate) struct Markers {
+    /// Whether to parse step markers
+    pub(crate) steps: bool,
+    pub(crate) on_step: Option<StepHook>,
+    /// Output commands to parse, if any
+    pub(crate) outputs: Option<OutputCommands>,
+}
+
+/// What was recognized by [`Markers::filter`].
+#[derive(Default)]
+pub(crate) struct Parsed {
+    pub(crate) steps: Vec<Step>,
+    pub(crate) outputs: BTreeMap<String, String>,
+}
+
+impl Markers {
+    /// Whether standard output needs to be filtered.
+    pub(crate) fn active(&self) -> bool {
+        self.steps || self.outputs.is_some()
+    }
+
+    /// Copy `input` to `output`, removing the marker lines and recording
+    /// what they mark; steps are timed relative to `start`.
+    pub(crate) fn filter<R: Read, W: Write>(
+        mut self,
+        input: R,
+        mut output: W,
+        start: Instant,
+    ) -> Result<Parsed, std::io::Error> {
+        let mut parsed = Parsed::default();
+        let mut input = BufReader::new(input);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if input.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            // Only complete lines are recognized
+            let text = line
+                .strip_suffix(b"\n")
+                .and_then(|l| std::str::from_utf8(l).ok());
+            let step = text
+                .filter(|_| self.steps)
+                .and_then(|l| l.strip_prefix(STEP_MARKER));
+            let command = text.and_then(|l| self.outputs.as_ref()?.parse_command(l));
+            if let Some(name) = step {
+                let now = start.elapsed();
+                if let Some(last) = parsed.steps.last_mut() {
+                    last.duration = now - last.start;
+                }
+                if let Some(hook) = self.on_step.as_mut() {
+                    hook(name);
+                }
+                parsed.steps.push(Step {
+                    name: name.to_string(),
+                    start: now,
+                    duration: Duration::default(),
+                });
+            } else if let Some((key, value)) = command {
+                parsed.outputs.insert(key, value);
+            } else {
+                output.write_all(&line)?;
+            }
+        }
+        output.flush()?;
+        if let Some(last) = parsed.steps.last_mut() {
+            last.duration = start.elapsed() - last.start;
+        }
+        Ok(parsed)
+    }
+}
@@ -0,0 +1,111 @@
+/// The shell options set at the start of every script, "bash strict mode"
+/// by default: `set -euo pipefail`.
+///
+/// Each flag can be turned off for scripts which need it, e.g. to source
+/// files which reference unset variables, or `xtrace` can be turned on for
+/// debugging; see the `strict` option and
+/// [`Config::strict`](struct.Config.html#method.strict).  Tracing starts
+/// after the bindings, so that their values aren't shown.
+///
+/// ```
+/// use sh_inline::*;
+/// let strict = StrictMode::new().nounset(false);
+/// assert_eq!(bash_output!(strict = strict, r#"echo "[${UNSET_VARIABLE}]""#)?, "[]\n");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StrictMode {
+    errexit: bool,
+    nounset: bool,
+    pipefail: bool,
+    xtrace: bool,
+}
+
+impl StrictMode {
+    /// The default, `set -euo pipefail`.
+    pub const fn new() -> Self {
+        StrictMode {
+            errexit: true,
+            nounset: true,
+            pipefail: true,
+            xtrace: false,
+        }
+    }
+
+    /// No options at all, as for a plain bash script.
+    pub const fn none() -> Self {
+        StrictMode {
+            errexit: false,
+            nounset: false,
+            pipefail: false,
+            xtrace: false,
+        }
+    }
+
+    /// Exit as soon as a command fails (`set -e`).
+    pub fn errexit(mut self, errexit: bool) -> Self {
+        self.errexit = errexit;
+        self
+    }
+
+    /// Treat references to unset variables as errors (`set -u`).
+    pub fn nounset(mut self, nounset: bool) -> Self {
+        self.nounset = nounset;
+        self
+    }
+
+    /// Make a pipeline fail if any of its commands fails (`set -o
+    /// pipefail`); ignored by the `posix` option.
+    pub fn pipefail(mut self, pipefail: bool) -> Self {
+        self.pipefail = pipefail;
+        self
+    }
+
+    /// Trace the commands of the script to standard error (`set -x`).
+    pub fn xtrace(mut self, xtrace: bool) -> Self {
+        self.xtrace = xtrace;
+        self
+    }
+
+    /// Whether the script is traced.
+    pub(crate) fn traces(&self) -> bool {
+        self.xtrace
+    }
+
+    /// The `set` line for the options other than `xtrace`, if any.
+    pub(crate) fn prelude(&self, posix: bool) -> Option<String> {
+        let mut flags = String::new();
+        if self.errexit {
+            flags.push('e');
+        }
+        if self.nounset {
+            flags.push('u');
+        }
+        match (flags.is_empty(), self.pipefail && !posix) {
+            (true, false) => None,
+            (true, true) => Some("set -o pipefail".to_string()),
+            (false, false) => Some(format!("set -{}", flags)),
+            (false, true) => Some(format!("set -{}o pipefail", flags)),
+        }
+    }
+
+    /// The equivalent lines for the `powershell` option; `pipefail` has no
+    /// equivalent.
+    pub(crate) fn powershell_prelude(&self) -> String {
+        let mut prelude = String::new();
+        if self.errexit {
+            prelude.push_str("$ErrorActionPreference = 'Stop'\n");
+            prelude.push_str("$PSNativeCommandUseErrorActionPreference = $true\n");
+        }
+        if self.nounset {
+            prelude.push_str("Set-StrictMode -Version Latest\n");
+        }
+        prelude
+    }
+}
+
+impl Default for StrictMode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
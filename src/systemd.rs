@@ -0,0 +1,80 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+/// Resource limits applied by running the script inside a transient systemd
+/// scope via `systemd-run --scope`; see the `systemd_scope` option.
+///
+/// If `systemd-run` is not installed or the system was not booted with
+/// systemd, the script runs without the scope (and hence without limits).
+///
+/// ```
+/// use sh_inline::*;
+/// let scope = SystemdScope::new().memory_max("512M").cpu_quota("50%");
+/// bash!(systemd_scope = scope, r"true")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct SystemdScope {
+    user: bool,
+    properties: Vec<(String, String)>,
+}
+
+impl Default for SystemdScope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SystemdScope {
+    /// A scope in the user's service manager (`--user`) without limits.
+    pub fn new() -> Self {
+        SystemdScope {
+            user: true,
+            properties: Vec::new(),
+        }
+    }
+
+    /// Use the user's service manager (the default) or the system one.
+    pub fn user(mut self, user: bool) -> Self {
+        self.user = user;
+        self
+    }
+
+    /// Set an arbitrary unit property, e.g. `TasksMax`.
+    pub fn property(mut self, name: &str, value: &str) -> Self {
+        self.properties.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Limit memory usage, e.g. `"512M"` (`MemoryMax=`).
+    pub fn memory_max(self, value: &str) -> Self {
+        self.property("MemoryMax", value)
+    }
+
+    /// Limit CPU time, e.g. `"50%"` (`CPUQuota=`).
+    pub fn cpu_quota(self, value: &str) -> Self {
+        self.property("CPUQuota", value)
+    }
+
+    /// Whether `systemd-run` can be used on this system.
+    pub fn available() -> bool {
+        Path::new("/run/systemd/system").exists()
+            && crate::internals::find_in_path("systemd-run").is_some()
+    }
+
+    /// The command line prefix which runs a program inside the scope.
+    pub(crate) fn wrapper(&self) -> Vec<OsString> {
+        let mut r: Vec<OsString> = vec![crate::internals::wrapper_program("systemd-run")];
+        if self.user {
+            r.push("--user".into());
+        }
+        r.push("--scope".into());
+        r.push("--quiet".into());
+        for (k, v) in self.properties.iter() {
+            r.push("-p".into());
+            r.push(format!("{}={}", k, v).into());
+        }
+        r.push("--".into());
+        r
+    }
+}
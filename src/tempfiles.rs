@@ -0,0 +1,232 @@
+use crate::internals::CommandArg;
+use crate::shell_arg::{ShellArg, ToShellArg};
+use std::collections::BTreeSet;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// The prefix of the names of all temporary files and directories, which
+/// are named `sh-inline-KIND-PID-XXXXXX`.
+const PREFIX: &str = "sh-inline-";
+
+/// The temporary files and directories which currently exist.
+static LIVE: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// A temporary file or directory, registered while it exists so that it
+/// isn't swept.
+pub(crate) struct Temp<T> {
+    value: T,
+    path: PathBuf,
+}
+
+impl<T> Temp<T> {
+    fn register(value: T, path: &Path) -> Self {
+        LIVE.lock().unwrap().insert(path.to_path_buf());
+        Temp {
+            value,
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl<T> Deref for Temp<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> Drop for Temp<T> {
+    fn drop(&mut self) {
+        LIVE.lock().unwrap().remove(&self.path);
+    }
+}
+
+/// The name prefix for a temporary file of `kind`, which mustn't contain
+/// `-`.
+fn prefix(kind: &str) -> String {
+    format!("{}{}-{}-", PREFIX, kind, std::process::id())
+}
+
+/// Create a temporary file of `kind` in the temporary directory.
+pub(crate) fn file(kind: &str) -> Result<Temp<tempfile::NamedTempFile>, std::io::Error> {
+    file_with_suffix(kind, "")
+}
+
+/// Create a temporary file of `kind` whose name ends with `suffix`, e.g. an
+/// extension.
+pub(crate) fn file_with_suffix(
+    kind: &str,
+    suffix: &str,
+) -> Result<Temp<tempfile::NamedTempFile>, std::io::Error> {
+    let file = tempfile::Builder::new()
+        .prefix(&prefix(kind))
+        .suffix(suffix)
+        .tempfile()?;
+    let path = file.path().to_path_buf();
+    Ok(Temp::register(file, &path))
+}
+
+/// Create a temporary directory of `kind` in `parent`, or the temporary
+/// directory.
+pub(crate) fn dir(
+    kind: &str,
+    parent: Option<&Path>,
+) -> Result<Temp<tempfile::TempDir>, std::io::Error> {
+    let prefix = prefix(kind);
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(&prefix);
+    let dir = match parent {
+        Some(parent) => builder.tempdir_in(parent)?,
+        None => builder.tempdir()?,
+    };
+    let path = dir.path().to_path_buf();
+    Ok(Temp::register(dir, &path))
+}
+
+/// The path a temporary file or directory of `kind` would be created at in
+/// `parent`, or the temporary directory, to describe a script without
+/// creating it.
+pub(crate) fn placeholder(kind: &str, parent: Option<&Path>, suffix: &str) -> PathBuf {
+    let parent = parent.map_or_else(std::env::temp_dir, Path::to_path_buf);
+    parent.join(format!("{}XXXXXX{}", prefix(kind), suffix))
+}
+
+/// A temporary file with given contents, bound as its path; see
+/// [`tempfile`](fn.tempfile.html).
+///
+/// The file is removed once both this value and the scripts it was bound
+/// into are gone, i.e. after they exit, whether successfully or not.
+#[derive(Clone)]
+pub struct TempFile(Arc<Temp<tempfile::NamedTempFile>>);
+
+/// Write `contents` into a new temporary file, readable and writable only by
+/// the current user, to bind its path into scripts, e.g. for tools which
+/// read their configuration from a file.
+///
+/// ```
+/// use sh_inline::*;
+/// let config = "[core]\n\tname = example\n";
+/// let out = bash_output!(r#"git config -f "${config}" core.name"#, config = tempfile(config)?)?;
+/// assert_eq!(out, "example\n");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn tempfile<T: AsRef<[u8]>>(contents: T) -> Result<TempFile, std::io::Error> {
+    use std::io::Write;
+    let file = file("data")?;
+    file.as_file().write_all(contents.as_ref())?;
+    Ok(TempFile(Arc::new(file)))
+}
+
+impl TempFile {
+    /// The path of the file.
+    pub fn path(&self) -> &Path {
+        self.0.path()
+    }
+}
+
+impl std::fmt::Debug for TempFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("TempFile").field(&self.path()).finish()
+    }
+}
+
+impl ToShellArg for TempFile {
+    fn to_shell_arg(&self) -> ShellArg {
+        ShellArg(CommandArg::TempFile(self.clone()))
+    }
+}
+
+/// The temporary files and directories currently held by this process,
+/// e.g. for the `private_tmp` option of running scripts.
+pub fn temp_files() -> Vec<PathBuf> {
+    LIVE.lock().unwrap().iter().cloned().collect()
+}
+
+/// Whether the process which created a temporary file, according to its
+/// name, may still be using it.
+fn owner_alive(name: &str) -> bool {
+    let pid = name
+        .strip_prefix(PREFIX)
+        .and_then(|rest| rest.split('-').nth(1))
+        .and_then(|pid| pid.parse::<i32>().ok());
+    match pid {
+        // The process identifier may have been reused after a crash, e.g. in
+        // a container; the registry has our own files
+        Some(pid) if pid as u32 == std::process::id() => false,
+        Some(pid) => !matches!(
+            nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None),
+            Err(nix::Error::Sys(nix::errno::Errno::ESRCH))
+        ),
+        None => false,
+    }
+}
+
+/// Remove the temporary files and directories left behind by processes
+/// which exited without cleaning up, e.g. because they were killed, and
+/// which haven't been modified for `max_age`, returning how many were
+/// removed.
+///
+/// Those of processes which still exist, and those registered by this
+/// process (see [`temp_files`]), are kept.  Nothing is swept unless this is
+/// called, e.g. at startup, and long-running processes can call it
+/// periodically.
+///
+/// ```
+/// use sh_inline::*;
+/// sweep_temp_files(std::time::Duration::from_secs(60 * 60))?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn sweep_temp_files(max_age: Duration) -> Result<usize, std::io::Error> {
+    let mut removed = sweep_dir(&std::env::temp_dir(), max_age)?;
+    #[cfg(target_os = "linux")]
+    {
+        // For the `Scratch::Shm` option
+        let shm = Path::new("/dev/shm");
+        if shm.is_dir() {
+            removed += sweep_dir(shm, max_age)?;
+        }
+    }
+    Ok(removed)
+}
+
+fn sweep_dir(parent: &Path, max_age: Duration) -> Result<usize, std::io::Error> {
+    let now = SystemTime::now();
+    let mut removed = 0;
+    for entry in std::fs::read_dir(parent)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = match name.to_str() {
+            Some(name) if name.starts_with(PREFIX) => name,
+            _ => continue,
+        };
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok());
+        if age.map_or(true, |age| age < max_age) || owner_alive(name) {
+            continue;
+        }
+        let path = entry.path();
+        if LIVE.lock().unwrap().contains(&path) {
+            continue;
+        }
+        let result = if metadata.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        // Files of other users can't be removed, and other processes may be
+        // sweeping at the same time
+        if result.is_ok() {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
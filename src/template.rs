@@ -0,0 +1,185 @@
+use crate::internals::{shell_quote, CommandArg};
+
+enum Node<'a> {
+    Text(&'a str),
+    If {
+        name: &'a str,
+        then: Vec<Node<'a>>,
+        otherwise: Vec<Node<'a>>,
+    },
+    Each {
+        name: &'a str,
+        body: Vec<Node<'a>>,
+    },
+    This,
+    Index,
+}
+
+fn error(msg: String) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("invalid script template: {}", msg),
+    )
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    /// Parse nodes until one of the tags in `end` (or the end of input if
+    /// empty), returning the nodes and the tag which ended them.
+    fn parse(&mut self, end: &[&str]) -> Result<(Vec<Node<'a>>, &'a str), std::io::Error> {
+        let mut nodes = Vec::new();
+        loop {
+            let start = match self.rest.find("{{") {
+                Some(start) => start,
+                None if end.is_empty() => {
+                    if !self.rest.is_empty() {
+                        nodes.push(Node::Text(self.rest));
+                    }
+                    self.rest = "";
+                    return Ok((nodes, ""));
+                }
+                None => return Err(error(format!("missing {{{{{}}}}}", end[0]))),
+            };
+            if start > 0 {
+                nodes.push(Node::Text(&self.rest[..start]));
+            }
+            let len = self.rest[start..]
+                .find("}}")
+                .ok_or_else(|| error("unterminated {{".to_string()))?;
+            let tag = self.rest[start + 2..start + len].trim();
+            self.rest = &self.rest[start + len + 2..];
+            if end.contains(&tag) {
+                return Ok((nodes, tag));
+            }
+            let mut words = tag.split_whitespace();
+            let node = match (words.next(), words.next(), words.next()) {
+                (Some("#if"), Some(name), None) => {
+                    let (then, tag) = self.parse(&["/if", "else"])?;
+                    let otherwise = match tag {
+                        "else" => self.parse(&["/if"])?.0,
+                        _ => Vec::new(),
+                    };
+                    Node::If {
+                        name,
+                        then,
+                        otherwise,
+                    }
+                }
+                (Some("#each"), Some(name), None) => Node::Each {
+                    name,
+                    body: self.parse(&["/each"])?.0,
+                },
+                (Some("this"), None, None) => Node::This,
+                (Some("@index"), None, None) => Node::Index,
+                _ => return Err(error(format!("unexpected {{{{{}}}}}", tag))),
+            };
+            nodes.push(node);
+        }
+    }
+}
+
+/// Whether a binding counts as true for `{{#if}}`: it must be non-empty
+/// and not `false`.
+fn truthy(value: &CommandArg) -> bool {
+    match value {
+        CommandArg::Empty => false,
+        CommandArg::Literal(s) => !(s.is_empty() || s == "false"),
+        CommandArg::Bytes(b) => !(b.is_empty() || b == b"false"),
+        CommandArg::Heredoc(s) => !s.is_empty(),
+        CommandArg::Path(p) => !p.as_os_str().is_empty(),
+        CommandArg::Fd(_) | CommandArg::TempFile(_) => true,
+        CommandArg::Array(l) => !l.is_empty(),
+        CommandArg::Assoc(m) => !m.is_empty(),
+        CommandArg::Secret(s) => !s.expose().is_empty(),
+    }
+}
+
+struct Context<'a, 'b> {
+    bindings: &'b [(String, CommandArg)],
+    /// The index and quoted value of the current `{{#each}}` item
+    item: Option<(usize, String)>,
+    out: &'a mut String,
+}
+
+impl Context<'_, '_> {
+    fn lookup(&self, name: &str) -> Result<&CommandArg, std::io::Error> {
+        self.bindings
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+            .ok_or_else(|| error(format!("unknown variable {}", name)))
+    }
+
+    fn render(&mut self, nodes: &[Node]) -> Result<(), std::io::Error> {
+        for node in nodes {
+            match node {
+                Node::Text(s) => self.out.push_str(s),
+                Node::If {
+                    name,
+                    then,
+                    otherwise,
+                } => {
+                    if truthy(self.lookup(name)?) {
+                        self.render(then)?;
+                    } else {
+                        self.render(otherwise)?;
+                    }
+                }
+                Node::Each { name, body } => {
+                    let items: Vec<String> = match self.lookup(name)? {
+                        CommandArg::Empty => Vec::new(),
+                        CommandArg::Literal(s) | CommandArg::Heredoc(s) => vec![shell_quote(s)],
+                        v @ (CommandArg::Bytes(_)
+                        | CommandArg::Path(_)
+                        | CommandArg::Fd(_)
+                        | CommandArg::TempFile(_)) => {
+                            vec![v.to_string()]
+                        }
+                        CommandArg::Array(l) => l.iter().map(|s| shell_quote(s)).collect(),
+                        CommandArg::Assoc(m) => m.iter().map(|(_, v)| shell_quote(v)).collect(),
+                        CommandArg::Secret(_) => {
+                            return Err(error(format!("cannot expand secret {}", name)));
+                        }
+                    };
+                    let outer = self.item.take();
+                    for (i, item) in items.iter().enumerate() {
+                        self.item = Some((i, item.clone()));
+                        self.render(body)?;
+                    }
+                    self.item = outer;
+                }
+                Node::This | Node::Index => {
+                    let (i, item) = self
+                        .item
+                        .as_ref()
+                        .ok_or_else(|| error("{{this}} outside of {{#each}}".to_string()))?;
+                    let value = match node {
+                        Node::This => item.clone(),
+                        _ => i.to_string(),
+                    };
+                    self.out.push_str(&value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Expand the template `script` using the values of `bindings`.
+pub(crate) fn render(
+    script: &str,
+    bindings: &[(String, CommandArg)],
+) -> Result<String, std::io::Error> {
+    let (nodes, _) = Parser { rest: script }.parse(&[])?;
+    let mut out = String::with_capacity(script.len());
+    Context {
+        bindings,
+        item: None,
+        out: &mut out,
+    }
+    .render(&nodes)?;
+    Ok(out)
+}
@@ -0,0 +1,89 @@
+//! Producing scripts from [Tera] or [Askama] templates (behind the `tera`
+//! and `askama` features respectively).
+//!
+//! The rendered template is passed to the macros like any other script, so
+//! the strict-mode prelude and the quoting of runtime bindings still apply.
+//! Values interpolated by the template itself are not quoted unless the
+//! `shell_quote` filter provided here is used.
+//!
+//! [Tera]: https://docs.rs/tera
+//! [Askama]: https://docs.rs/askama
+
+/// Register the `shell_quote` filter with `tera`, which quotes a value for
+/// use as a single shell word.
+///
+/// ```
+/// use sh_inline::*;
+/// let mut tera = tera::Tera::default();
+/// templates::tera_register(&mut tera);
+/// tera.add_raw_template("cleanup", r#"
+/// {% for d in dirs %}rm -rf {{ d | shell_quote }}/cache
+/// {% endfor %}
+/// test -d "${root}""#)?;
+/// let mut context = tera::Context::new();
+/// context.insert("dirs", &["/tmp/a b", "/tmp/c"]);
+/// let script = templates::tera_render(&tera, "cleanup", &context)?;
+/// let root = "/";
+/// bash!(script, root)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[cfg(feature = "tera")]
+pub fn tera_register(tera: &mut tera::Tera) {
+    tera.register_filter(
+        "shell_quote",
+        |value: &tera::Value, _: &std::collections::HashMap<String, tera::Value>| {
+            let s = match value {
+                tera::Value::String(s) => s.clone(),
+                v => v.to_string(),
+            };
+            Ok(tera::Value::String(crate::internals::shell_quote(&s)))
+        },
+    );
+}
+
+/// Render the template `name` into a script.
+#[cfg(feature = "tera")]
+pub fn tera_render(
+    tera: &tera::Tera,
+    name: &str,
+    context: &tera::Context,
+) -> Result<String, std::io::Error> {
+    tera.render(name, context)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+}
+
+/// Render an Askama template into a script.  Use a non-HTML extension such
+/// as `txt` to avoid HTML escaping, and bring the [`filters`] module into
+/// scope as `filters` to use the `shell_quote` filter.
+///
+/// ```
+/// use askama::Template;
+/// use sh_inline::*;
+/// use sh_inline::templates::filters;
+///
+/// #[derive(Template)]
+/// #[template(source = "test {{ name|shell_quote }} = \"${expected}\"", ext = "txt")]
+/// struct Check<'a> {
+///     name: &'a str,
+/// }
+///
+/// let script = templates::askama_render(&Check { name: "a b" })?;
+/// let expected = "a b";
+/// bash!(script, expected)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[cfg(feature = "askama")]
+pub fn askama_render<T: askama::Template>(template: &T) -> Result<String, std::io::Error> {
+    template
+        .render()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+}
+
+/// Custom Askama filters; see [`askama_render`](../fn.askama_render.html).
+#[cfg(feature = "askama")]
+pub mod filters {
+    /// Quote `value` for use as a single shell word.
+    pub fn shell_quote<T: std::fmt::Display>(value: T) -> askama::Result<String> {
+        Ok(crate::internals::shell_quote(&value.to_string()))
+    }
+}
@@ -0,0 +1,155 @@
+use crate::internals::signal_group;
+use nix::sys::signal::Signal;
+use std::ffi::OsString;
+use std::process::{Child, ExitStatus};
+use std::time::{Duration, Instant};
+
+/// Exit status used by coreutils `timeout` when the time limit expired.
+const COREUTILS_TIMED_OUT: i32 = 124;
+
+/// How the `timeout` option is enforced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeoutMethod {
+    /// Use coreutils `timeout` if it is installed, otherwise `Builtin`.
+    #[default]
+    Auto,
+    /// Wrap the script in coreutils `timeout --kill-after=...`, as found in
+    /// the `PATH` of this process.  This works for all the macros, including
+    /// `bash_command!`, and needs no threads.
+    Coreutils,
+    /// Wait for the script in-process, polling for its exit and sending
+    /// `SIGTERM` followed by `SIGKILL`.  The script runs in its own process
+    /// group, which is signalled as a whole so that the commands it started
+    /// are terminated too.  Not available for `bash_command!` and
+    /// `bash_spawn!`, which return before the script has finished.
+    Builtin,
+}
+
+/// A resolved time limit for a script.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Timeout {
+    pub(crate) method: TimeoutMethod,
+    pub(crate) duration: Duration,
+    pub(crate) grace: Duration,
+}
+
+impl Timeout {
+    pub(crate) fn new(method: TimeoutMethod, duration: Duration, grace: Duration) -> Self {
+        let method = match method {
+            TimeoutMethod::Auto if crate::internals::find_in_path("timeout").is_some() => {
+                TimeoutMethod::Coreutils
+            }
+            TimeoutMethod::Auto => TimeoutMethod::Builtin,
+            m => m,
+        };
+        Timeout {
+            method,
+            duration,
+            grace,
+        }
+    }
+
+    /// The command line prefix implementing the timeout via coreutils, found
+    /// as for `Auto`.
+    pub(crate) fn coreutils_wrapper(&self) -> Vec<OsString> {
+        vec![
+            crate::internals::wrapper_program("timeout"),
+            "--signal=TERM".into(),
+            format!("--kill-after={}", seconds(self.grace)).into(),
+            seconds(self.duration).into(),
+        ]
+    }
+
+    /// The exit status from the coreutils wrapper, which ran for `elapsed`,
+    /// or `None` if the time limit expired.
+    pub(crate) fn check_coreutils(
+        &self,
+        status: ExitStatus,
+        elapsed: Duration,
+    ) -> Option<ExitStatus> {
+        match status.code() {
+            Some(COREUTILS_TIMED_OUT) => None,
+            // 128 + SIGKILL means the script had to be killed after the grace
+            // period, unless it was killed otherwise before the limit
+            Some(137) if elapsed >= self.duration => None,
+            _ => Some(status),
+        }
+    }
+
+    /// Wait for `child`, which leads its own process group, terminating the
+    /// group once the time limit expires; returns `None` if it did.
+    pub(crate) fn wait(&self, child: &mut Child) -> Result<Option<ExitStatus>, std::io::Error> {
+        let deadline = Instant::now() + self.duration;
+        if let Some(status) = wait_until(child, deadline)? {
+            return Ok(Some(status));
+        }
+        signal_group(child.id(), Signal::SIGTERM);
+        let deadline = Instant::now() + self.grace;
+        while !exited(child.id())? && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        // Kill anything which outlived the script, before it is reaped so
+        // that the group can't have been replaced by another one
+        signal_group(child.id(), Signal::SIGKILL);
+        child.wait()?;
+        Ok(None)
+    }
+
+    /// Asynchronously wait for `child` as with `wait`.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn wait_async(
+        &self,
+        child: &mut tokio::process::Child,
+    ) -> Result<Option<ExitStatus>, std::io::Error> {
+        if let Ok(status) = tokio::time::timeout(self.duration, child.wait()).await {
+            return status.map(Some);
+        }
+        let pid = match child.id() {
+            Some(pid) => pid,
+            None => return child.wait().await.map(Some),
+        };
+        signal_group(pid, Signal::SIGTERM);
+        let deadline = Instant::now() + self.grace;
+        while !exited(pid)? && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        signal_group(pid, Signal::SIGKILL);
+        child.wait().await?;
+        Ok(None)
+    }
+}
+
+/// Whether the child process `pid` has exited, without reaping it, so that
+/// its process group ID stays reserved.
+fn exited(pid: u32) -> Result<bool, std::io::Error> {
+    use nix::libc;
+    // SAFETY: siginfo_t is plain data, which waitid() fills in
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let flags = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+    // SAFETY: `info` is valid for writes
+    if unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, flags) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // With WNOHANG, the process ID is left zero unless the child exited
+    // SAFETY: waitid() succeeded, so `info` is initialized
+    Ok(unsafe { info.si_pid() } != 0)
+}
+
+/// Poll for the exit of `child` until `deadline`.
+fn wait_until(child: &mut Child, deadline: Instant) -> Result<Option<ExitStatus>, std::io::Error> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            return Ok(None);
+        }
+        std::thread::sleep(remaining.min(Duration::from_millis(10)));
+    }
+}
+
+/// Format a duration for coreutils `timeout`, which accepts fractional seconds.
+fn seconds(d: Duration) -> String {
+    format!("{}.{:03}s", d.as_secs(), d.subsec_millis())
+}
@@ -0,0 +1,100 @@
+use crate::script::Script;
+use std::fmt;
+
+/// A sequence of scripts, each paired with a script undoing it, which is
+/// applied all or nothing as far as the rollback scripts allow.
+///
+/// The steps are applied in order; if one fails, the rollback scripts of
+/// the steps applied before it are run in reverse order, and the failure
+/// is returned as a [`TransactionError`](struct.TransactionError.html).
+/// A failing rollback script does not stop the others from running.
+///
+/// ```
+/// use sh_inline::*;
+/// let dir = tempfile::tempdir()?;
+/// let dir = dir.path();
+/// let r = Transaction::new()
+///     .step(
+///         Script::new(r#"mkdir "${dir}/a""#).bind("dir", dir),
+///         Script::new(r#"rmdir "${dir}/a""#).bind("dir", dir),
+///     )
+///     .step(Script::new("exit 1").name("failing"), Script::new("true"))
+///     .run();
+/// let e = r.unwrap_err();
+/// assert_eq!(e.step, 1);
+/// assert!(e.rollback_errors.is_empty());
+/// assert!(!dir.join("a").exists());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Default)]
+pub struct Transaction {
+    steps: Vec<(Script, Script)>,
+}
+
+/// Why a [`Transaction`](struct.Transaction.html) failed.
+#[derive(Debug)]
+pub struct TransactionError {
+    /// The index of the step which failed
+    pub step: usize,
+    /// The error from applying it
+    pub error: std::io::Error,
+    /// The rollback scripts which failed too, by step index
+    pub rollback_errors: Vec<(usize, std::io::Error)>,
+}
+
+impl Transaction {
+    /// An empty transaction.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a step applied by `apply` and undone by `rollback`.
+    pub fn step(mut self, apply: Script, rollback: Script) -> Self {
+        self.steps.push((apply, rollback));
+        self
+    }
+
+    /// Apply the steps in order, rolling back the applied ones if any step
+    /// fails.
+    pub fn run(self) -> Result<(), TransactionError> {
+        let mut applied: Vec<(usize, Script)> = Vec::new();
+        for (step, (apply, rollback)) in self.steps.into_iter().enumerate() {
+            if let Err(error) = apply.run() {
+                let rollback_errors = applied
+                    .into_iter()
+                    .rev()
+                    .filter_map(|(i, rollback)| rollback.run().err().map(|e| (i, e)))
+                    .collect();
+                return Err(TransactionError {
+                    step,
+                    error,
+                    rollback_errors,
+                });
+            }
+            applied.push((step, rollback));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "transaction step {} failed: {}", self.step, self.error)?;
+        for (step, e) in self.rollback_errors.iter() {
+            write!(f, "; rolling back step {} failed: {}", step, e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TransactionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<TransactionError> for std::io::Error {
+    fn from(e: TransactionError) -> Self {
+        std::io::Error::new(e.error.kind(), e)
+    }
+}
@@ -0,0 +1,149 @@
+use crate::config::Config;
+use crate::internals::bash_path;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The version of the `bash` used to execute scripts.
+///
+/// ```
+/// use sh_inline::*;
+/// let version = BashVersion::current()?;
+/// if version.supports(BashFeature::WaitN) {
+///     bash!(r"sleep 0 & sleep 0 & wait -n")?;
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BashVersion {
+    /// Major version, e.g. `5`
+    pub major: u32,
+    /// Minor version, e.g. `1`
+    pub minor: u32,
+    /// Patch level
+    pub patch: u32,
+}
+
+/// Bash features which are not available in all versions still in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BashFeature {
+    /// Associative arrays (`declare -A`), since 4.0
+    AssocArrays,
+    /// `${var@Q}` and related parameter transformations, since 4.4
+    ParameterTransform,
+    /// `shopt -s inherit_errexit`, since 4.4
+    InheritErrexit,
+    /// Name references (`declare -n`), since 4.3
+    Nameref,
+    /// `wait -n` to wait for any job, since 4.3
+    WaitN,
+    /// The `EPOCHSECONDS` and `EPOCHREALTIME` variables, since 5.0
+    EpochRealtime,
+}
+
+impl BashFeature {
+    /// The first version providing this feature.
+    pub fn since(self) -> BashVersion {
+        let (major, minor) = match self {
+            BashFeature::AssocArrays => (4, 0),
+            BashFeature::Nameref | BashFeature::WaitN => (4, 3),
+            BashFeature::ParameterTransform | BashFeature::InheritErrexit => (4, 4),
+            BashFeature::EpochRealtime => (5, 0),
+        };
+        BashVersion {
+            major,
+            minor,
+            patch: 0,
+        }
+    }
+}
+
+impl fmt::Display for BashVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Detected versions by bash executable.
+static DETECTED: Mutex<Option<HashMap<PathBuf, Result<BashVersion, String>>>> = Mutex::new(None);
+
+impl BashVersion {
+    /// Detect the version of the `bash` used to execute scripts; the result is
+    /// cached for the lifetime of the process.
+    pub fn current() -> Result<Self, std::io::Error> {
+        Self::of(&bash_path(&Config::current())?)
+    }
+
+    /// Detect the version of the bash executable at `bash`, with caching as
+    /// for [`current`](#method.current).
+    pub fn of(bash: &Path) -> Result<Self, std::io::Error> {
+        let mut detected = DETECTED.lock().unwrap();
+        detected
+            .get_or_insert_with(HashMap::new)
+            .entry(bash.to_path_buf())
+            .or_insert_with_key(|bash| Self::detect(bash).map_err(|e| e.to_string()))
+            .clone()
+            .map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("detecting bash version: {}", e),
+                )
+            })
+    }
+
+    fn detect(bash: &Path) -> Result<Self, std::io::Error> {
+        let out = std::process::Command::new(bash)
+            .args([
+                "-c",
+                "echo ${BASH_VERSINFO[0]} ${BASH_VERSINFO[1]} ${BASH_VERSINFO[2]}",
+            ])
+            .output()?;
+        let parsed: Vec<u32> = String::from_utf8_lossy(&out.stdout)
+            .split_whitespace()
+            .filter_map(|s| s.parse().ok())
+            .collect();
+        match parsed[..] {
+            [major, minor, patch] => Ok(BashVersion {
+                major,
+                minor,
+                patch,
+            }),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "unexpected output",
+            )),
+        }
+    }
+
+    /// Whether this version is at least `major.minor`.
+    pub fn at_least(&self, major: u32, minor: u32) -> bool {
+        (self.major, self.minor) >= (major, minor)
+    }
+
+    /// Whether this version supports `feature`.
+    pub fn supports(&self, feature: BashFeature) -> bool {
+        *self >= feature.since()
+    }
+}
+
+/// Return an `Unsupported` error unless `bash` has all of `features`.
+pub(crate) fn require(bash: &Path, features: &[BashFeature]) -> Result<(), std::io::Error> {
+    if features.is_empty() {
+        return Ok(());
+    }
+    let version = BashVersion::of(bash)?;
+    match features.iter().find(|f| !version.supports(**f)) {
+        Some(f) => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!(
+                "bash {} does not support {:?}, which requires bash {}.{}",
+                version,
+                f,
+                f.since().major,
+                f.since().minor
+            ),
+        )),
+        None => Ok(()),
+    }
+}
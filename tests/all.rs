@@ -1,8 +1,22 @@
 extern crate sh_inline;
-use sh_inline::{bash, bash_command};
+use sh_inline::capture::{self, Stream};
+use sh_inline::quoting::{self, Fish, PowerShell};
+use sh_inline::{
+    awk_prog, bash, bash_command, bash_env, bash_eval, bash_lines, bash_output, bash_plan,
+    bash_run, bash_script, bash_source, bash_spawn, bash_with, bash_with_return_fd, pwsh,
+    reap_finished, sed_expr, sh, sh_command, temp_files, BashFeature, BashSession, BashVersion,
+    Bytes, Config, Distribution, Error, EvalError, Fingerprint, Float, Glob, Heredoc, Limits,
+    OutputCommands, Policy, QuotingProfile, Rebase, Recording, Repl, Resource, Retry, Scratch,
+    Script, Seatbelt, SessionInfo, ShellArg, StrictMode, SystemdScope, TimeoutMethod, ToShellArg,
+    Transaction,
+};
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::OwnedFd;
 use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
 
 #[test]
 fn sh_exit_var() {
@@ -75,3 +89,2342 @@ fn sh_path_binary() {
     let p = Path::new(OsStr::from_bytes(&[0x21, 0, 0xFF, 0x22, 0x61]));
     bash!(r#"test ${p} = $'!\x00\xFF\"a'"#, p).unwrap();
 }
+
+#[test]
+fn spawn_wait_for_output() -> Result<(), std::io::Error> {
+    let port = 4242;
+    let mut child = bash_spawn!(
+        r#"echo starting; sleep 0.1; echo "Listening on port ${port}"; exec sleep 30"#,
+        port
+    )?;
+    let line = child.wait_for_output(r"Listening on port \d+", Duration::from_secs(30))?;
+    assert_eq!(line, "Listening on port 4242");
+    child.kill()?;
+    child.wait()?;
+    Ok(())
+}
+
+#[test]
+fn spawn_wait_for_output_timeout() -> Result<(), std::io::Error> {
+    let mut child = bash_spawn!(r"exec sleep 30")?;
+    let e = child
+        .wait_for_output("never", Duration::from_millis(100))
+        .unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+    child.kill()?;
+    child.wait()?;
+    let mut child = bash_spawn!(r"echo done")?;
+    let e = child
+        .wait_for_output("never", Duration::from_secs(30))
+        .unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert!(child.wait()?.success());
+    Ok(())
+}
+
+#[test]
+fn spawn_tail() -> Result<(), std::io::Error> {
+    let mut child = bash_spawn!(r"echo ready; echo one; echo -n two")?;
+    child.wait_for_output("ready", Duration::from_secs(30))?;
+    let lines = child.tail().collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(lines, ["one", "two"]);
+    assert!(child.wait()?.success());
+    let mut child = bash_spawn!(r"head -c 1000000 /dev/zero")?;
+    let mut output = Vec::new();
+    std::io::Read::read_to_end(child.stdout(), &mut output)?;
+    assert_eq!(output.len(), 1000000);
+    assert!(child.wait()?.success());
+    Ok(())
+}
+
+#[test]
+fn daemonize() -> Result<(), std::io::Error> {
+    let td = tempfile::tempdir()?;
+    let out = td.path().join("out");
+    let ppid = std::process::id();
+    bash!(
+        daemonize = true,
+        r#"sleep 0.2; echo "$(pwd) $(ps -o ppid= -p $$ | tr -d ' ')" > ${out}.tmp; mv ${out}.tmp ${out}"#,
+        out
+    )?;
+    assert!(!out.exists());
+    for _ in 0..100 {
+        if out.exists() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    let contents = std::fs::read_to_string(&out)?;
+    let mut fields = contents.split_whitespace();
+    assert_eq!(fields.next(), Some("/"));
+    assert_ne!(fields.next(), Some(ppid.to_string().as_str()));
+    Ok(())
+}
+
+#[test]
+fn reap_abandoned() -> Result<(), std::io::Error> {
+    let mut child = bash_spawn!(r"exec sleep 0.2")?;
+    let proc_path = format!("/proc/{}", child.id());
+    child.detach();
+    drop(child);
+    assert!(Path::new(&proc_path).exists());
+    for _ in 0..100 {
+        reap_finished();
+        if !Path::new(&proc_path).exists() {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    panic!("child {} was not reaped", proc_path);
+}
+
+#[test]
+fn spawn_kill_on_drop() -> Result<(), std::io::Error> {
+    use std::io::Read;
+    let mut child = bash_spawn!(
+        pipe_stderr = true,
+        r"sleep 30 & echo $!; echo oops >&2; wait"
+    )?;
+    let pid = child.wait_for_output(r"^\d+$", Duration::from_secs(30))?;
+    let mut stderr = String::new();
+    child
+        .stderr()
+        .unwrap()
+        .take(5)
+        .read_to_string(&mut stderr)?;
+    assert_eq!(stderr, "oops\n");
+    let stat = format!("/proc/{}/stat", pid);
+    drop(child);
+    // Killed, so at most a zombie until it is reaped by init
+    for _ in 0..100 {
+        match std::fs::read_to_string(&stat) {
+            Ok(s) if !s.contains(") Z ") => std::thread::sleep(Duration::from_millis(50)),
+            _ => return Ok(()),
+        }
+    }
+    panic!("sleep {} was not killed", pid);
+}
+
+#[test]
+fn parent_death_signal() -> Result<(), std::io::Error> {
+    use std::os::unix::process::ExitStatusExt;
+    let mut child = std::thread::spawn(|| {
+        bash_spawn!(parent_death_signal = nix::libc::SIGKILL, r"exec sleep 30")
+    })
+    .join()
+    .unwrap()?;
+    let status = child.wait()?;
+    assert_eq!(status.signal(), Some(nix::libc::SIGKILL));
+    Ok(())
+}
+
+#[test]
+fn private_tmp() -> Result<(), std::io::Error> {
+    let td = tempfile::tempdir()?;
+    let out = td.path().join("out");
+    bash!(
+        private_tmp = true,
+        r#"test "${TMPDIR}" != /tmp
+f=$(mktemp)
+test "$(dirname ${f})" = "${TMPDIR}"
+echo -n "${TMPDIR}" > ${out}"#,
+        out
+    )?;
+    let tmpdir = std::fs::read_to_string(&out)?;
+    assert!(!Path::new(&tmpdir).exists());
+    Ok(())
+}
+
+#[test]
+fn scratch_shm() -> Result<(), std::io::Error> {
+    let td = tempfile::tempdir()?;
+    let out = td.path().join("out");
+    bash!(
+        scratch = Scratch::Shm,
+        r#"case "${scratch}" in /dev/shm/*) ;; *) exit 1;; esac
+echo data > ${scratch}/file
+echo -n "${scratch}" > ${out}"#,
+        out
+    )?;
+    let scratch = std::fs::read_to_string(&out)?;
+    assert!(!Path::new(&scratch).exists());
+    Ok(())
+}
+
+#[test]
+fn scratch_tmpfs() -> Result<(), std::io::Error> {
+    if !nix::unistd::getuid().is_root() {
+        return Ok(());
+    }
+    let r = bash!(
+        scratch = Scratch::Tmpfs { size: 1 << 20 },
+        r#"test "$(stat -f -c %T ${scratch})" = tmpfs
+! dd if=/dev/zero of=${scratch}/big bs=1M count=2 2>/dev/null"#
+    );
+    match r {
+        // Mounting may still be forbidden, e.g. in an unprivileged container
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => Ok(()),
+        r => r,
+    }
+}
+
+#[test]
+fn helpers() -> Result<(), std::io::Error> {
+    let td = tempfile::tempdir()?;
+    let out = td.path().join("out");
+    bash!(
+        helpers = true,
+        r#"require_cmd mktemp rm
+log "starting"
+tmpfile_tracked t
+echo hello > "${t}"
+n=0
+flaky() { n=$((n + 1)); test ${n} -ge 2; }
+retry 3 0 flaky
+test ${n} = 2
+echo -n "${t}" > ${out}"#,
+        out
+    )?;
+    let t = std::fs::read_to_string(&out)?;
+    assert!(!Path::new(&t).exists());
+    assert!(bash!(helpers = true, r"require_cmd no-such-command-exists").is_err());
+    assert!(bash!(helpers = true, r"retry 2 0 false").is_err());
+    assert!(bash!(helpers = true, r"die oops").is_err());
+    Ok(())
+}
+
+#[test]
+fn shopt() -> Result<(), std::io::Error> {
+    bash!(
+        shopt = ["inherit_errexit", "nullglob"],
+        r#"shopt -q inherit_errexit; shopt -q nullglob; x=(/no/such/*); test ${#x[@]} = 0"#
+    )?;
+    assert!(bash!(r"shopt -q nullglob").is_err());
+    assert!(bash!(shopt = ["x; touch /tmp/oops"], r"true").is_err());
+    Ok(())
+}
+
+#[test]
+fn ifs_and_glob() -> Result<(), std::io::Error> {
+    let words = "a b";
+    bash!(
+        safe_ifs = true,
+        r#"set -- ${words}; test $# = 1; test "$IFS" = $'\n\t'"#,
+        words
+    )?;
+    bash!(glob = Glob::Disabled, r#"x=(/*); test "${x[0]}" = '/*'"#)?;
+    assert!(bash!(glob = Glob::Fail, r"echo /no/such/*").is_err());
+    Ok(())
+}
+
+#[test]
+fn xtrace_fd() -> Result<(), std::io::Error> {
+    use std::io::{Read, Seek};
+    let mut trace = tempfile::tempfile()?;
+    let secret = "not traced";
+    let res = bash_command!(
+        xtrace = trace.try_clone()?,
+        r#"echo "${secret}" > /dev/null; echo to-stderr >&2"#,
+        secret
+    )?
+    .output()?;
+    assert!(res.status.success());
+    assert_eq!(res.stderr, b"to-stderr\n");
+    let mut buf = String::new();
+    trace.seek(std::io::SeekFrom::Start(0))?;
+    trace.read_to_string(&mut buf)?;
+    assert!(buf.contains("+ echo to-stderr"), "{}", buf);
+    assert!(!buf.contains("secret="), "{}", buf);
+    Ok(())
+}
+
+#[test]
+fn capture_interleaved() -> Result<(), std::io::Error> {
+    let r = capture::interleaved(bash_command!(
+        r"echo out1; sleep 0.05; echo err1 >&2; sleep 0.05; echo out2; exit 3"
+    )?)?;
+    assert_eq!(r.status.code(), Some(3));
+    let chunks: Vec<_> = r
+        .chunks
+        .iter()
+        .map(|c| (c.stream, String::from_utf8_lossy(&c.data).into_owned()))
+        .collect();
+    assert_eq!(
+        chunks,
+        [
+            (Stream::Out, "out1\n".to_string()),
+            (Stream::Err, "err1\n".to_string()),
+            (Stream::Out, "out2\n".to_string())
+        ]
+    );
+    assert!(r.chunks.windows(2).all(|w| w[0].elapsed <= w[1].elapsed));
+    Ok(())
+}
+
+#[test]
+fn capture_binary() -> Result<(), std::io::Error> {
+    let r = capture::interleaved(bash_command!(r"printf 'a\xffb'; printf 'ok' >&2")?)?;
+    assert_eq!(r.stdout(), b"a\xffb");
+    assert_eq!(r.stdout_utf8_lossy(), "a\u{fffd}b");
+    assert_eq!(r.stdout_str().unwrap_err().into_bytes(), b"a\xffb");
+    assert_eq!(r.stderr_str().unwrap(), "ok");
+    Ok(())
+}
+
+#[test]
+fn capture_streams() -> Result<(), std::io::Error> {
+    // Enough output on stderr to fill a pipe while stdout is being read
+    let s = capture::streams(bash_command!(
+        r"for i in $(seq 20000); do echo warning $i >&2; done; echo done"
+    )?)?;
+    let out: Vec<String> = s.stdout.iter().collect();
+    assert_eq!(out, ["done"]);
+    assert_eq!(s.stderr.iter().count(), 20000);
+    assert!(s.wait()?.success());
+    Ok(())
+}
+
+#[test]
+fn capture_tagged() -> Result<(), std::io::Error> {
+    let r = capture::tagged(bash_command!(
+        r"echo -n par; echo warn >&2; sleep 0.05; echo tial; echo -n last"
+    )?)?;
+    assert!(r.status.success());
+    assert_eq!(
+        r.lines,
+        [
+            (Stream::Err, "warn".to_string()),
+            (Stream::Out, "partial".to_string()),
+            (Stream::Out, "last".to_string())
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn systemd_scope() -> Result<(), std::io::Error> {
+    let scope = SystemdScope::new().memory_max("256M").cpu_quota("50%");
+    let cmd = bash_command!(systemd_scope = scope.clone(), r"true")?;
+    let expected = if SystemdScope::available() {
+        "systemd-run"
+    } else {
+        "bash"
+    };
+    assert!(Path::new(cmd.get_program()).ends_with(expected));
+    bash!(systemd_scope = scope, r"echo ok >/dev/null")?;
+    Ok(())
+}
+
+#[test]
+fn timeout() -> Result<(), std::io::Error> {
+    use std::time::Instant;
+    for &method in &[TimeoutMethod::Coreutils, TimeoutMethod::Builtin] {
+        let start = Instant::now();
+        let e = bash!(
+            timeout = Duration::from_millis(200),
+            timeout_method = method,
+            r"sleep 10"
+        )
+        .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        bash!(
+            timeout = Duration::from_secs(10),
+            timeout_method = method,
+            r"true"
+        )?;
+        let e = bash!(
+            timeout = Duration::from_secs(10),
+            timeout_method = method,
+            r"exit 137"
+        )
+        .unwrap_err();
+        assert_ne!(e.kind(), std::io::ErrorKind::TimedOut);
+    }
+    let cmd = bash_command!(
+        timeout = Duration::from_secs(1),
+        timeout_method = TimeoutMethod::Coreutils,
+        r"true"
+    )?;
+    assert!(Path::new(cmd.get_program()).ends_with("timeout"));
+    assert!(bash_command!(
+        timeout = Duration::from_secs(1),
+        timeout_method = TimeoutMethod::Builtin,
+        r"true"
+    )
+    .is_err());
+    Ok(())
+}
+
+#[test]
+fn deadline() -> Result<(), std::io::Error> {
+    use std::time::Instant;
+    let deadline = Instant::now() + Duration::from_millis(300);
+    bash!(deadline = deadline, r"sleep 0.1")?;
+    let e = bash!(deadline = deadline, r"sleep 10").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+    assert!(Instant::now() < deadline + Duration::from_secs(5));
+    let e = bash!(deadline = deadline, r"true").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+    Ok(())
+}
+
+#[test]
+fn plan() -> Result<(), std::io::Error> {
+    let target = Path::new("/some path");
+    let plan = bash_plan!(
+        timeout = Duration::from_secs(5),
+        timeout_method = TimeoutMethod::Coreutils,
+        private_tmp = true,
+        r"rm -rf ${target}",
+        target
+    )?;
+    assert!(Path::new(&plan.program).ends_with("timeout"));
+    assert!(Path::new(plan.args.last().unwrap()).ends_with("bash"));
+    assert!(plan.env.iter().any(|(k, v)| k == "TMPDIR" && v.is_some()));
+    assert_eq!(
+        plan.script,
+        "set -euo pipefail\ntarget=\"/some path\"\nrm -rf ${target}"
+    );
+    let preview = plan.to_string();
+    assert!(preview.starts_with(&format!("# command: {}\n", plan.command_line())));
+    assert!(preview.ends_with("rm -rf ${target}\n"));
+    assert!(!plan.env_cleared);
+    // Nothing is set up or probed for a plan
+    let tmpdir = plan.env.iter().find(|(k, _)| k == "TMPDIR").unwrap();
+    assert!(!Path::new(tmpdir.1.as_ref().unwrap()).exists());
+    let plan = bash_plan!(
+        shell = "sh-inline-missing-shell",
+        requires = ["sh-inline-missing-tool"],
+        scratch = Scratch::Shm,
+        isolated_home = true,
+        env_keep = [PATH],
+        r"true"
+    )?;
+    assert_eq!(plan.program, "sh-inline-missing-shell");
+    assert!(plan.script.starts_with("set -euo pipefail\nscratch="));
+    assert!(plan.env_cleared);
+    assert!(plan.to_string().contains("# env: cleared\n"));
+    Ok(())
+}
+
+#[test]
+fn approve() -> Result<(), std::io::Error> {
+    let e = bash!(
+        approve = |plan| !plan.script.contains("rm -rf"),
+        r"rm -rf /nonexistent"
+    )
+    .unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+    bash!(
+        approve = |plan| Path::new(&plan.program).ends_with("bash"),
+        r"true"
+    )?;
+    Ok(())
+}
+
+#[test]
+fn policy() -> Result<(), Box<dyn std::error::Error>> {
+    let policy =
+        Policy::new()
+            .deny(r"/etc")?
+            .check(|input| match input.script.contains("reboot") {
+                true => Err("no rebooting".into()),
+                false => Ok(()),
+            });
+    let path = "/etc/passwd";
+    let e = bash!(policy = policy.clone(), r"cat ${path}", path).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+    assert!(e.to_string().contains("path"));
+    let e = bash!(policy = policy.clone(), r"reboot").unwrap_err();
+    assert!(e.to_string().contains("no rebooting"));
+    bash!(policy = policy, r"true")?;
+    Ok(())
+}
+
+#[test]
+fn prefix() -> Result<(), std::io::Error> {
+    #[allow(non_snake_case)]
+    let PATH = "not a path";
+    bash!(
+        prefix = "my_",
+        r#"test "${PATH}" = "not a path" && test ${#PATH} = 10 && command -v true >/dev/null"#,
+        PATH
+    )?;
+    let plan = bash_plan!(prefix = "my_", r"echo $PATH_X ${PATH:-x}", PATH)?;
+    assert!(plan.script.ends_with("echo $PATH_X ${my_PATH:-x}"));
+    Ok(())
+}
+
+#[test]
+fn special_variables() {
+    #[allow(non_snake_case)]
+    let UID = "0";
+    let e = bash!(r"true", UID).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(e.to_string().contains("UID"));
+    bash!(prefix = "x_", r"test ${UID} = 0", UID).unwrap();
+}
+
+#[test]
+fn dedent() -> Result<(), std::io::Error> {
+    let plan = bash_plan!(
+        dedent = true,
+        r"
+        cat <<EOF
+          indented
+        EOF
+        "
+    )?;
+    assert!(plan.script.ends_with("\ncat <<EOF\n  indented\nEOF\n"));
+    bash!(
+        dedent = true,
+        r#"
+        test "$(cat <<EOF
+          x
+        EOF
+        )" = "  x"
+        "#
+    )?;
+    Ok(())
+}
+
+#[test]
+fn template() -> Result<(), std::io::Error> {
+    let verbose = false;
+    let files = vec!["a b", "c"];
+    let plan = bash_plan!(
+        template = true,
+        r"{{#if verbose}}set -x{{else}}: quiet{{/if}}
+{{#each files}}echo {{@index}} {{this}}
+{{/each}}",
+        verbose,
+        files
+    )?;
+    assert!(plan.script.ends_with(": quiet\necho 0 \"a b\"\necho 1 c\n"));
+    let e = bash!(template = true, r"{{#if missing}}{{/if}}").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    let e = bash!(template = true, r"{{#each files}}", files).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    Ok(())
+}
+
+#[test]
+fn floats() -> Result<(), std::io::Error> {
+    let a = 0.1f32;
+    let b = Float::new(2.0 / 3.0).precision(2);
+    let c = Float::new(1e21);
+    bash!(
+        r#"test "${a} ${b} ${c}" = "0.1 0.67 1000000000000000000000""#,
+        a,
+        b,
+        c
+    )?;
+    Ok(())
+}
+
+#[test]
+fn bytes() -> Result<(), std::io::Error> {
+    let data: Vec<u8> = (1..=255).collect();
+    let all = Bytes(&data[..]);
+    let quote = Bytes(b"it's \"\\\n");
+    bash!(
+        r#"test "$(printf %s "${all}" | od -An -tu1 | wc -w)" = 255 && test "${quote}" = $'it\'s "\\\n'"#,
+        all,
+        quote
+    )?;
+    Ok(())
+}
+
+#[test]
+fn chars_and_bytes() -> Result<(), std::io::Error> {
+    let delim = ':';
+    let tab = Bytes([b'\t']);
+    let field = 2u8;
+    bash!(
+        r#"test "$(echo a:b:c | cut -d "${delim}" -f "${field}")" = b && test "$(printf 'x\ty' | cut -d "${tab}" -f 2)" = y"#,
+        delim,
+        tab,
+        field
+    )?;
+    Ok(())
+}
+
+#[test]
+fn os_strings() -> Result<(), std::io::Error> {
+    use std::os::unix::ffi::OsStrExt;
+    let raw = std::ffi::OsStr::from_bytes(b"caf\xe9");
+    let owned = raw.to_os_string();
+    let utf8 = std::ffi::OsString::from("a b");
+    bash!(
+        r#"test "$(printf %s "${raw}" | od -An -tx1 | tr -d ' ')" = 636166e9 && test "${owned}" = "${raw}" && test "${utf8}" = "a b""#,
+        raw,
+        owned,
+        utf8
+    )?;
+    Ok(())
+}
+
+#[test]
+fn stdin_data() -> Result<(), std::io::Error> {
+    let name = "world";
+    assert_eq!(
+        bash_output!(
+            stdin = "hello\n",
+            r#"read line; echo "${line} ${name}""#,
+            name
+        )?,
+        "hello world\n"
+    );
+    let bytes: &[u8] = b"a\0b";
+    assert_eq!(
+        bash_output!(stdin = bytes, r"od -An -c | tr -d ' \n'")?,
+        "a\\0b"
+    );
+    let mut reader = std::io::Read::chain(&b"x\ny\n"[..], &b"z\n"[..]);
+    assert_eq!(
+        bash_output!(stdin = &mut reader, r"sort -r | head -1")?,
+        "z\n"
+    );
+    struct Failing;
+    impl std::io::Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::new(std::io::ErrorKind::Other, "unreadable"))
+        }
+    }
+    let e = bash!(stdin = &mut Failing, r"cat").unwrap_err();
+    assert_eq!(e.to_string(), "unreadable");
+    Ok(())
+}
+
+#[test]
+fn tempfile_binding() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+    let contents = b"key = 'value'\n\n";
+    let out = bash_output!(
+        r#"cmp "${config}" - <<<"key = 'value'"$'\n'; echo "${config}""#,
+        config = sh_inline::tempfile(&contents[..])?
+    )?;
+    let path = std::path::Path::new(out.trim_end());
+    assert!(!path.exists());
+    let file = sh_inline::tempfile("secret")?;
+    let mode = std::fs::metadata(file.path())?.permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let script = Script::new(r#"cat "${file}"; exit 1"#).bind("file", &file);
+    let path = file.path().to_path_buf();
+    drop(file);
+    assert!(path.exists());
+    assert!(script.run().is_err());
+    assert!(!path.exists());
+    let dir = tempfile::tempdir()?;
+    let record = Recording::Record(dir.path().into());
+    let replay = Recording::Replay(dir.path().into());
+    let run = |recording: Recording| {
+        let file = sh_inline::tempfile("recorded")?;
+        bash_output!(recording = recording, r#"cat "${file}"; date +%s.%N"#, file)
+    };
+    let recorded = run(record.clone())?;
+    assert_eq!(run(replay.clone())?, recorded);
+    let run = |recording: Recording| {
+        let file = sh_inline::tempfile("recorded")?;
+        bash_run!(recording = recording, r#"cat "${file}"; date +%s.%N"#, file)
+    };
+    let recorded = run(record)?;
+    assert_eq!(run(replay)?.stdout, recorded.stdout);
+    Ok(())
+}
+
+#[test]
+fn stdio() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::{Read, Seek, Write};
+    let mut input = tempfile::tempfile()?;
+    input.write_all(b"hello\n")?;
+    input.seek(std::io::SeekFrom::Start(0))?;
+    let mut output = tempfile::tempfile()?;
+    let mut errors = tempfile::tempfile()?;
+    bash!(
+        stdin = input,
+        stdout = output.try_clone()?,
+        stderr = errors.try_clone()?,
+        r"read line; echo ${line} world; echo oops >&2"
+    )?;
+    let mut buf = String::new();
+    output.seek(std::io::SeekFrom::Start(0))?;
+    output.read_to_string(&mut buf)?;
+    assert_eq!(buf, "hello world\n");
+    buf.clear();
+    errors.seek(std::io::SeekFrom::Start(0))?;
+    errors.read_to_string(&mut buf)?;
+    assert_eq!(buf, "oops\n");
+    Ok(())
+}
+
+#[test]
+fn env_keep() -> Result<(), std::io::Error> {
+    std::env::set_var("SH_INLINE_TEST_KEEP", "kept");
+    std::env::set_var("SH_INLINE_TEST_DROP", "dropped");
+    bash!(
+        env_keep = [PATH, SH_INLINE_TEST_KEEP],
+        r#"test "${SH_INLINE_TEST_KEEP}" = kept && test -z "${SH_INLINE_TEST_DROP:-}" && test -z "${HOME:-}" && command -v true >/dev/null"#
+    )?;
+    let keep = vec!["SH_INLINE_TEST_DROP"];
+    bash!(
+        env_keep = keep,
+        r#"test "${SH_INLINE_TEST_DROP}" = dropped"#
+    )?;
+    Ok(())
+}
+
+#[test]
+fn isolated_home() -> Result<(), std::io::Error> {
+    let real = std::env::var("HOME").unwrap_or_default();
+    let home = bash_command!(
+        isolated_home = true,
+        r#"test "${HOME}" != "${real}" && test -d "${XDG_CONFIG_HOME}" && test "${XDG_CACHE_HOME}" = "${HOME}/.cache" && touch ~/.bashrc && echo "${HOME}""#,
+        real
+    )?
+    .output()?;
+    assert!(home.status.success());
+    let home = String::from_utf8(home.stdout).unwrap();
+    assert!(!Path::new(home.trim()).exists());
+    Ok(())
+}
+
+#[test]
+fn bash_version() -> Result<(), std::io::Error> {
+    let version = BashVersion::current()?;
+    assert!(version.at_least(3, 2));
+    let r = bash!(
+        bash_features = [BashFeature::AssocArrays],
+        r"declare -A m=([k]=v); test ${m[k]} = v"
+    );
+    assert_eq!(r.is_ok(), version.supports(BashFeature::AssocArrays));
+    Ok(())
+}
+
+#[test]
+fn fast_path() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let dest = dir.path().join("a b");
+    let plan = bash_plan!(fast_path = true, r#"touch "${dest}""#, dest)?;
+    assert_eq!(plan.program, "touch");
+    assert_eq!(plan.args, [dest.as_os_str()]);
+    bash!(fast_path = true, r#"touch "${dest}""#, dest)?;
+    assert!(dest.exists());
+    for script in [
+        r"touch ${dest}",
+        r#"touch "${dest}" && true"#,
+        r"cd /",
+        r"FOO=1 true",
+        r"echo -e ${dest}",
+        r"printf x",
+    ] {
+        let plan = bash_plan!(fast_path = true, script, dest)?;
+        assert!(Path::new(&plan.program).ends_with("bash"));
+    }
+    assert!(bash!(fast_path = true, r"false").is_err());
+    // Programs are looked up in the PATH of the script
+    let tool = dir.path().join("sh-inline-fast-tool");
+    std::fs::write(&tool, "#!/bin/sh\necho fast\n")?;
+    std::fs::set_permissions(&tool, std::os::unix::fs::PermissionsExt::from_mode(0o755))?;
+    let script = || Script::new("sh-inline-fast-tool").fast_path(true);
+    assert!(Path::new(&script().plan()?.program).ends_with("bash"));
+    let dirs = [dir.path(), Path::new("/usr/bin"), Path::new("/bin")];
+    assert_eq!(
+        script().tool_dirs(dirs).plan()?.program,
+        "sh-inline-fast-tool"
+    );
+    assert_eq!(script().tool_dirs(dirs).output()?, "fast\n");
+    Ok(())
+}
+
+#[test]
+fn interpreter() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let link = dir.path().join("my-bash");
+    std::os::unix::fs::symlink("/bin/bash", &link)?;
+    let plan = bash_plan!(bash = &link, r"true")?;
+    assert_eq!(plan.program, link.as_os_str());
+    bash!(bash = &link, r#"test "${BASH}" = "${link}""#, link)?;
+    bash_with!(&link, r#"test "${BASH}" = "${link}""#, link)?;
+    let e = bash_with!(dir.path().join("missing"), r"true").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    let path =
+        std::env::join_paths([dir.path(), Path::new("/usr/bin"), Path::new("/bin")]).unwrap();
+    for shell in ["/usr/bin/env my-bash", "my-bash"] {
+        let script = || Script::new(r#"test "${BASH}" = "${link}""#).bind("link", &link);
+        script().env_var("PATH", &path).shell(shell).run()?;
+        let e = script().shell(shell).run().unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    }
+    let e = bash!(shell = "/usr/bin/env -i bash", r"true").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    std::env::set_var("SH_INLINE_BASH", "/bin/bash");
+    let plan = bash_plan!(r"true");
+    std::env::remove_var("SH_INLINE_BASH");
+    assert_eq!(plan?.program, "/bin/bash");
+    Ok(())
+}
+
+#[test]
+fn seatbelt() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let sandbox = Seatbelt::new().writable(dir.path()).network(true);
+    let profile = sandbox.profile();
+    assert!(!profile.contains("(deny network*)"));
+    assert!(profile.contains(&format!(
+        "(allow file-write* (subpath \"{}\"))",
+        dir.path().canonicalize()?.display()
+    )));
+    let path = dir.path();
+    let r = bash!(seatbelt = sandbox, r"touch ${path}/ok", path);
+    if cfg!(target_os = "macos") {
+        r?;
+    } else {
+        assert_eq!(r.unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+    }
+    Ok(())
+}
+
+#[test]
+fn eval() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let p = dir.path().join("f");
+    std::fs::write(&p, b"hello")?;
+    let n: u64 = bash_eval!(r"stat -c %s ${p}", p)?;
+    assert_eq!(n, 5);
+    let s: String = bash_eval!(r"echo '  padded  '; echo ignored >&2")?;
+    assert_eq!(s, "padded");
+    match bash_eval!(r"echo nope") as Result<u64, _> {
+        Err(EvalError::Parse { output, .. }) => assert_eq!(output, "nope"),
+        r => panic!("unexpected {:?}", r),
+    }
+    let r: Result<u64, _> = bash_eval!(r"echo 1; false");
+    assert!(matches!(r, Err(EvalError::Io(_))));
+    let e: std::io::Error = bash_eval!(r"echo x").map(|_: u32| ()).unwrap_err().into();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    Ok(())
+}
+
+#[test]
+fn heredoc() -> Result<(), std::io::Error> {
+    for text in [
+        "",
+        "no newline",
+        "SH_INLINE_EOF\n\n\n",
+        "$(false) `false` ${x} '\"\\\n.\n",
+    ] {
+        let text = Heredoc(text);
+        let s: String = bash_eval!(r#"printf '%s|' "${text}""#, text)?;
+        assert_eq!(s, format!("{}|", text.0).trim());
+    }
+    let text = Heredoc("line\nSH_INLINE_EOF\n");
+    let plan = bash_plan!(r"true", text)?;
+    assert!(plan
+        .script
+        .contains("<<'SH_INLINE_EOF_1'\nline\nSH_INLINE_EOF\n"));
+    Ok(())
+}
+
+#[test]
+fn rebase_paths() -> Result<(), std::io::Error> {
+    let rel = Path::new("some/file");
+    let cwd = std::env::current_dir()?;
+    let expected = cwd.join(rel);
+    bash!(
+        rebase_paths = Rebase::Parent,
+        r#"cd / && test "${rel}" = "${expected}""#,
+        rel,
+        expected
+    )?;
+    let expected = Path::new("/srv/some/file");
+    bash!(
+        rebase_paths = Rebase::Dir("/srv".into()),
+        r#"test "${rel}" = "${expected}""#,
+        rel,
+        expected
+    )?;
+    let e = bash!(rebase_paths = Rebase::Canonical, r"true", rel).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    bash!(r#"test "${rel}" = some/file"#, rel)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+struct NoSuchFile(String);
+
+impl std::fmt::Display for NoSuchFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "no such file: {}", self.0)
+    }
+}
+
+impl std::error::Error for NoSuchFile {}
+
+#[test]
+fn on_stderr() -> Result<(), std::io::Error> {
+    let classify = |stderr: &str| {
+        stderr
+            .lines()
+            .find(|l| l.contains("No such file"))
+            .map(|l| std::io::Error::new(std::io::ErrorKind::NotFound, NoSuchFile(l.to_string())))
+    };
+    let e = bash!(on_stderr = classify, r"cat /nonexistent").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    assert!(e.get_ref().unwrap().downcast_ref::<NoSuchFile>().is_some());
+    let e = bash!(on_stderr = classify, r"echo other >&2; exit 1").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::Other);
+    let n: u32 = bash_eval!(on_stderr = classify, r"echo 42")?;
+    assert_eq!(n, 42);
+    Ok(())
+}
+
+#[test]
+fn name() -> Result<(), Box<dyn std::error::Error>> {
+    let e = bash!(name = "ostree-pull", r"exit 3").unwrap_err();
+    assert!(e.to_string().contains("\"ostree-pull\" failed"));
+    let policy = Policy::new().check(|input| match input.name {
+        Some("allowed") => Ok(()),
+        _ => Err("unknown script".into()),
+    });
+    bash!(name = "allowed", policy = policy.clone(), r"true")?;
+    let e = bash!(name = "other", policy = policy, r"true").unwrap_err();
+    assert!(e.to_string().contains("\"other\" denied by policy"));
+    let plan = bash_plan!(name = "step", r"true")?;
+    assert_eq!(plan.name.as_deref(), Some("step"));
+    assert!(plan.to_string().starts_with("# name: step\n"));
+    Ok(())
+}
+
+#[test]
+fn single_flight() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let counter = dir.path().join("counter");
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let counter = counter.clone();
+            std::thread::spawn(move || -> Result<u32, EvalError<std::num::ParseIntError>> {
+                bash_eval!(
+                    single_flight = true,
+                    r"echo x >> ${counter}; sleep 1; wc -l < ${counter}",
+                    counter
+                )
+            })
+        })
+        .collect();
+    let results: Vec<u32> = threads
+        .into_iter()
+        .map(|t| t.join().unwrap().unwrap())
+        .collect();
+    assert_eq!(results, [1, 1, 1, 1]);
+    let e = bash!(single_flight = true, stdout = Stdio::null(), r"echo out").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(bash_output!(single_flight = true, r"echo out")?, "out\n");
+    Ok(())
+}
+
+#[test]
+fn env_diff() -> Result<(), std::io::Error> {
+    std::env::set_var("SH_INLINE_TEST_UNSET", "1");
+    std::env::set_var("SH_INLINE_TEST_SAME", "same");
+    let value = "a b\nc";
+    let diff = bash_env!(
+        r"export SH_INLINE_TEST_NEW=${value}
+        unset SH_INLINE_TEST_UNSET
+        export SH_INLINE_TEST_SAME=same
+        not_exported=1",
+        value
+    )?;
+    assert_eq!(diff.set.len(), 1);
+    assert_eq!(diff.set["SH_INLINE_TEST_NEW"], "a b\nc");
+    assert!(diff.unset.contains("SH_INLINE_TEST_UNSET"));
+    assert_eq!(diff.unset.len(), 1);
+    assert!(bash_env!(r"true")?.is_empty());
+    let e = bash_env!(r"trap - EXIT").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    Ok(())
+}
+
+#[test]
+fn source() -> Result<(), std::io::Error> {
+    std::env::set_var("SH_INLINE_TEST_SOURCE_REMOVED", "1");
+    let diff = bash_source!(
+        r"export SH_INLINE_TEST_SOURCED=yes
+        unset SH_INLINE_TEST_SOURCE_REMOVED"
+    )?;
+    assert_eq!(std::env::var("SH_INLINE_TEST_SOURCED").unwrap(), "yes");
+    assert!(std::env::var_os("SH_INLINE_TEST_SOURCE_REMOVED").is_none());
+    let mut env = HashMap::new();
+    env.insert("SH_INLINE_TEST_SOURCE_REMOVED".into(), "1".into());
+    diff.select(&["SH_INLINE_TEST_SOURCE_REMOVED"])
+        .apply_to(&mut env);
+    assert!(env.is_empty());
+    Ok(())
+}
+
+#[test]
+fn reproducible() -> Result<(), std::io::Error> {
+    std::env::set_var("SH_INLINE_TEST_LEAKED", "1");
+    bash!(
+        reproducible = true,
+        r#"test "${LC_ALL}" = C
+        test "${TZ}" = UTC
+        test "$(umask)" = 0022
+        test -z "${SH_INLINE_TEST_LEAKED:-}"
+        test -n "${PATH}""#
+    )?;
+    let plan = bash_plan!(reproducible = true, r"true")?;
+    assert!(plan.args.iter().any(|a| a == "--norc"));
+    Ok(())
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+#[test]
+fn compressed() -> Result<(), std::io::Error> {
+    use sh_inline::capture::Compression;
+    let cmd = || bash_command!(r"for i in $(seq 1 1000); do echo line; done; echo err >&2; exit 3");
+    #[cfg(feature = "gzip")]
+    {
+        use std::io::Read;
+        let r = capture::compressed(cmd()?, Compression::Gzip, Vec::new())?;
+        assert_eq!(r.status.code(), Some(3));
+        let mut log = String::new();
+        flate2::read::GzDecoder::new(&r.output[..]).read_to_string(&mut log)?;
+        assert_eq!(log.len() as u64, r.len);
+        assert!(log.ends_with("line\nerr\n"));
+    }
+    #[cfg(feature = "zstd")]
+    {
+        let r = capture::compressed(cmd()?, Compression::Zstd(0), Vec::new())?;
+        let log = zstd::stream::decode_all(&r.output[..])?;
+        assert_eq!(log.len(), 5004);
+    }
+    Ok(())
+}
+
+#[test]
+fn output() -> Result<(), std::io::Error> {
+    let word = "a b";
+    assert_eq!(
+        bash_output!(r#"printf '%s\n' "${word}" c"#, word)?,
+        "a b\nc\n"
+    );
+    assert_eq!(bash_output!(trim = true, r"echo '  x  '")?, "x");
+    let e = bash_output!(r"printf '\xff'").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    assert!(bash_output!(r"echo x; exit 1").is_err());
+    Ok(())
+}
+
+#[test]
+fn prelude_epilogue() -> Result<(), std::io::Error> {
+    let out = bash_output!(
+        prelude = "start=1",
+        prelude = "echo start",
+        epilogue = r#"echo "end ${start} $?""#,
+        r"echo body"
+    )?;
+    assert_eq!(out, "start\nbody\nend 1 0\n");
+    let dir = tempfile::tempdir()?;
+    let marker = dir.path().join("cleaned");
+    let r = bash!(
+        epilogue = r#"echo "status $?" > "${marker}""#,
+        helpers = true,
+        r"tmpfile_tracked f; exit 3",
+        marker
+    );
+    assert!(r.is_err());
+    assert_eq!(std::fs::read_to_string(&marker)?, "status 3\n");
+    let diff = bash_env!(epilogue = "export SH_INLINE_TEST_EPILOGUE=1", r"true")?;
+    assert!(diff.set.contains_key("SH_INLINE_TEST_EPILOGUE"));
+    Ok(())
+}
+
+#[test]
+fn run_output() -> Result<(), std::io::Error> {
+    let r = bash_run!(r"printf 'a\xff'; sleep 0.2; echo oops >&2; exit 4")?;
+    assert!(!r.success());
+    assert_eq!(r.stdout, b"a\xff");
+    assert_eq!(r.stdout_utf8_lossy(), "a\u{fffd}");
+    assert!(r.stdout_str().is_err());
+    assert_eq!(r.stderr_str().unwrap(), "oops\n");
+    assert!(r.duration >= Duration::from_millis(200));
+    assert!(r.check().is_err());
+    let r = bash_run!(timeout = Duration::from_millis(100), r"sleep 5");
+    assert_eq!(r.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+    assert!(bash_run!(r"true")?.check()?.stdout.is_empty());
+    Ok(())
+}
+
+#[test]
+fn on_exit() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let log = dir.path().join("log");
+    let r = bash!(
+        on_exit = r#"echo first >> "${log}""#,
+        on_exit = r#"echo "${undefined}"; echo never >> "${log}""#,
+        on_exit = r#"false; echo last >> "${log}""#,
+        r"exit 5",
+        log
+    );
+    assert_eq!(
+        r.unwrap_err().to_string(),
+        "bash script failed: exit status: 5"
+    );
+    assert_eq!(std::fs::read_to_string(&log)?, "last\nfirst\n");
+    Ok(())
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio() -> Result<(), std::io::Error> {
+    use sh_inline::{bash_async, bash_command_async};
+    let a = "a b";
+    bash_async!(r#"test "${a}" = "a b""#, a).await?;
+    let e = bash_async!(name = "failing", r"exit 2").await.unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        r#"bash script "failing" failed: exit status: 2"#
+    );
+    let e = bash_async!(
+        timeout = Duration::from_millis(100),
+        timeout_method = TimeoutMethod::Builtin,
+        r"sleep 5"
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+    let out = bash_command_async!(r"echo out")?.output().await?;
+    assert_eq!(out.stdout, b"out\n");
+    Ok(())
+}
+
+#[test]
+fn recording() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let record = Recording::Record(dir.path().into());
+    let replay = Recording::Replay(dir.path().into());
+    let n = 3;
+    let r = bash_run!(
+        recording = record.clone(),
+        r"echo $RANDOM; echo ${n} >&2; exit ${n}",
+        n
+    )?;
+    assert_eq!(r.status.code(), Some(3));
+    let replayed = bash_run!(
+        recording = replay.clone(),
+        r"echo $RANDOM; echo ${n} >&2; exit ${n}",
+        n
+    )?;
+    assert_eq!(replayed.stdout, r.stdout);
+    assert_eq!(replayed.stderr, b"3\n");
+    assert_eq!(replayed.status, r.status);
+    assert_eq!(replayed.duration, r.duration);
+    let e = bash!(
+        recording = replay.clone(),
+        r"echo $RANDOM; echo ${n} >&2; exit ${n}",
+        n
+    );
+    assert_eq!(
+        e.unwrap_err().to_string(),
+        "bash script failed: exit status: 3"
+    );
+    let e = bash!(recording = replay, r"echo $RANDOM").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    Ok(())
+}
+
+#[test]
+fn fan_out() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let dir = dir.path();
+    let consumer = |i: usize| {
+        bash_command!(
+            stdin = Stdio::piped(),
+            r#"while read -r line; do echo "${line}" >> "${dir}/${i}"; done"#,
+            dir,
+            i
+        )
+    };
+    let producer = bash_command!(r"for i in 1 2 3; do echo a; echo b; done")?;
+    let r = sh_inline::fan_out(producer, 2, Distribution::Hash, consumer)?;
+    r.check()?;
+    let mut outputs = [
+        std::fs::read_to_string(dir.join("0")).unwrap_or_default(),
+        std::fs::read_to_string(dir.join("1")).unwrap_or_default(),
+    ];
+    outputs.sort();
+    assert!(outputs == ["", "a\na\na\nb\nb\nb\n"] || outputs == ["a\na\na\n", "b\nb\nb\n"]);
+    // An early exit of one consumer sends the remaining lines to the others
+    let r = sh_inline::fan_out(
+        bash_command!(r"seq 1 1000")?,
+        2,
+        Distribution::RoundRobin,
+        |i| {
+            bash_command!(
+                stdin = Stdio::piped(),
+                r#"[ "${i}" = 0 ] || exit 7; wc -l > "${dir}/count""#,
+                dir,
+                i
+            )
+        },
+    )?;
+    assert!(!r.success());
+    assert_eq!(r.consumers[1].code(), Some(7));
+    assert_eq!(
+        r.check().unwrap_err().to_string(),
+        "consumer bash script 1 failed: exit status: 7"
+    );
+    let count: usize = std::fs::read_to_string(dir.join("count"))?
+        .trim()
+        .parse()
+        .unwrap();
+    assert!(count >= 500);
+    Ok(())
+}
+
+#[test]
+fn expression_bindings() -> Result<(), std::io::Error> {
+    struct Ctx {
+        dir: tempfile::TempDir,
+    }
+    let ctx = Ctx {
+        dir: tempfile::tempdir()?,
+    };
+    let n = 2;
+    bash!(
+        r#"touch "${dir}/f"; test "${count}" = 3; test "${n}" = 2"#,
+        dir = ctx.dir.path(),
+        n,
+        count = n + 1,
+    )?;
+    assert!(ctx.dir.path().join("f").exists());
+    let out = bash_output!(
+        trim = true,
+        r#"echo "${greeting}""#,
+        greeting = format!("{} {}", "a", "b")
+    )?;
+    assert_eq!(out, "a b");
+    Ok(())
+}
+
+#[test]
+fn fd_bindings() -> Result<(), std::io::Error> {
+    let tmp = tempfile::tempdir()?;
+    let dir = std::fs::File::open(tmp.path())?;
+    // The directory stays reachable through the descriptor after a rename
+    let moved = tmp.path().with_extension("moved");
+    std::fs::rename(tmp.path(), &moved)?;
+    bash!(r#"cd "${dir}"; echo hi > f"#, dir)?;
+    assert_eq!(std::fs::read_to_string(moved.join("f"))?, "hi\n");
+    let fd = OwnedFd::from(std::fs::File::open(moved.join("f"))?);
+    assert_eq!(bash_output!(r#"cat "${fd}""#, fd)?, "hi\n");
+    std::fs::remove_dir_all(&moved)?;
+    Ok(())
+}
+
+#[cfg(feature = "macros")]
+#[test]
+fn bash_fmt() -> Result<(), std::io::Error> {
+    use sh_inline::bash_fmt;
+    let dir = tempfile::tempdir()?;
+    let src = dir.path().join("a b");
+    let dst = dir.path().join("c d");
+    let n = 2;
+    bash_fmt!(
+        name = "copy",
+        r#"seq 1 {n} > {src}
+        # it's a comment with {{braces}}
+        cp {src} "{dst}"
+        test "$(wc -l < {dst})" = "{n}"
+        test "${HOME}" = "${home}""#,
+        home = std::env::var("HOME").unwrap()
+    )?;
+    assert_eq!(std::fs::read_to_string(&dst)?, "1\n2\n");
+    let e = bash_fmt!(name = "failing", "exit {n}").unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        r#"bash script "failing" failed: exit status: 2"#
+    );
+    Ok(())
+}
+
+#[test]
+fn time_vars() -> Result<(), std::io::Error> {
+    bash!(
+        time_vars = true,
+        timeout = std::time::Duration::from_secs(60),
+        r#"now=$(date +%s)
+        test "${SH_INLINE_START_EPOCH}" -le "${now}"
+        remaining=$((SH_INLINE_DEADLINE_EPOCH - now))
+        test "${remaining}" -gt 50 && test "${remaining}" -le 60"#
+    )?;
+    bash!(
+        time_vars = true,
+        r#"test -n "${SH_INLINE_START_EPOCH}" && test -z "${SH_INLINE_DEADLINE_EPOCH:-}""#
+    )?;
+    bash!(r#"test -z "${SH_INLINE_START_EPOCH:-}""#)?;
+    Ok(())
+}
+
+#[cfg(feature = "macros")]
+#[test]
+fn bash_checked() -> Result<(), std::io::Error> {
+    use sh_inline::bash_checked;
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("lines");
+    bash_checked!(
+        r#"count=0
+        for i in 1 2 3; do echo "line ${i}" >> "${path}"; done
+        while read -r line; do count=$((count + 1)); done < "${path}"
+        printf -v total '%d' "${count}"
+        test "${total}" = 3
+        test "${missing:-default}" = default
+        test -n "${HOME}"
+        echo '${quoted}' "\${escaped}" >/dev/null
+        # ${commented}"#,
+        path
+    )?;
+    bash_checked!(scratch = Scratch::Shm, r#"test -d "${scratch}""#)?;
+    Ok(())
+}
+
+#[test]
+fn error() -> Result<(), std::io::Error> {
+    let e = bash!(
+        name = "noisy",
+        stderr_tail = 8,
+        r"echo first >&2; echo 0123456789 >&2; exit 4"
+    )
+    .unwrap_err();
+    let failure = sh_inline::Error::of(&e).expect("script failure");
+    assert_eq!(failure.name(), Some("noisy"));
+    assert_eq!(failure.code(), Some(4));
+    assert_eq!(failure.signal(), None);
+    assert_eq!(failure.stderr(), b"3456789\n");
+    assert!(failure.script().unwrap().contains("echo first"));
+    assert_eq!(
+        e.to_string(),
+        "bash script \"noisy\" failed: exit status: 4\nstandard error:\n3456789"
+    );
+    let e = bash!(r"kill -TERM $$").unwrap_err();
+    assert_eq!(sh_inline::Error::of(&e).unwrap().signal(), Some(15));
+    let e = bash!(bash = "/nonexistent/bash", r"true").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    assert!(matches!(
+        sh_inline::Error::of(&e),
+        Some(sh_inline::Error::Spawn { .. })
+    ));
+    assert!(
+        sh_inline::Error::of(&std::io::Error::new(std::io::ErrorKind::Other, "unrelated"))
+            .is_none()
+    );
+    Ok(())
+}
+
+#[test]
+fn steps() -> Result<(), std::io::Error> {
+    let r = bash_run!(
+        steps = true,
+        r"echo before
+        echo ::step::first
+        sleep 0.2
+        echo ::step::second
+        echo ::step::not-a-marker-at-eof | tr -d '\n'"
+    )?;
+    assert_eq!(r.stdout, b"before\n::step::not-a-marker-at-eof");
+    let names: Vec<&str> = r.steps.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["first", "second"]);
+    assert!(r.steps[0].duration >= std::time::Duration::from_millis(200));
+    assert!(r.steps[1].start >= r.steps[0].start + r.steps[0].duration);
+    let started = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let s = started.clone();
+    let out = bash_output!(
+        on_step = move |name: &str| s.lock().unwrap().push(name.to_string()),
+        r"echo ::step::a; echo output; echo ::step::b"
+    )?;
+    assert_eq!(out, "output\n");
+    assert_eq!(*started.lock().unwrap(), ["a", "b"]);
+    bash!(steps = true, r"echo ::step::shown")?;
+    assert!(bash_run!(r"echo ::step::a")?.steps.is_empty());
+    Ok(())
+}
+
+#[test]
+fn outputs() -> Result<(), Box<dyn std::error::Error>> {
+    let r = bash_run!(
+        outputs = OutputCommands::new(),
+        r#"echo "::set-output name=multi::a%0Ab%25"
+        echo kept
+        echo "count=3" >> "${SH_INLINE_OUTPUT}"
+        printf 'notes<<EOF\nx=1\ny<<2\nEOF\n' >> "${SH_INLINE_OUTPUT}""#
+    )?;
+    assert_eq!(r.stdout, b"kept\n");
+    assert_eq!(r.outputs["multi"], "a\nb%");
+    assert_eq!(r.outputs["notes"], "x=1\ny<<2");
+    assert_eq!(r.parse_output::<u8>("count")?, 3);
+    assert!(matches!(
+        r.parse_output::<u8>("multi"),
+        Err(EvalError::Parse { .. })
+    ));
+    match r.parse_output::<u8>("missing") {
+        Err(EvalError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+        _ => panic!("expected NotFound"),
+    }
+    let r = bash_run!(
+        outputs = OutputCommands::new()
+            .marker("::out")
+            .file_var("GITHUB_OUTPUT"),
+        r#"echo "::out name=a::1"; echo "::set-output name=b::2"; echo "c=3" >> "${GITHUB_OUTPUT}""#
+    )?;
+    assert_eq!(r.stdout, b"::set-output name=b::2\n");
+    assert_eq!(r.outputs.len(), 2);
+    assert_eq!(r.outputs["c"], "3");
+    Ok(())
+}
+
+#[test]
+fn script_builder() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("script.sh");
+    std::fs::write(
+        &path,
+        "echo \"${n} ${name}\" > \"${out}\"\nexit \"${code}\"\n",
+    )?;
+    let text = std::fs::read_to_string(&path)?;
+    let out = dir.path().join("out");
+    let script = |code: i32| {
+        Script::new(&text)
+            .name("loaded")
+            .bind("n", &3)
+            .bind("name", "a b")
+            .bind("out", &out)
+            .bind("code", &code)
+    };
+    script(0).run()?;
+    assert_eq!(std::fs::read_to_string(&out)?, "3 a b\n");
+    assert!(script(0).command()?.status()?.success());
+    let e = script(1).run().unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        r#"bash script "loaded" failed: exit status: 1"#
+    );
+    Ok(())
+}
+
+#[test]
+fn transaction() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let log = dir.path().join("log");
+    let step = |n: i32, fail: bool| {
+        let apply = Script::new(r#"echo "apply ${n}" >> "${log}"; test "${fail}" = false"#)
+            .bind("n", &n)
+            .bind("log", &log)
+            .bind("fail", &fail);
+        let rollback = Script::new(r#"echo "rollback ${n}" >> "${log}"; test "${n}" != 1"#)
+            .bind("n", &n)
+            .bind("log", &log);
+        (apply, rollback)
+    };
+    let (a0, r0) = step(0, false);
+    let (a1, r1) = step(1, false);
+    let (a2, r2) = step(2, false);
+    let (a3, r3) = step(3, true);
+    let e = Transaction::new()
+        .step(a0, r0)
+        .step(a1, r1)
+        .step(a2, r2)
+        .step(a3, r3)
+        .run()
+        .unwrap_err();
+    assert_eq!(e.step, 3);
+    assert_eq!(e.rollback_errors.len(), 1);
+    assert_eq!(e.rollback_errors[0].0, 1);
+    assert_eq!(
+        std::fs::read_to_string(&log)?,
+        "apply 0\napply 1\napply 2\napply 3\nrollback 2\nrollback 1\nrollback 0\n"
+    );
+    let e: std::io::Error = e.into();
+    assert!(e
+        .to_string()
+        .starts_with("transaction step 3 failed: bash script failed"));
+    std::fs::remove_file(&log)?;
+    let (a0, r0) = step(0, false);
+    Transaction::new().step(a0, r0).run()?;
+    assert_eq!(std::fs::read_to_string(&log)?, "apply 0\n");
+    Ok(())
+}
+
+#[test]
+fn cwd() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let sub = dir.path().join("a dir");
+    std::fs::create_dir(&sub)?;
+    bash!(cwd = &sub, r"touch here")?;
+    assert!(sub.join("here").exists());
+    let pwd = bash_output!(cwd = dir.path(), trim = true, r"pwd -P")?;
+    assert_eq!(pwd, dir.path().canonicalize()?.to_str().unwrap());
+    let e = bash!(cwd = dir.path().join("missing"), r"true").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    Ok(())
+}
+
+#[test]
+fn env() -> Result<(), std::io::Error> {
+    let value = std::ffi::OsString::from("a b");
+    bash!(
+        env = { SH_INLINE_A: "1", SH_INLINE_B: &value },
+        r#"test "${SH_INLINE_A}" = 1
+        bash -c 'test "${SH_INLINE_B}" = "a b"'"#
+    )?;
+    let mut map = HashMap::new();
+    map.insert("SH_INLINE_C", "c");
+    bash!(
+        env = map,
+        env_keep = [PATH],
+        r#"test "$(printenv SH_INLINE_C)" = c"#
+    )?;
+    let plan = bash_plan!(env = { SH_INLINE_D: "d" }, r"true")?;
+    assert!(plan
+        .env
+        .iter()
+        .any(|(k, v)| k == "SH_INLINE_D" && v.as_deref() == Some("d".as_ref())));
+    Ok(())
+}
+
+#[test]
+fn session() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let mut a = BashSession::named("a")?;
+    let mut b = BashSession::new()?;
+    a.bind("dir", dir.path())?;
+    a.run(r#"cd "${dir}"; export SH_INLINE_SESSION=a; f() { echo "f $1"; }"#)?;
+    assert_eq!(a.cwd(), dir.path());
+    assert_eq!(a.output(r#"f "${SH_INLINE_SESSION}""#)?, "f a\n");
+    // Sessions are isolated from each other and from this process
+    assert_eq!(b.output(r#"echo "${SH_INLINE_SESSION-unset}""#)?, "unset\n");
+    assert_ne!(b.cwd(), dir.path());
+    assert!(std::env::var_os("SH_INLINE_SESSION").is_none());
+    // Failures stop the script but not the session
+    let e = a.run(r"g() { false; echo no; }; g; echo no").unwrap_err();
+    assert_eq!(e.to_string(), "bash script failed: exit status: 1");
+    let r = a.run_output(r"echo out; echo err >&2; exit_code=3; (exit ${exit_code})")?;
+    assert_eq!(
+        (r.stdout.as_slice(), r.stderr.as_slice()),
+        (&b"out\n"[..], &b"err\n"[..])
+    );
+    assert_eq!(r.status.code(), Some(3));
+    assert!(a.bind("not a name", "x").is_err());
+    for e in [
+        a.bind("f", &std::fs::File::open("/dev/null")?),
+        a.bind("h", &Heredoc("text")),
+    ] {
+        assert_eq!(e.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+    a.bind("t", &sh_inline::tempfile("data\n")?)?;
+    assert_eq!(a.output(r#"cat "${t}""#)?, "data\n");
+    let info: Vec<SessionInfo> = sh_inline::sessions()
+        .into_iter()
+        .filter(|s| s.id == a.id() || s.id == b.id())
+        .collect();
+    assert_eq!(info.len(), 2);
+    assert_eq!(info[0].name.as_deref(), Some("a"));
+    assert_eq!(info[0].cwd, dir.path());
+    assert_eq!(info[0].scripts, 7);
+    assert!(sh_inline::terminate_session(b.id())?);
+    assert_eq!(
+        b.run("true").unwrap_err().kind(),
+        std::io::ErrorKind::BrokenPipe
+    );
+    let id = b.id();
+    drop(b);
+    assert!(!sh_inline::sessions().iter().any(|s| s.id == id));
+    assert!(!sh_inline::terminate_session(id)?);
+    Ok(())
+}
+
+#[test]
+fn repl() -> Result<(), std::io::Error> {
+    let history = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let h = history.clone();
+    let policy = Policy::new().deny(r"\brm\b").unwrap();
+    let mut repl = Repl::new(BashSession::new()?)
+        .prompt("")
+        .continuation("")
+        .policy(policy)
+        .on_history(move |c| h.borrow_mut().push(c.to_string()));
+    let input = "echo 'a\nb'\n\nrm -rf /nonexistent\necho x \\\ny\nfalse\necho err >&2\nexit\necho unreachable\n";
+    let mut output = Vec::new();
+    repl.run(input.as_bytes(), &mut output)?;
+    assert_eq!(
+        String::from_utf8_lossy(&output),
+        "a\nb\nbash script denied by policy: script matches \"\\\\brm\\\\b\"\nx y\nerr\n"
+    );
+    assert_eq!(
+        *history.borrow(),
+        [
+            "echo 'a\nb'",
+            "rm -rf /nonexistent",
+            "echo x \\\ny",
+            "false",
+            "echo err >&2"
+        ]
+    );
+    assert_eq!(repl.session().output("echo still")?, "still\n");
+    Ok(())
+}
+
+#[test]
+fn timeout_kills_tree() -> Result<(), std::io::Error> {
+    use std::time::Instant;
+    for &method in &[TimeoutMethod::Coreutils, TimeoutMethod::Builtin] {
+        let start = Instant::now();
+        // The background sleep keeps standard output open unless it is killed too
+        let e = bash_output!(
+            name = "hanging",
+            timeout = Duration::from_millis(200),
+            timeout_method = method,
+            r"sleep 30 & sleep 30"
+        )
+        .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!(
+            e.to_string(),
+            r#"bash script "hanging" timed out after 200ms"#
+        );
+        match sh_inline::Error::of(&e) {
+            Some(sh_inline::Error::TimedOut { limit, script, .. }) => {
+                assert_eq!(*limit, Duration::from_millis(200));
+                assert!(script.contains("sleep 30 &"));
+            }
+            _ => panic!("expected a timeout"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(any(feature = "json", feature = "csv"))]
+#[test]
+fn decode() -> Result<(), std::io::Error> {
+    use sh_inline::decode;
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct Package {
+        name: String,
+        size: u64,
+    }
+    #[cfg(feature = "json")]
+    {
+        let r = bash_run!(
+            r#"echo '{"name": "bash", "size": 3}'
+            echo
+            echo '{"name": "coreutils", "size": 10}'"#
+        )?;
+        let rows: Vec<Package> = r.decode(decode::JsonLines::new())?;
+        assert_eq!(rows[1].name, "coreutils");
+        let r = bash_run!(r#"echo '{"name": 1}'"#)?;
+        let e = r.decode(decode::JsonLines::<Package>::new()).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+    #[cfg(feature = "csv")]
+    {
+        let r = bash_run!(r"printf 'size,name\n3,bash\n'")?;
+        let rows: Vec<Package> = r.decode(decode::Csv::new())?;
+        assert_eq!(
+            rows,
+            [Package {
+                name: "bash".to_string(),
+                size: 3
+            }]
+        );
+        let r = bash_run!(r"printf 'name\tsize\nbash\t3\ncoreutils\tmany\n'")?;
+        let e = r.decode(decode::Tsv::<Package>::new()).unwrap_err();
+        assert!(e.to_string().contains("TSV"), "{}", e);
+        let r = bash_run!(r"printf 'bash;3\n'")?;
+        let rows: Vec<(String, u64)> = r.decode(decode::Csv::new().delimiter(b';').no_headers())?;
+        assert_eq!(rows[0].1, 3);
+    }
+    Ok(())
+}
+
+#[test]
+fn retry_transient() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let count = dir.path().join("count");
+    let attempts = || std::fs::read_to_string(&count).map(|s| s.lines().count());
+    let flaky = |message: &str| {
+        bash!(
+            stderr_tail = 1024,
+            r#"echo x >> "${count}"; echo "${message}" >&2; exit 1"#,
+            count,
+            message
+        )
+    };
+    let retry = Retry::new(3).delay(Duration::from_millis(1));
+    let e = retry
+        .run(|| flaky("ssh: Temporary failure in name resolution"))
+        .unwrap_err();
+    assert!(retry.is_transient(&e));
+    assert_eq!(attempts()?, 3);
+    std::fs::remove_file(&count)?;
+    assert!(retry.run(|| flaky("No such file or directory")).is_err());
+    assert_eq!(attempts()?, 1);
+    std::fs::remove_file(&count)?;
+    let retry = retry.no_default_patterns().pattern(r"^HTTP 5\d\d")?;
+    assert!(retry.run(|| flaky("Connection reset")).is_err());
+    assert_eq!(attempts()?, 1);
+    std::fs::remove_file(&count)?;
+    assert!(retry.run(|| flaky("HTTP 503")).is_err());
+    assert_eq!(attempts()?, 3);
+    assert!(Retry::new(2).pattern("(").is_err());
+    Ok(())
+}
+
+#[test]
+fn lines() -> Result<(), std::io::Error> {
+    let count = 3;
+    let mut lines = bash_lines!(
+        name = "progress",
+        r#"for i in $(seq 1 ${count}); do echo "step ${i}"; done; exit 2"#,
+        count
+    )?;
+    assert_eq!(lines.next().unwrap()?, "step 1");
+    assert_eq!(
+        lines.by_ref().take(2).collect::<Result<Vec<_>, _>>()?.len(),
+        2
+    );
+    let e = lines.next().unwrap().unwrap_err();
+    assert_eq!(Error::of(&e).unwrap().name(), Some("progress"));
+    assert_eq!(Error::of(&e).unwrap().code(), Some(2));
+    assert!(lines.next().is_none());
+    assert_eq!(bash_lines!(r"true")?.count(), 0);
+    Ok(())
+}
+
+#[test]
+fn array_binding() -> Result<(), std::io::Error> {
+    let files = vec!["a b", "c", "*", ""];
+    let counts = [1, 2];
+    bash!(
+        r#"test ${#files[@]} = 4
+        test "${files[0]}" = "a b"
+        test "${files[2]}" = "*"
+        for f in "${files[@]}"; do n=$((${n:-0} + 1)); done
+        test ${n} = 4
+        test $((counts[0] + counts[1])) = 3"#,
+        files,
+        counts
+    )?;
+    let empty: Vec<String> = Vec::new();
+    bash!(r#"test ${#empty[@]} = 0"#, empty)?;
+    let plan = bash_plan!(prefix = "p_", r#"echo "${files[@]}""#, files)?;
+    assert!(
+        plan.script
+            .contains(r#"declare -a p_files=("a b" c "*" "")"#),
+        "{}",
+        plan.script
+    );
+    assert!(plan.script.contains(r#"echo "${p_files[@]}""#));
+    Ok(())
+}
+
+#[test]
+fn limits() -> Result<(), std::io::Error> {
+    std::env::set_var("SH_INLINE_TEST_LIMITS_SECRET", "1");
+    bash!(
+        limits = Limits::untrusted(),
+        env = { KEPT: "yes" },
+        r#"test "$(ulimit -c)" = 0
+        test "$(ulimit -t)" -le 60
+        test "$(ulimit -n)" -le 256
+        test -z "${SH_INLINE_TEST_LIMITS_SECRET:-}"
+        test "${KEPT}" = yes
+        test -n "${PATH}"
+        grep -q '^NoNewPrivs:.*1' /proc/self/status"#
+    )?;
+    bash!(
+        limits = "build".parse::<Limits>()?.rlimit(Resource::OpenFiles, 100),
+        r#"test "$(ulimit -n)" = 100
+        test "${SH_INLINE_TEST_LIMITS_SECRET}" = 1"#
+    )?;
+    let dir = tempfile::tempdir()?;
+    let big = dir.path().join("big");
+    bash!(
+        limits = Limits::new().rlimit(Resource::FileSize, 1024),
+        r#"trap '' XFSZ
+        ! head -c 2048 /dev/zero > "${big}""#,
+        big
+    )?;
+    assert!("paranoid".parse::<Limits>().is_err());
+    Ok(())
+}
+
+#[test]
+fn assoc_binding() -> Result<(), std::io::Error> {
+    use std::collections::{BTreeMap, HashMap};
+    let mut env = HashMap::new();
+    env.insert("key with spaces", "a 'quoted' $value");
+    env.insert("]", "bracket");
+    env.insert("n", "");
+    let mut sizes = BTreeMap::new();
+    sizes.insert("small".to_string(), 1);
+    sizes.insert("large".to_string(), 100);
+    bash!(
+        r#"test ${#env[@]} = 3
+        test "${env["key with spaces"]}" = "a 'quoted' \$value"
+        test "${env["]"]}" = bracket
+        test -z "${env[n]}"
+        test $((sizes[large] + sizes[small])) = 101"#,
+        env,
+        sizes
+    )?;
+    let mut session = BashSession::new()?;
+    session.bind("env", &env)?;
+    assert_eq!(session.output(r#"echo "${env["]"]}""#)?, "bracket\n");
+    let plan = bash_plan!(r"true", env)?;
+    assert!(
+        plan.script.contains(r"declare -A env=([']']='bracket' "),
+        "{}",
+        plan.script
+    );
+    Ok(())
+}
+
+#[test]
+fn requires() -> Result<(), std::io::Error> {
+    bash!(requires = ["bash >= 3.2", "sh", "bash<1000"], r"true")?;
+    let e = bash!(
+        name = "deploy",
+        requires = ["no-such-command-exists", "bash >= 999", "bash"],
+        r"touch /nonexistent/should-not-run"
+    )
+    .unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    let message = e.to_string();
+    assert!(message.starts_with("bash script \"deploy\" requirements not met"));
+    assert!(message.contains("no-such-command-exists: command not found"));
+    assert!(
+        message.contains("does not satisfy bash >= 999"),
+        "{}",
+        message
+    );
+    let e = bash!(requires = ["bash = 1.0"], r"true").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::Unsupported);
+    let e = bash!(requires = ["bash >= x"], r"true").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    Ok(())
+}
+
+#[test]
+fn resolve_tool() -> Result<(), std::io::Error> {
+    use std::os::unix::fs::PermissionsExt;
+    let bash = sh_inline::resolve_tool("bash")?;
+    assert!(bash.is_absolute());
+    assert_eq!(sh_inline::resolve_tool("bash")?, bash);
+    let e = sh_inline::resolve_tool("sh-inline-test-tool").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    let dir = tempfile::tempdir()?;
+    let tool = dir.path().join("sh-inline-test-tool");
+    std::fs::write(&tool, "#!/bin/sh\necho 'sh-inline-test-tool 2.1'\n")?;
+    std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755))?;
+    let path = std::env::join_paths(
+        std::iter::once(dir.path().to_path_buf())
+            .chain(std::env::split_paths(&std::env::var_os("PATH").unwrap())),
+    )
+    .unwrap();
+    let script = Script::new(r#"test "$(command -v sh-inline-test-tool)" = "${tool}""#)
+        .env_var("PATH", &path)
+        .bind("tool", &tool)
+        .requires(["sh-inline-test-tool >= 2"]);
+    assert_eq!(script.resolve_tool("sh-inline-test-tool")?, tool);
+    script.run()?;
+    Ok(())
+}
+
+#[test]
+fn to_shell_arg() -> Result<(), std::io::Error> {
+    struct Digest([u8; 4]);
+    impl ToShellArg for Digest {
+        fn to_shell_arg(&self) -> ShellArg {
+            ShellArg::string(
+                self.0
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>(),
+            )
+        }
+    }
+    struct Layers(Vec<Digest>);
+    impl ToShellArg for Layers {
+        fn to_shell_arg(&self) -> ShellArg {
+            ShellArg::array(self.0.iter().map(|d| format!("sha256:{}", d.0[0])))
+        }
+    }
+    let digest = Digest([0xde, 0xad, 0xbe, 0xef]);
+    let layers = Layers(vec![Digest([1, 0, 0, 0]), Digest([2, 0, 0, 0])]);
+    bash!(
+        r#"test "${digest}" = deadbeef && test "${layers[*]}" = "sha256:1 sha256:2""#,
+        digest,
+        layers
+    )?;
+    let digest = &digest;
+    Script::new(r#"test "${d}" = deadbeef"#)
+        .bind("d", &digest)
+        .run()?;
+    let mut session = BashSession::new()?;
+    session.bind("d", digest)?;
+    assert_eq!(session.run_output("echo $d")?.stdout, b"deadbeef\n");
+    Ok(())
+}
+
+#[test]
+fn tool_dirs() -> Result<(), std::io::Error> {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = tempfile::tempdir()?;
+    let tool = dir.path().join("sh-inline-test-tool");
+    std::fs::write(&tool, "#!/bin/sh\necho 'sh-inline-test-tool 1.0'\n")?;
+    std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755))?;
+    let script = || {
+        Script::new(
+            r#"test "${PATH}" = "${dir}"
+            sh-inline-test-tool
+            if command -v cat; then exit 1; fi"#,
+        )
+        .bind("dir", dir.path())
+        .env_var("PATH", "/usr/bin:/bin")
+        .tool_dirs([dir.path()])
+    };
+    assert_eq!(script().resolve_tool("sh-inline-test-tool")?, tool);
+    assert_eq!(
+        script().resolve_tool("cat").unwrap_err().kind(),
+        std::io::ErrorKind::NotFound
+    );
+    assert_eq!(script().output()?, "sh-inline-test-tool 1.0\n");
+    // Wrappers are found where this process finds them
+    let timed = script()
+        .timeout(Duration::from_secs(5))
+        .timeout_method(TimeoutMethod::Coreutils);
+    assert_eq!(timed.output()?, "sh-inline-test-tool 1.0\n");
+    let e = Script::new("sh-inline-test-tool")
+        .tool_dirs(["tools"])
+        .run()
+        .unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    Ok(())
+}
+
+#[test]
+fn posix_sh() -> Result<(), std::io::Error> {
+    let name = "a \"b\" $c 'd'";
+    let config = Heredoc("x = 1\n\n");
+    sh!(
+        r#"test "${name}" = "a \"b\" \$c 'd'"
+        test "${config}" = "x = 1
+
+"
+        false | true"#,
+        name,
+        config
+    )?;
+    assert!(sh!(r"false; true").is_err());
+    let out = Script::new(r#"printf '%s\n' ${words}"#)
+        .posix(true)
+        .safe_ifs(true)
+        .bind("words", "a b")
+        .output()?;
+    assert_eq!(out, "a b\n");
+    let status = sh_command!(r"exit 3")?.status()?;
+    assert_eq!(status.code(), Some(3));
+    let plan = Script::new("true").posix(true).plan()?;
+    assert_eq!(plan.program, OsStr::new("/bin/sh"));
+    assert!(plan.script.starts_with("set -eu\n"), "{}", plan.script);
+    let files = vec!["a", "b"];
+    let magic = Bytes([0x7f, b'E']);
+    for e in [
+        sh!(r"true", files).unwrap_err(),
+        sh!(r"true", magic).unwrap_err(),
+        sh!(shopt = ["nullglob"], r"true").unwrap_err(),
+    ] {
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    }
+    Ok(())
+}
+
+#[test]
+fn error_excerpt() -> Result<(), std::io::Error> {
+    let dir = Path::new("/nonexistent");
+    let e = bash!(
+        r#"echo start >/dev/null
+        check() {
+            test -d "${dir}"
+        }
+        check
+        echo unreachable"#,
+        dir
+    )
+    .unwrap_err();
+    let failure = Error::of(&e).unwrap();
+    let script = failure.script().unwrap();
+    let line = script.lines().position(|l| l.contains("test -d")).unwrap() + 1;
+    assert_eq!(failure.line(), Some(line));
+    let message = e.to_string();
+    let marked = format!("> {} |             test -d \"${{dir}}\"\n", line);
+    let caret = format!(" |             {}\n", "^".repeat(16));
+    assert!(message.contains(&format!("at line {}:\n", line)));
+    assert!(message.contains(&format!("{}  ", marked)), "{}", message);
+    assert!(message.contains(&caret), "{}", message);
+    let e = bash!(r"false || true; exit 2").unwrap_err();
+    assert_eq!(Error::of(&e).unwrap().line(), None);
+    assert!(!e.to_string().contains("at line"));
+    // Nothing reads where a returned command fails, so nothing records it
+    let cmd = bash_command!(r"false")?;
+    assert!(cmd.get_envs().all(|(k, _)| k != "SH_INLINE_ERR_FILE"));
+    assert!(!bash_script!(r"false")?.contains("ERR"));
+    Ok(())
+}
+
+#[test]
+fn artifacts() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let artifacts = dir.path().join("artifacts");
+    bash!(artifacts = &artifacts, r"echo fine")?;
+    assert!(!artifacts.exists());
+    let script = || {
+        Script::new("echo out; echo err >&2\nfalse\necho unreachable")
+            .name("build step")
+            .env_var("SH_INLINE_TEST_ARTIFACT", "a b")
+            .artifacts(&artifacts)
+    };
+    let e = script().run().unwrap_err();
+    let failure = Error::of(&e).unwrap();
+    let bundle = artifacts.join("build-step-1");
+    let read = |name: &str| std::fs::read_to_string(bundle.join(name));
+    assert_eq!(read("script.sh")?, failure.script().unwrap());
+    assert!(read("env")?.contains("\nSH_INLINE_TEST_ARTIFACT=\"a b\"\n"));
+    assert_eq!(read("stdout")?, "out\n");
+    assert_eq!(read("stderr")?, "err\n");
+    let status = read("status")?;
+    let expected = format!(
+        "name=\"build step\"\nexit_code=1\nline={}\nstarted=",
+        failure.line().unwrap()
+    );
+    assert!(status.starts_with(&expected), "{}", status);
+    assert!(status.contains("\nduration=0."), "{}", status);
+    assert_eq!(script().output().unwrap_err().kind(), e.kind());
+    assert_eq!(
+        std::fs::read_to_string(artifacts.join("build-step-2/stdout"))?,
+        "out\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn strict_mode() -> Result<(), std::io::Error> {
+    let relaxed = StrictMode::new()
+        .errexit(false)
+        .nounset(false)
+        .pipefail(false);
+    let out = bash_output!(
+        strict = relaxed,
+        r#"false; false | true; echo "continued${UNSET_VARIABLE}""#
+    )?;
+    assert_eq!(out, "continued\n");
+    assert!(bash!(r"false | true").is_err());
+    assert!(bash!(strict = StrictMode::new().pipefail(false), r"false | true").is_ok());
+    let word = "hidden";
+    let r = bash_run!(
+        strict = StrictMode::new().xtrace(true),
+        r"echo ${word}",
+        word
+    )?;
+    assert_eq!(r.stdout, b"hidden\n");
+    let trace = String::from_utf8_lossy(&r.stderr);
+    assert!(trace.contains("+ echo hidden"), "{}", trace);
+    assert!(!trace.contains("word="), "{}", trace);
+    let plan = bash_plan!(strict = StrictMode::none(), r"true")?;
+    assert_eq!(plan.script, "true");
+    let plan = Script::new("true")
+        .posix(true)
+        .strict(StrictMode::new().nounset(false))
+        .plan()?;
+    assert!(plan.script.starts_with("set -e\n"), "{}", plan.script);
+    Ok(())
+}
+
+#[test]
+fn temp_files_sweep() -> Result<(), std::io::Error> {
+    let tmp = std::env::temp_dir();
+    let mut exited = std::process::Command::new("true").spawn()?;
+    exited.wait()?;
+    let old = std::time::SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+    let stale = tmp.join(format!("sh-inline-tmp-{}-stale", exited.id()));
+    let fresh = tmp.join(format!("sh-inline-tmp-{}-fresh", exited.id()));
+    let running = tmp.join("sh-inline-tmp-1-running");
+    for path in [&stale, &fresh, &running] {
+        let file = std::fs::File::create(path)?;
+        if path != &fresh {
+            file.set_modified(old)?;
+        }
+    }
+    let mut child = bash_spawn!(private_tmp = true, r"exec sleep 30")?;
+    let private = format!("sh-inline-tmp-{}-", std::process::id());
+    let private = temp_files()
+        .into_iter()
+        .find(|p| p.to_str().unwrap().contains(&private))
+        .expect("private TMPDIR registered");
+    sh_inline::sweep_temp_files(Duration::from_secs(60 * 60))?;
+    assert!(!stale.exists());
+    assert!(fresh.exists());
+    assert!(running.exists());
+    sh_inline::sweep_temp_files(Duration::ZERO)?;
+    assert!(private.exists());
+    assert!(!fresh.exists());
+    child.kill()?;
+    std::fs::remove_file(&running)?;
+    Ok(())
+}
+
+#[test]
+fn trace() -> Result<(), std::io::Error> {
+    let token = "hunter2";
+    let user = "admin";
+    let script = || {
+        Script::new(r#"test -n "${token}""#)
+            .name("login")
+            .bind("token", token)
+            .bind("user", user)
+            .redact(["token"])
+    };
+    if std::env::var_os("SH_INLINE_TRACE").is_some() {
+        return script().run();
+    }
+    // Trace a run in a child process, so that its standard error can be read
+    let output = std::process::Command::new(std::env::current_exe()?)
+        .args(["--exact", "trace", "--nocapture"])
+        .env("SH_INLINE_TRACE", "1")
+        .output()?;
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("sh-inline: bash script \"login\" rendered as:\n"));
+    assert!(stderr.contains("\ntoken=<redacted>\nuser=admin\n"));
+    assert!(stderr.contains("\ntest -n \"${token}\"\n"));
+    assert!(!stderr.contains(token), "{}", stderr);
+    let e = script().redact(["tokn"]).trace(false).run().unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    Ok(())
+}
+
+#[test]
+fn fingerprint() -> Result<(), std::io::Error> {
+    let fingerprint = Fingerprint::current();
+    assert_eq!(Fingerprint::current(), fingerprint);
+    assert!(fingerprint.bash_version.is_some());
+    assert_eq!(fingerprint.umask.is_some(), cfg!(target_os = "linux"));
+    let mut other = fingerprint.clone();
+    other.vars.push(("LC_ALL".into(), "C".into()));
+    assert_ne!(other.id(), fingerprint.id());
+    if std::env::var_os("SH_INLINE_TEST_FINGERPRINT").is_none() {
+        // Enable fingerprints in a child process, as the configuration is global
+        let output = std::process::Command::new(std::env::current_exe()?)
+            .args(["--exact", "fingerprint"])
+            .env("SH_INLINE_TEST_FINGERPRINT", "1")
+            .output()?;
+        assert!(output.status.success(), "{:?}", output);
+        return Ok(());
+    }
+    let dir = tempfile::tempdir()?;
+    Config::current().fingerprint(true).install();
+    let e = bash!(artifacts = dir.path(), r"exit 3").unwrap_err();
+    assert_eq!(Error::of(&e).unwrap().fingerprint(), Some(&fingerprint));
+    let suffix = format!("\nenvironment fingerprint {}", fingerprint.id());
+    assert!(e.to_string().ends_with(&suffix), "{}", e);
+    let environment = std::fs::read_to_string(dir.path().join("script-1/environment"))?;
+    assert_eq!(environment, format!("{}\n", fingerprint));
+    Ok(())
+}
+
+#[test]
+fn quoting_profiles() -> Result<(), std::io::Error> {
+    let values = [
+        "",
+        "it's",
+        "a\"b\\c",
+        "$(id) `id` ${x}",
+        "tab\tnew\nline",
+        "*?[a]~",
+    ];
+    for (shell, profile) in [
+        ("bash", &quoting::Bash as &dyn QuotingProfile),
+        ("dash", &quoting::Posix),
+    ] {
+        for value in values.iter() {
+            let script = format!(
+                "{}\nprintf %s \"$v\"",
+                profile.assign("v", &profile.quote(value))
+            );
+            let out = std::process::Command::new(shell)
+                .args(["-c", &script])
+                .output()?;
+            assert_eq!(String::from_utf8_lossy(&out.stdout), *value, "{}", script);
+        }
+    }
+    assert_eq!(quoting::Posix.quote_bytes(b"\xff"), None);
+    assert_eq!(quoting::Bash.quote_bytes(b"\xff").unwrap(), r"$'\xff'");
+    let list = ["a b".to_string(), "it's".to_string()];
+    assert_eq!(
+        Fish.assign("v", &Fish.quote_list(&list).unwrap()),
+        r"set v 'a b' 'it\'s'"
+    );
+    assert_eq!(
+        PowerShell.quote("it\u{2019}s $x"),
+        "'it\u{2019}\u{2019}s $x'"
+    );
+    assert_eq!(PowerShell.quote_list(&list).unwrap(), "@('a b', 'it''s')");
+    let map = [("k".to_string(), "v".to_string())];
+    assert_eq!(PowerShell.quote_map(&map).unwrap(), "[ordered]@{'k' = 'v'}");
+    assert_eq!(Fish.quote_map(&map), None);
+    Ok(())
+}
+
+#[test]
+fn bash_script() -> Result<(), std::io::Error> {
+    let words = ["a b", "$(false)"];
+    let script = bash_script!(r#"printf '<%s>' "${words[@]}""#, words)?;
+    assert!(script.contains("\ndeclare -a words=(\"a b\" \"\\$(false)\")\n"));
+    let out = std::process::Command::new("bash")
+        .args(["-c", &script])
+        .output()?;
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "<a b><$(false)>");
+    assert_eq!(
+        bash_script!(posix = true, r"true")?,
+        Script::new("true").posix(true).plan()?.script
+    );
+    let script = bash_script!(
+        scratch = Scratch::Shm,
+        requires = ["sh-inline-missing-tool"],
+        r#"touch "${scratch}/file""#
+    )?;
+    let line = script.lines().find(|l| l.starts_with("scratch=")).unwrap();
+    assert!(!Path::new(line.trim_start_matches("scratch=").trim_matches('"')).exists());
+    Ok(())
+}
+
+#[test]
+fn bind_env() -> Result<(), std::io::Error> {
+    let secret = "s3cret value";
+    let files = vec!["a b", "c"];
+    let mut config = std::collections::BTreeMap::new();
+    config.insert("k ey", "v'al");
+    let notes = Heredoc("line\n\n");
+    let script = || {
+        Script::new(
+            r#"printf '<%s>' "${secret}" "${files[@]}" "${config["k ey"]}" "${notes}"
+env | grep -c ^SH_INLINE_BIND_ || true"#,
+        )
+        .bind("secret", secret)
+        .bind("files", &files)
+        .bind("config", &config)
+        .bind("notes", &notes)
+        .bind_env(true)
+    };
+    assert_eq!(
+        script().output()?,
+        "<s3cret value><a b><c><v'al><line\n\n>0\n"
+    );
+    let text = script().render()?;
+    assert!(!text.contains("s3cret"), "{}", text);
+    assert!(!text.contains("v'al"), "{}", text);
+    let e = bash!(bind_env = true, r"exit 3", secret).unwrap_err();
+    assert!(!e.to_string().contains("s3cret"), "{}", e);
+    Ok(())
+}
+
+#[test]
+fn secret_bindings() -> Result<(), std::io::Error> {
+    let token = sh_inline::secret("t0ken\nvalue");
+    assert_eq!(format!("{:?}", token), "Secret(<redacted>)");
+    let script = || {
+        Script::new(r#"printf '<%s>' "${token}"; env | grep -c ^SH_INLINE_SECRET_ || true"#)
+            .bind("token", &token)
+    };
+    assert_eq!(script().output()?, "<t0ken\nvalue>0\n");
+    let plan = script().plan()?;
+    assert!(!plan.to_string().contains("t0ken"), "{}", plan);
+    assert!(plan
+        .env
+        .iter()
+        .any(|(_, v)| v.as_deref() == Some("<redacted>".as_ref())));
+    assert!(!script().render()?.contains("t0ken"));
+    let e = script().powershell(true).plan().unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    Ok(())
+}
+
+#[test]
+fn awk_sed_programs() -> Result<(), std::io::Error> {
+    let sep = "'\\n\"";
+    let prog = awk_prog!(r#"$2 > min { print $1 sep $2 }"#, sep, min = 9);
+    let out = bash_output!(r#"printf 'a 10\nb 9\n' | awk "${prog[@]}""#, prog)?;
+    assert_eq!(out, "a'\\n\"10\n");
+    let (from, to) = ("a.b/[c]", r"x\1&|/");
+    let expr = sed_expr!("/^#/d; s|{}|{}|g", from, to)?;
+    let out = bash_output!(
+        r#"printf '# a.b/[c]\naxb/[c] a.b/[c]\n' | sed -e "${expr}""#,
+        expr
+    )?;
+    assert_eq!(out, "axb/[c] x\\1&|/\n");
+    assert_eq!(sed_expr!("s/{}/{{}}/", "a")?.as_str(), "s/a/{}/");
+    assert!(sed_expr!("{}d", "1").is_err());
+    assert!(sed_expr!("s/{}//").is_err());
+    Ok(())
+}
+
+#[test]
+fn powershell() -> Result<(), std::io::Error> {
+    let name = "it's";
+    let list = vec!["a", "b"];
+    let script = || {
+        Script::new("Write-Output $name")
+            .bind("name", name)
+            .bind("list", &list)
+            .powershell(true)
+    };
+    let plan = script().shell("/usr/bin/true").plan()?;
+    assert_eq!(
+        &plan.args[..4],
+        ["-NoLogo", "-NoProfile", "-NonInteractive", "-File"]
+    );
+    assert!(plan.args[4].to_str().unwrap().ends_with(".ps1"));
+    assert_eq!(
+        plan.script,
+        "$ErrorActionPreference = 'Stop'\n\
+         $PSNativeCommandUseErrorActionPreference = $true\n\
+         Set-StrictMode -Version Latest\n\
+         $name = 'it''s'\n\
+         $list = @('a', 'b')\n\
+         Write-Output $name"
+    );
+    let e = script().on_exit("true").plan().unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    if sh_inline::resolve_tool("pwsh").is_ok() {
+        assert_eq!(script().output()?, "it's\n");
+        assert!(pwsh!(r"Get-Item /nonexistent; exit 0").is_err());
+    }
+    Ok(())
+}
+
+#[test]
+fn return_fd() -> Result<(), std::io::Error> {
+    let n = 100000;
+    let ret = bash_with_return_fd!(
+        r#"echo noise; echo more noise >&2; seq "${n}" >"${ret}"; echo done >>"${ret}""#,
+        n
+    )?;
+    let ret = String::from_utf8(ret).unwrap();
+    assert!(ret.starts_with("1\n2\n"));
+    assert!(ret.ends_with("\n100000\ndone\n"));
+    assert!(bash_with_return_fd!(r#"echo partial >"${ret}"; exit 1"#).is_err());
+    assert_eq!(bash_with_return_fd!(r"true")?, b"");
+    Ok(())
+}